@@ -14,7 +14,6 @@
 // You should have received a copy of the GNU General Public License
 // along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -23,15 +22,23 @@ use errors::Error;
 use map_consensus::poa;
 use map_core;
 use map_core::trie::NULL_ROOT;
-use map_core::block::{Block, Header};
+use map_core::block::{Block, BlockProof, Header, MerkleProof, BLOCK_GAS_LIMIT, MAX_BLOCK_TXS};
+use map_core::gas::GasSchedule;
+use map_core::reward::IssuanceSchedule;
 use map_core::genesis;
+use map_core::receipt::Receipt;
+use map_core::runtime::Interpreter;
+use map_core::staking::Staking;
+use map_core::transaction::Transaction;
 #[allow(unused_imports)]
-use map_core::state::{ArchiveDB, StateDB};
+use map_core::state::{ArchiveDB, StateDB, StateJournal, PruningMode, CacheStats};
 use map_core::types::Hash;
-use map_store;
-use map_store::mapdb::MapDB;
+use map_store::{KVDB, MemoryKV};
+use map_executor::Executor;
+use map_events::{ChainReorg, EventHandler};
 
-use crate::store::ChainDB;
+use crate::store::{ChainDB, TxLocation, DBMeta};
+use crate::schema::{self, SCHEMA_VERSION};
 
 use super::BlockChainErrorKind;
 
@@ -40,43 +47,91 @@ pub struct BlockChain {
     state_backend: ArchiveDB,
     validator: Validator,
     genesis: Block,
-    consensus: poa::POA
+    consensus: poa::POA,
+    events: Option<EventHandler>,
+    schedule: GasSchedule,
+    issuance: IssuanceSchedule,
 }
 
 impl BlockChain {
-    pub fn new(datadir: PathBuf, key: String) -> Self {
-        info!("using datadir {}", datadir.display());
-        let db_cfg = map_store::Config::new(datadir.clone());
-        let backend;
-        {
-            let mut dir = datadir.clone();
-            dir.push("data");
-            let db = MapDB::open(map_store::Config::new(dir.clone())).unwrap();
-            let kv: Arc<RwLock<dyn map_store::KVDB>> = Arc::new(RwLock::new(db));
-            backend = ArchiveDB::new(Arc::clone(&kv));
-        }
+    pub fn new(kv: Arc<RwLock<dyn KVDB>>, key: String) -> Self {
+        BlockChain::with_pruning(kv, key, PruningMode::Archive)
+    }
+
+    /// Chain and state share one db so that a block is written in single batch
+    pub fn with_pruning(kv: Arc<RwLock<dyn KVDB>>, key: String, pruning: PruningMode) -> Self {
+        info!("using pruning {:?}", pruning);
+        let db = ChainDB::new(Arc::clone(&kv));
+        let backend = ArchiveDB::with_pruning(kv, pruning);
+        let existing = db.head_hash().is_some();
+        backend.check_pruning(existing).expect("pruning mode mismatch");
 
         BlockChain {
-            db: ChainDB::new(db_cfg).unwrap(),
+            db: db,
             genesis: genesis::to_genesis(),
             state_backend: backend,
             validator: Validator{},
             consensus: poa::POA::new_from_string(key),
+            events: None,
+            schedule: GasSchedule::default(),
+            issuance: IssuanceSchedule::default(),
         }
     }
 
+    pub fn gas_schedule(&self) -> &GasSchedule {
+        &self.schedule
+    }
+
+    pub fn issuance_schedule(&self) -> &IssuanceSchedule {
+        &self.issuance
+    }
+
+    /// Number of trie nodes and of values kept in state cache
+    pub fn set_state_cache_size(&mut self, size: usize) {
+        self.state_backend.set_cache_size(size);
+    }
+
+    pub fn state_cache_stats(&self) -> CacheStats {
+        self.state_backend.cache_stats()
+    }
+
+    pub fn set_event_handler(&mut self, handler: EventHandler) {
+        self.events = Some(handler);
+    }
+
     pub fn setup_genesis(&mut self) -> Hash {
+        self.db.begin_batch();
         let state_db = Rc::new(RefCell::new(StateDB::from_existing(&self.state_backend, NULL_ROOT)));
         let root = genesis::setup_allocation(state_db.clone());
         self.genesis.set_state_root(root);
+        let journal = state_db.borrow_mut().take_journal();
+        self.state_backend.apply_journal(0, &self.genesis.hash(), &root, journal);
 
         self.db.write_block(&self.genesis).expect("can not write block");
+        self.db.write_header_hash(0, &self.genesis.hash()).expect("can not write header hash");
         self.db.write_head_hash(self.genesis.hash()).expect("can not wirte head");
+        self.db.write_meta(&DBMeta {
+            version: SCHEMA_VERSION,
+            genesis: self.genesis.hash(),
+        }).expect("can not write meta");
+        self.db.commit_batch().expect("can not commit genesis");
         info!("setup genesis hash={}", self.genesis.hash());
         self.genesis.hash()
     }
 
-    pub fn load(&mut self) {
+    /// Genesis block of this chain, computed apart from the database
+    fn expected_genesis(&self) -> Block {
+        let kv: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let backend = ArchiveDB::new(kv);
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&backend, NULL_ROOT)));
+        let mut block = genesis::to_genesis();
+        block.set_state_root(genesis::setup_allocation(state_db));
+        block
+    }
+
+    /// Open chain of database, which must be of current schema version and genesis
+    pub fn load(&mut self) -> Result<(), Error> {
+        schema::check(&self.db, &self.expected_genesis().hash())?;
         let block_zero = self.get_block_by_number(0);
         if block_zero.is_none() {
             self.setup_genesis();
@@ -86,6 +141,108 @@ impl BlockChain {
             info!("load genesis hash={}", self.genesis.hash());
             info!("load block height={} hash={}", current.height(), current.hash());
         }
+        self.load_schedules();
+        Ok(())
+    }
+
+    // Chain parameters recorded at genesis, databases older than them keep defaults
+    fn load_schedules(&mut self) {
+        let state = self.state_at(self.current_block().state_root());
+        match GasSchedule::from_state(state) {
+            Some(schedule) => self.schedule = schedule,
+            None => warn!("no gas schedule in state, using default"),
+        }
+        let state = self.state_at(self.current_block().state_root());
+        match IssuanceSchedule::from_state(state) {
+            Some(issuance) => self.issuance = issuance,
+            None => warn!("no issuance schedule in state, using default"),
+        }
+    }
+
+    /// Start chain from snapshot state of block, headers are the canonical ancestors from genesis.
+    /// Only a chain holding nothing but genesis can be bootstrapped.
+    pub fn import_snapshot<I>(&mut self, headers: &[Header], block: &Block, entries: I) -> Result<(), Error>
+        where I: IntoIterator<Item = Result<(Hash, Vec<u8>), Error>>
+    {
+        if self.current_block().height() != 0 {
+            return Err(BlockChainErrorKind::InvalidSnapshot.reason("chain is not empty").into());
+        }
+        if headers.len() as u64 != block.height() {
+            return Err(BlockChainErrorKind::InvalidSnapshot.reason("missing headers").into());
+        }
+        let mut parent = self.genesis_hash();
+        for (i, header) in headers.iter().enumerate() {
+            if header.height != i as u64 || (i > 0 && header.parent_hash != parent) {
+                return Err(BlockChainErrorKind::UnknownAncestor.into());
+            }
+            if i == 0 && header.hash() != self.genesis_hash() {
+                return Err(BlockChainErrorKind::MismatchHash.into());
+            }
+            parent = header.hash();
+        }
+        if block.height() == 0 && block.hash() != self.genesis_hash() {
+            return Err(BlockChainErrorKind::MismatchHash.into());
+        }
+        if block.height() > 0 && block.header.parent_hash != parent {
+            return Err(BlockChainErrorKind::UnknownAncestor.into());
+        }
+        if block.height() > 0 {
+            self.validator.validate_block(self, block)?;
+        }
+
+        self.db.begin_batch();
+        match self.write_snapshot(headers, block, entries) {
+            Ok(_) => {
+                self.db.commit_batch().expect("can not commit snapshot");
+                info!("import snapshot height={} hash={}", block.height(), block.hash());
+                Ok(())
+            }
+            Err(e) => {
+                self.db.discard_batch();
+                Err(e)
+            }
+        }
+    }
+
+    fn write_snapshot<I>(&mut self, headers: &[Header], block: &Block, entries: I) -> Result<(), Error>
+        where I: IntoIterator<Item = Result<(Hash, Vec<u8>), Error>>
+    {
+        let mut state = StateDB::new(&self.state_backend);
+        for entry in entries {
+            let (key, value) = entry?;
+            state.set_storage(key, &value);
+        }
+        state.commit();
+        if state.root() != block.state_root() {
+            error!("snapshot state root mismatch, expect={}, got={}", block.state_root(), state.root());
+            return Err(BlockChainErrorKind::MismatchStateRoot.into());
+        }
+
+        // Ancestor headers are bound to the block by parent hash, so the block seal covers them.
+        // Parent state is not in snapshot, proposer is checked against staking of the block state.
+        let state = Rc::new(RefCell::new(state));
+        if block.height() > 0 {
+            let staking = Staking::from_state(Interpreter::new(state.clone()));
+            let authorized = |pk: &[u8]| {
+                self.consensus.is_poa_sign(pk) || Self::is_active_validator(&staking, pk, block.height())
+            };
+            if let Err(e) = self.consensus.verify_with(block, authorized) {
+                error!("snapshot consensus err height={}, {:?}", block.height(), e);
+                return Err(BlockChainErrorKind::InvalidAuthority.into());
+            }
+        }
+        let journal = state.borrow_mut().take_journal();
+        self.state_backend.apply_journal(block.height(), &block.hash(), &block.state_root(), journal);
+
+        // Ancestors are kept as headers only, their bodies are not part of snapshot
+        for header in headers.iter().skip(1) {
+            self.db.write_header(header).expect("can not write header");
+            self.db.write_header_hash(header.height, &header.hash()).expect("can not write header hash");
+        }
+        self.db.write_block(block).expect("can not write block");
+        self.db.write_header_hash(block.height(), &block.hash()).expect("can not write header hash");
+        self.db.write_head_hash(block.hash()).expect("can not wirte head");
+        Ok(())
     }
 
     pub fn statedb(&self) -> &ArchiveDB {
@@ -96,6 +253,13 @@ impl BlockChain {
         Rc::new(RefCell::new(StateDB::from_existing(&self.state_backend, root)))
     }
 
+    /// Runtime on the state of certain root, metered by chain gas schedule
+    pub fn interpreter_at(&self, root: Hash) -> Interpreter {
+        let mut runner = Interpreter::with_schedule(self.state_at(root), self.schedule.clone());
+        runner.set_issuance(self.issuance.clone());
+        runner
+    }
+
     pub fn genesis_hash(&self) -> Hash {
         self.genesis.hash()
     }
@@ -106,7 +270,7 @@ impl BlockChain {
 
     #[allow(unused_variables)]
     pub fn exits_block(&self, h: Hash, num: u64) -> bool {
-        self.db.get_block_by_number(num).is_some()
+        self.db.get_block(&h).is_some()
     }
 
     pub fn check_previous(&self, header: &Header) -> bool {
@@ -125,11 +289,231 @@ impl BlockChain {
         self.db.get_header_by_number(num)
     }
 
+    pub fn get_transaction(&self, hash: &Hash) -> Option<(Transaction, TxLocation)> {
+        let location = match self.db.get_tx_location(hash) {
+            Some(l) => l,
+            None => return None,
+        };
+        let block = match self.db.get_block(&location.block_hash) {
+            Some(b) => b,
+            None => return None,
+        };
+
+        block.txs.get(location.index as usize).map(|tx| (tx.clone(), location))
+    }
+
+    /// Merkle inclusion proof of transaction against tx_root of its block
+    pub fn get_transaction_proof(&self, hash: &Hash) -> Option<(Transaction, TxLocation, Header, MerkleProof)> {
+        let location = match self.db.get_tx_location(hash) {
+            Some(l) => l,
+            None => return None,
+        };
+        let block = match self.db.get_block(&location.block_hash) {
+            Some(b) => b,
+            None => return None,
+        };
+        let tx = match block.txs.get(location.index as usize) {
+            Some(tx) => tx.clone(),
+            None => return None,
+        };
+
+        block.tx_proof(location.index as usize).map(|proof| (tx, location, block.header, proof))
+    }
+
+    pub fn get_receipt(&self, hash: &Hash) -> Option<(Receipt, TxLocation)> {
+        let location = match self.db.get_tx_location(hash) {
+            Some(l) => l,
+            None => return None,
+        };
+        let receipts = match self.db.get_receipts(&location.block_hash) {
+            Some(r) => r,
+            None => return None,
+        };
+
+        receipts.get(location.index as usize).map(|r| (r.clone(), location))
+    }
+
+    pub fn get_receipts(&self, hash: &Hash) -> Option<Vec<Receipt>> {
+        self.db.get_receipts(hash)
+    }
+
+    // Apply block transactions on the parent state
+    fn execute_block(&self, block: &Block) -> Result<(Hash, Vec<Receipt>, StateJournal), Error> {
+        let parent = match self.get_block(block.header.parent_hash) {
+            Some(b) => b,
+            None => return Err(BlockChainErrorKind::UnknownAncestor.into()),
+        };
+        let mut runner = self.interpreter_at(parent.state_root());
+
+        let (root, receipts) = Executor::exc_txs_in_block(block, &mut runner, &poa::POA::get_default_miner())?;
+        let journal = runner.statedb().borrow_mut().take_journal();
+        Ok((root, receipts, journal))
+    }
+
+    /// State of block is kept by pruning mode
+    pub fn has_state(&self, header: &Header) -> bool {
+        !self.is_pruned(header)
+    }
+
+    // State of parent is kept only within pruning history. States of side blocks are
+    // released once their height is pruned, so a side branch is kept only while its
+    // fork point with the canonical chain is within history.
+    fn is_pruned(&self, parent: &Header) -> bool {
+        match self.state_backend.pruning() {
+            PruningMode::Archive => false,
+            PruningMode::Recent(history) => match self.fork_height(parent) {
+                Some(height) => height + history < self.current_block().height(),
+                None => true,
+            },
+        }
+    }
+
+    // Height of the nearest canonical ancestor of header, its own height if canonical
+    fn fork_height(&self, header: &Header) -> Option<u64> {
+        let mut header = *header;
+        loop {
+            if self.db.get_header_hash(header.height) == Some(header.hash()) {
+                return Some(header.height);
+            }
+            if header.height == 0 {
+                return None;
+            }
+            header = self.db.get_header(&header.parent_hash)?;
+        }
+    }
+
+    // Release states of blocks leaving pruning history
+    fn prune_state(&mut self) {
+        let history = match self.state_backend.pruning() {
+            PruningMode::Archive => return,
+            PruningMode::Recent(history) => history,
+        };
+        let head = self.current_block().height();
+        if head <= history {
+            return;
+        }
+        let start = self.state_backend.pruned_height().map_or(0, |h| h + 1);
+        for height in start..=(head - history) {
+            let canonical = self.db.get_header_by_number(height).map(|h| h.hash());
+            self.state_backend.prune(height, canonical);
+        }
+    }
+
+    // Total weight of chain ending with the block, counting stake of each proposer
+    fn block_weight(&self, block: &Block, parent: &Block) -> u128 {
+        let parent_weight = self.db.get_weight(&parent.hash()).unwrap_or(0);
+        let proposer = match block.proofs.first() {
+            Some(p) => p.to_address(),
+            None => return parent_weight,
+        };
+
+        let staking = Staking::from_state(Interpreter::new(self.state_at(parent.state_root())));
+        let stake = match staking.get_validator(&proposer) {
+            Some(v) => v.effective_balance,
+            None => 0,
+        };
+        parent_weight + stake
+    }
+
+    // Proposer key must belong to a validator staking at that height
+    fn is_active_validator(staking: &Staking, pk: &[u8], height: u64) -> bool {
+        let addr = BlockProof::new(0u8, pk).to_address();
+        match staking.get_validator(&addr) {
+            Some(v) => v.pubkey.as_slice() == pk && v.is_active(height),
+            None => false,
+        }
+    }
+
+    // Fork choice, prefer the longest chain and then the heaviest one
+    fn is_better_head(&self, current: &Block, block: &Block) -> bool {
+        if block.height() != current.height() {
+            return block.height() > current.height();
+        }
+        let current_weight = self.db.get_weight(&current.hash()).unwrap_or(0);
+        let weight = self.db.get_weight(&block.hash()).unwrap_or(0);
+        weight > current_weight
+    }
+
+    // Switch canonical chain from current head to a block on side chain
+    fn reorg(&mut self, current: &Block, head: &Block) -> Result<(), Error> {
+        let mut enacted = Vec::new();
+        let mut retracted = Vec::new();
+
+        // Walk back new branch to the height of current head
+        let mut new_branch = head.clone();
+        while new_branch.height() > current.height() {
+            let parent = match self.get_block(new_branch.header.parent_hash) {
+                Some(b) => b,
+                None => return Err(BlockChainErrorKind::UnknownAncestor.into()),
+            };
+            enacted.push(new_branch);
+            new_branch = parent;
+        }
+
+        let ancestor = match self.db.find_ancestor(current.header.clone(), new_branch.header.clone()) {
+            Some(h) => h,
+            None => return Err(BlockChainErrorKind::UnknownAncestor.into()),
+        };
+
+        let mut old_branch = current.clone();
+        while old_branch.hash() != ancestor {
+            let parent = match self.get_block(old_branch.header.parent_hash) {
+                Some(b) => b,
+                None => return Err(BlockChainErrorKind::UnknownAncestor.into()),
+            };
+            retracted.push(old_branch);
+            old_branch = parent;
+        }
+        while new_branch.hash() != ancestor {
+            let parent = match self.get_block(new_branch.header.parent_hash) {
+                Some(b) => b,
+                None => return Err(BlockChainErrorKind::UnknownAncestor.into()),
+            };
+            enacted.push(new_branch);
+            new_branch = parent;
+        }
+        enacted.reverse();
+
+        for b in retracted.iter() {
+            self.db.delete_tx_index(b).expect("can not delete tx index");
+        }
+        for b in enacted.iter() {
+            self.db.write_header_hash(b.height(), &b.hash()).expect("can not write header hash");
+            self.db.write_tx_index(b).expect("can not write tx index");
+        }
+        self.db.write_head_hash(head.hash()).expect("can not wirte head");
+
+        info!("chain reorg, ancestor={}, retracted={}, enacted={}, head height={} hash={}",
+            ancestor, retracted.len(), enacted.len(), head.height(), head.hash());
+        if let Some(ref events) = self.events {
+            events.notify_chain_reorg(ChainReorg {
+                retracted: retracted,
+                enacted: enacted,
+            });
+        }
+        Ok(())
+    }
+
     pub fn insert_block(&mut self, block: Block) -> Result<(), Error> {
         self.insert_block_ref(&block)
     }
 
+    /// Import block, all writes of chain and state are committed in one batch
     pub fn insert_block_ref(&mut self, block: &Block) -> Result<(), Error> {
+        self.db.begin_batch();
+        match self.import_block(block) {
+            Ok(_) => {
+                self.db.commit_batch().expect("can not commit block");
+                Ok(())
+            }
+            Err(e) => {
+                self.db.discard_batch();
+                Err(e)
+            }
+        }
+    }
+
+    fn import_block(&mut self, block: &Block) -> Result<(), Error> {
         // Already in chain
         if self.exits_block(block.hash(), block.height()) {
             return Err(BlockChainErrorKind::KnownBlock.into());
@@ -139,22 +523,51 @@ impl BlockChain {
             return Err(BlockChainErrorKind::UnknownAncestor.into());
         }
 
-        let current = self.current_block();
+        self.validator.validate_header(self, &block.header)?;
+        self.validator.validate_block(self, &block)?;
 
-        if block.header.parent_hash != current.hash() {
-            return Err(BlockChainErrorKind::UnknownAncestor.into());
+        let parent = match self.get_block(block.header.parent_hash) {
+            Some(b) => b,
+            None => return Err(BlockChainErrorKind::UnknownAncestor.into()),
+        };
+        if self.is_pruned(&parent.header) {
+            return Err(BlockChainErrorKind::PrunedState.into());
         }
 
-        self.validator.validate_header(self, &block.header)?;
-        // self.validator.validate_block(self, &block)?;
-        // if let Err(e) = self.consensus.verify(&block) {
-        //     error!("consensus err height={}, {:?}", block.height(), e);
-        //     return Err(BlockChainErrorKind::InvalidAuthority.into());
-        // }
+        let staking = Staking::from_state(Interpreter::new(self.state_at(parent.state_root())));
+        let authorized = |pk: &[u8]| {
+            self.consensus.is_poa_sign(pk) || Self::is_active_validator(&staking, pk, block.height())
+        };
+        if let Err(e) = self.consensus.verify_with(&block, authorized) {
+            error!("consensus err height={}, {:?}", block.height(), e);
+            return Err(BlockChainErrorKind::InvalidAuthority.into());
+        }
+
+        let (receipts, journal) = self.validator.validate_state(self, block)?;
+        let weight = self.block_weight(block, &parent);
 
+        // Side chain blocks are stored as well
         self.db.write_block(&block).expect("can not write block");
-        self.db.write_head_hash(block.header.hash()).expect("can not wirte head");
-        info!("insert block, height={}, hash={}, previous={}", block.height(), block.hash(), block.header.parent_hash);
+        self.db.write_receipts(&block.hash(), &receipts).expect("can not write receipts");
+        self.db.write_weight(&block.hash(), weight).expect("can not write weight");
+        self.state_backend.apply_journal(block.height(), &block.hash(), &block.state_root(), journal);
+
+        let current = self.current_block();
+        if block.header.parent_hash == current.hash() {
+            self.db.write_header_hash(block.height(), &block.hash()).expect("can not write header hash");
+            self.db.write_tx_index(&block).expect("can not write tx index");
+            self.db.write_head_hash(block.header.hash()).expect("can not wirte head");
+            info!("insert block, height={}, hash={}, previous={}", block.height(), block.hash(), block.header.parent_hash);
+            debug!("state cache {:?}", self.state_backend.cache_stats());
+            if let Some(ref events) = self.events {
+                events.notify_new_block(block.clone());
+            }
+        } else if self.is_better_head(&current, block) {
+            self.reorg(&current, block)?;
+        } else {
+            info!("insert side block, height={}, hash={}, previous={}", block.height(), block.hash(), block.header.parent_hash);
+        }
+        self.prune_state();
         Ok(())
     }
 }
@@ -172,9 +585,28 @@ impl Validator {
             return Err(BlockChainErrorKind::MismatchHash.into());
         }
 
+        // Same limits the producer packs transactions by
+        if block.txs.len() > MAX_BLOCK_TXS {
+            return Err(BlockChainErrorKind::BlockLimitExceeded.reason("too many transactions").into());
+        }
+        let gas = block.txs.iter().fold(0u64, |sum, tx| sum.saturating_add(tx.gas));
+        if gas > BLOCK_GAS_LIMIT {
+            return Err(BlockChainErrorKind::BlockLimitExceeded.reason("gas limit exceeded").into());
+        }
+
         Ok(())
     }
 
+    /// Re-execute transactions on the parent state, the result must match the claimed state root
+    pub fn validate_state(&self, chain: &BlockChain, block: &Block) -> Result<(Vec<Receipt>, StateJournal), Error> {
+        let (root, receipts, journal) = chain.execute_block(block)?;
+        if root != block.state_root() {
+            error!("state root mismatch height={}, expect={}, got={}", block.height(), block.state_root(), root);
+            return Err(BlockChainErrorKind::MismatchStateRoot.into());
+        }
+        Ok((receipts, journal))
+    }
+
     pub fn validate_header(&self, chain: &BlockChain, header: &Header) -> Result<(), Error> {
         // Ensure block parent exists on chain
         let pre = match chain.get_block(header.parent_hash) {
@@ -198,24 +630,43 @@ impl Validator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use map_core::balance::Balance;
-    use std::time::SystemTime;
+    use std::time::{Duration, SystemTime};
+    use ed25519::generator::Generator;
+    use map_core::staking::MsgValidatorCreate;
+    use map_core::test_helpers::{address_of, genesis_key, signed_tx, transfer_tx};
+    use map_core::types::Address;
+    use map_events::EventService;
+    use crate::BlockChainError;
+    use crate::test_helpers::make_block;
+
+    fn memory_chain() -> BlockChain {
+        BlockChain::new(Arc::new(RwLock::new(MemoryKV::new())), "".to_string())
+    }
+
+    fn canonical_hash(chain: &BlockChain, num: u64) -> Hash {
+        chain.get_header_by_number(num).unwrap().hash()
+    }
+
+    fn error_kind(e: Error) -> Option<BlockChainErrorKind> {
+        e.downcast_ref::<BlockChainError>().map(|e| e.kind().clone())
+    }
 
     #[test]
     fn test_init() {
-        let mut chain = BlockChain::new(PathBuf::from("./mapdata"),"".to_string());
-        let mut state = Balance::new(PathBuf::from("./balance"));
-        chain.load(&mut state);
+        let mut chain = memory_chain();
+        chain.load().unwrap();
         assert_eq!(chain.genesis.height(), 0);
         assert_eq!(chain.genesis.header.parent_hash, Hash::default());
         assert!(chain.get_block_by_number(0).is_some());
+        let state = chain.state_at(chain.genesis.state_root());
+        assert_eq!(GasSchedule::from_state(state.clone()).as_ref(), Some(chain.gas_schedule()));
+        assert_eq!(IssuanceSchedule::from_state(state).as_ref(), Some(chain.issuance_schedule()));
     }
 
     #[test]
     fn test_insert_empty() {
-        let mut chain = BlockChain::new(PathBuf::from("./mapdata"),"".to_string());
-        let mut state = Balance::new(PathBuf::from("./balance"));
-        chain.load(&mut state);
+        let mut chain = memory_chain();
+        chain.load().unwrap();
         {
             let block = Block {
                 header: Header{
@@ -246,4 +697,197 @@ mod tests {
             assert!(ret.is_err());
         }
     }
+
+    #[test]
+    fn test_side_block() {
+        let mut chain = memory_chain();
+        chain.load().unwrap();
+        let key = genesis_key();
+        let genesis = chain.current_block();
+
+        let a1 = make_block(&chain, &genesis, vec![transfer_tx(&key, 1, Address([1; 20]), 10)], &key, 1);
+        chain.insert_block_ref(&a1).unwrap();
+        let b1 = make_block(&chain, &genesis, vec![transfer_tx(&key, 1, Address([2; 20]), 10)], &key, 2);
+        chain.insert_block_ref(&b1).unwrap();
+
+        // Sibling of same height and weight is kept aside
+        assert_eq!(chain.current_block().hash(), a1.hash());
+        assert_eq!(canonical_hash(&chain, 1), a1.hash());
+        assert!(chain.get_block(b1.hash()).is_some());
+        assert_eq!(chain.get_transaction(&a1.txs[0].hash()).unwrap().1.block_hash, a1.hash());
+        assert!(chain.get_transaction(&b1.txs[0].hash()).is_none());
+        assert!(chain.insert_block_ref(&b1).is_err());
+    }
+
+    #[test]
+    fn test_reorg_longer_branch() {
+        let mut chain = memory_chain();
+        chain.load().unwrap();
+        let events = EventService::new().start(None::<String>);
+        let reorgs = events.subscribe_chain_reorg("test");
+        chain.set_event_handler(events.clone());
+        let key = genesis_key();
+        let genesis = chain.current_block();
+
+        let a1 = make_block(&chain, &genesis, vec![transfer_tx(&key, 1, Address([1; 20]), 10)], &key, 1);
+        chain.insert_block_ref(&a1).unwrap();
+        let b1 = make_block(&chain, &genesis, vec![transfer_tx(&key, 1, Address([2; 20]), 10)], &key, 2);
+        chain.insert_block_ref(&b1).unwrap();
+        let b2 = make_block(&chain, &b1, vec![transfer_tx(&key, 2, Address([2; 20]), 10)], &key, 1);
+        chain.insert_block_ref(&b2).unwrap();
+
+        assert_eq!(chain.current_block().hash(), b2.hash());
+        assert_eq!(canonical_hash(&chain, 1), b1.hash());
+        assert_eq!(canonical_hash(&chain, 2), b2.hash());
+        assert!(chain.get_transaction(&a1.txs[0].hash()).is_none());
+        let (_, location) = chain.get_transaction(&b1.txs[0].hash()).unwrap();
+        assert_eq!((location.block_hash, location.height), (b1.hash(), 1));
+        assert_eq!(chain.get_transaction(&b2.txs[0].hash()).unwrap().1.block_hash, b2.hash());
+
+        let reorg = reorgs.recv_timeout(Duration::from_secs(5)).unwrap();
+        let retracted: Vec<Hash> = reorg.retracted.iter().map(|b| b.hash()).collect();
+        let enacted: Vec<Hash> = reorg.enacted.iter().map(|b| b.hash()).collect();
+        assert_eq!(retracted, vec![a1.hash()]);
+        assert_eq!(enacted, vec![b1.hash(), b2.hash()]);
+    }
+
+    #[test]
+    fn test_reorg_heavier_tie() {
+        let mut chain = memory_chain();
+        chain.load().unwrap();
+        let key = genesis_key();
+        let (other, other_pk) = Generator::default().new();
+
+        // Stake of the second validator is active from the first epoch
+        let fund = transfer_tx(&key, 1, address_of(&other), 1_000_000);
+        let input = bincode::serialize(&MsgValidatorCreate {
+            pubkey: other_pk.to_bytes(),
+            amount: 1000,
+            commission: 0,
+        }).unwrap();
+        let validate = signed_tx(&other, 1, 1, 100_000, b"staking.validate", input);
+        for txs in vec![vec![fund], vec![validate]] {
+            let block = make_block(&chain, &chain.current_block(), txs, &key, 1);
+            chain.insert_block_ref(&block).unwrap();
+        }
+        while chain.current_block().height() < map_core::staking::EPOCH_LENGTH {
+            let block = make_block(&chain, &chain.current_block(), Vec::new(), &key, 1);
+            chain.insert_block_ref(&block).unwrap();
+        }
+
+        let parent = chain.current_block();
+        let light = make_block(&chain, &parent, Vec::new(), &key, 1);
+        let heavy = make_block(&chain, &parent, Vec::new(), &other, 2);
+        chain.insert_block_ref(&light).unwrap();
+        assert_eq!(chain.current_block().hash(), light.hash());
+        chain.insert_block_ref(&heavy).unwrap();
+        assert_eq!(chain.current_block().hash(), heavy.hash());
+        assert_eq!(canonical_hash(&chain, heavy.height()), heavy.hash());
+
+        // Lighter sibling does not take the head back
+        let late = make_block(&chain, &parent, Vec::new(), &key, 3);
+        chain.insert_block_ref(&late).unwrap();
+        assert_eq!(chain.current_block().hash(), heavy.hash());
+    }
+
+    #[test]
+    fn test_reject_mismatch_roots() {
+        let mut chain = memory_chain();
+        chain.load().unwrap();
+        let key = genesis_key();
+        let genesis = chain.current_block();
+        let txs = vec![transfer_tx(&key, 1, Address([1; 20]), 10)];
+        let valid = make_block(&chain, &genesis, txs.clone(), &key, 1);
+
+        // Sealed by the authority but claiming another state
+        let wrong_state = poa::POA::new(None).finalize_block(
+            Block { signs: Vec::new(), ..valid.clone() }, Hash([7; 32])).unwrap();
+        let err = chain.insert_block_ref(&wrong_state).unwrap_err();
+        assert_eq!(error_kind(err), Some(BlockChainErrorKind::MismatchStateRoot));
+
+        let mut unsealed = Block { signs: Vec::new(), ..valid.clone() };
+        unsealed.header.tx_root = Hash([7; 32]);
+        let wrong_txs = poa::POA::new(None).finalize_block(unsealed, valid.state_root()).unwrap();
+        let err = chain.insert_block_ref(&wrong_txs).unwrap_err();
+        assert_eq!(error_kind(err), Some(BlockChainErrorKind::MismatchHash));
+
+        let mut wrong_signs = valid.clone();
+        wrong_signs.header.sign_root = Hash([7; 32]);
+        let err = chain.insert_block_ref(&wrong_signs).unwrap_err();
+        assert_eq!(error_kind(err), Some(BlockChainErrorKind::MismatchHash));
+
+        assert_eq!(chain.current_block().hash(), genesis.hash());
+        chain.insert_block_ref(&valid).unwrap();
+        assert_eq!(chain.current_block().hash(), valid.hash());
+    }
+
+    #[test]
+    fn test_reject_block_limits() {
+        let mut chain = memory_chain();
+        chain.load().unwrap();
+        let key = genesis_key();
+        let genesis = chain.current_block();
+        let valid = make_block(&chain, &genesis, Vec::new(), &key, 1);
+
+        let txs = (0..=MAX_BLOCK_TXS as u64).map(|n| transfer_tx(&key, n + 1, Address([1; 20]), 1)).collect();
+        let too_many = Block::new(valid.header, txs, valid.signs.clone(), valid.proofs.clone());
+        let err = chain.insert_block_ref(&too_many).unwrap_err();
+        assert_eq!(error_kind(err), Some(BlockChainErrorKind::BlockLimitExceeded));
+
+        let txs = (1..=2).map(|n| signed_tx(&key, n, 1, BLOCK_GAS_LIMIT / 2 + 1, b"balance.transfer", Vec::new())).collect();
+        let over_gas = Block::new(valid.header, txs, valid.signs.clone(), valid.proofs.clone());
+        let err = chain.insert_block_ref(&over_gas).unwrap_err();
+        assert_eq!(error_kind(err), Some(BlockChainErrorKind::BlockLimitExceeded));
+
+        chain.insert_block_ref(&valid).unwrap();
+    }
+
+    #[test]
+    fn test_reject_foreign_proposer() {
+        let mut chain = memory_chain();
+        chain.load().unwrap();
+        let genesis = chain.current_block();
+
+        // Well signed, but the proposer is neither the authority nor a validator
+        let (foreign, _) = Generator::default().new();
+        let b1 = make_block(&chain, &genesis, Vec::new(), &foreign, 1);
+        let e = chain.insert_block_ref(&b1).unwrap_err();
+        assert_eq!(error_kind(e), Some(BlockChainErrorKind::InvalidAuthority));
+        assert_eq!(chain.current_block().hash(), genesis.hash());
+
+        let a1 = make_block(&chain, &genesis, Vec::new(), &genesis_key(), 1);
+        chain.insert_block_ref(&a1).unwrap();
+    }
+
+    #[test]
+    fn test_pruned_side_branch() {
+        let kv: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let mut chain = BlockChain::with_pruning(kv, "".to_string(), PruningMode::Recent(2));
+        chain.load().unwrap();
+        let key = genesis_key();
+        let genesis = chain.current_block();
+
+        let a1 = make_block(&chain, &genesis, vec![transfer_tx(&key, 1, Address([1; 20]), 10)], &key, 1);
+        chain.insert_block_ref(&a1).unwrap();
+        let b1 = make_block(&chain, &genesis, vec![transfer_tx(&key, 1, Address([2; 20]), 10)], &key, 2);
+        chain.insert_block_ref(&b1).unwrap();
+        let a2 = make_block(&chain, &a1, Vec::new(), &key, 1);
+        chain.insert_block_ref(&a2).unwrap();
+        let b2 = make_block(&chain, &b1, Vec::new(), &key, 1);
+        chain.insert_block_ref(&b2).unwrap();
+        assert_eq!(chain.current_block().hash(), a2.hash());
+        let b3 = make_block(&chain, &b2, Vec::new(), &key, 1);
+
+        // Height 1 leaves history, state of b1 is released with it
+        let a3 = make_block(&chain, &a2, Vec::new(), &key, 1);
+        chain.insert_block_ref(&a3).unwrap();
+        assert_eq!(chain.state_backend.pruned_height(), Some(1));
+        let e = chain.insert_block_ref(&b3).unwrap_err();
+        assert_eq!(error_kind(e), Some(BlockChainErrorKind::PrunedState));
+
+        // Side block forked within history is still imported
+        let c3 = make_block(&chain, &a2, Vec::new(), &key, 2);
+        chain.insert_block_ref(&c3).unwrap();
+        assert_eq!(chain.current_block().hash(), a3.hash());
+    }
 }
@@ -25,6 +25,10 @@ extern crate enum_display_derive;
 pub mod store;
 pub mod blockchain;
 pub mod tx_pool;
+pub mod snapshot;
+pub mod schema;
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod test_helpers;
 use std::fmt::{self, Display,Debug};
 use errors::{Error,ErrorKind};
 use failure::{Backtrace,err_msg, Context, Fail};
@@ -43,7 +47,12 @@ pub enum BlockChainErrorKind {
     InvalidBlockProof,
     InvalidBlockTime,
     InvalidBlockHeight,
+    BlockLimitExceeded,
     InvalidAuthority,
+    MismatchStateRoot,
+    PrunedState,
+    InvalidSnapshot,
+    SchemaMismatch,
 }
 
 #[derive(Debug, PartialEq)]
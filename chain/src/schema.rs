@@ -0,0 +1,267 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Schema version of chain database and migration steps between versions.
+//!
+//! A migration step upgrades the database by exactly one version. Steps run in a
+//! single batch with the metadata update, so an interrupted migration is not visible.
+
+use std::io;
+use std::sync::{Arc, RwLock};
+use errors::Error;
+use map_core::types::Hash;
+use map_store::KVDB;
+
+use crate::store::{ChainDB, DBMeta};
+use super::BlockChainErrorKind;
+
+/// Schema version written by this build
+pub const SCHEMA_VERSION: u32 = 2;
+/// Version of databases written before the metadata record existed
+pub const LEGACY_VERSION: u32 = 1;
+
+pub struct Migration {
+    /// Version upgraded from, the step results in version + 1
+    pub version: u32,
+    pub description: &'static str,
+    pub run: fn(&mut dyn KVDB) -> io::Result<()>,
+}
+
+/// Registered migration steps in version order
+pub fn migrations() -> Vec<Migration> {
+    Vec::new()
+}
+
+/// Schema version of database, None if it holds no chain
+pub fn db_version(db: &ChainDB) -> Option<u32> {
+    match db.get_meta() {
+        Some(meta) => Some(meta.version),
+        None if db.head_hash().is_some() => Some(LEGACY_VERSION),
+        None => None,
+    }
+}
+
+// Version 1 hashed transaction and signature lists as one blob, keyed accounts by hash of
+// address and had no gas and issuance schedules in genesis state. Its genesis hash differs
+// from the one of this build and every later header commits to it, so it is not upgraded.
+fn legacy_error() -> Error {
+    BlockChainErrorKind::SchemaMismatch.reason(format!(
+        "database schema version {} predates the current block and state encoding and can not be migrated, \
+         remove the datadir and sync again", LEGACY_VERSION)).into()
+}
+
+/// Refuse database of other schema version or genesis
+pub fn check(db: &ChainDB, genesis: &Hash) -> Result<(), Error> {
+    let version = match db_version(db) {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    if version == LEGACY_VERSION {
+        return Err(legacy_error());
+    }
+    if version < SCHEMA_VERSION {
+        return Err(BlockChainErrorKind::SchemaMismatch.reason(format!(
+            "database schema version {} is older than {}, run `map db migrate`", version, SCHEMA_VERSION)).into());
+    }
+    if version > SCHEMA_VERSION {
+        return Err(BlockChainErrorKind::SchemaMismatch.reason(format!(
+            "database schema version {} is newer than supported {}", version, SCHEMA_VERSION)).into());
+    }
+    let meta = db.get_meta().unwrap();
+    if meta.genesis != *genesis {
+        return Err(BlockChainErrorKind::SchemaMismatch.reason(format!(
+            "database genesis {} does not match chain genesis {}", meta.genesis, genesis)).into());
+    }
+    Ok(())
+}
+
+/// Upgrade database to the current schema version, returns the applied step descriptions
+pub fn migrate(kv: Arc<RwLock<dyn KVDB>>) -> Result<Vec<&'static str>, Error> {
+    run_migrations(kv, &migrations(), SCHEMA_VERSION)
+}
+
+fn run_migrations(kv: Arc<RwLock<dyn KVDB>>, steps: &[Migration], target: u32) -> Result<Vec<&'static str>, Error> {
+    let mut db = ChainDB::new(Arc::clone(&kv));
+    let mut version = match db_version(&db) {
+        Some(v) => v,
+        None => return Ok(Vec::new()),
+    };
+    if version == LEGACY_VERSION {
+        return Err(legacy_error());
+    }
+    if version > target {
+        return Err(BlockChainErrorKind::SchemaMismatch.reason(format!(
+            "database schema version {} is newer than supported {}", version, target)).into());
+    }
+
+    let mut applied = Vec::new();
+    for step in steps {
+        if step.version < version {
+            continue;
+        }
+        if step.version != version {
+            break;
+        }
+        info!("migrate database from version {}: {}", version, step.description);
+        db.begin_batch();
+        if let Err(e) = (step.run)(&mut *kv.write().unwrap()) {
+            db.discard_batch();
+            return Err(BlockChainErrorKind::SchemaMismatch.reason(format!(
+                "migration from version {} failed: {}", version, e)).into());
+        }
+        let genesis = db.get_meta().unwrap().genesis;
+        version += 1;
+        db.write_meta(&DBMeta { version: version, genesis: genesis }).expect("can not write meta");
+        db.commit_batch().expect("can not commit migration");
+        applied.push(step.description);
+    }
+
+    if version != target {
+        return Err(BlockChainErrorKind::SchemaMismatch.reason(format!(
+            "no migration from database schema version {}", version)).into());
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_store::{MemoryKV, Column};
+    use map_core::block::{Block, BlockProof};
+    use map_core::genesis;
+    use map_core::state::{ArchiveDB, StateDB};
+    use map_core::storage::storage_prefix;
+    use map_core::transaction::Transaction;
+    use map_core::types::Address;
+    use crate::blockchain::BlockChain;
+
+    fn memory_kv() -> Arc<RwLock<dyn KVDB>> {
+        Arc::new(RwLock::new(MemoryKV::new()))
+    }
+
+    // Plain blake2b of data, as version 1 hashed lists and account keys
+    fn legacy_hash(data: &[u8]) -> Hash {
+        storage_prefix(data)
+    }
+
+    // Chain db of version 1 in the baseline encoding, holding genesis and a block on it.
+    // Genesis state keys the account by hash of address and has no schedules.
+    fn legacy_fixture() -> (Arc<RwLock<dyn KVDB>>, Block) {
+        let backend = ArchiveDB::new(memory_kv());
+        let mut state = StateDB::new(&backend);
+        // Account of version 1 is (balance, nonce, locked_balance)
+        let account = bincode::serialize(&(1_000_000_000_000_000_000u128, 0u64, 0u128)).unwrap();
+        state.set_storage(legacy_hash(Hash::from_bytes(Address::default().as_slice()).as_bytes()), &account);
+        state.commit();
+
+        let mut genesis = Block::default();
+        genesis.proofs.push(BlockProof(genesis::ed_genesis_pub_key, [0u8; 32], 0));
+        genesis.header.tx_root = legacy_hash(&bincode::serialize(&genesis.txs).unwrap());
+        genesis.header.sign_root = legacy_hash(&bincode::serialize(&genesis.signs).unwrap());
+        genesis.header.state_root = state.root();
+
+        let mut block = Block::default();
+        block.header.height = 1;
+        block.header.parent_hash = genesis.hash();
+        block.header.state_root = genesis.state_root();
+        block.header.time = genesis.header.time + 1;
+        block.txs.push(Transaction::new(Address::default(), 0, 1, 100_000, b"balance.transfer".to_vec(), Vec::new()));
+        block.header.tx_root = legacy_hash(&bincode::serialize(&block.txs).unwrap());
+        block.header.sign_root = legacy_hash(&bincode::serialize(&block.signs).unwrap());
+
+        let chain = memory_kv();
+        {
+            let mut db = chain.write().unwrap();
+            for b in [&genesis, &block].iter() {
+                let mut key = vec![b'h'];
+                key.extend_from_slice(b.hash().as_bytes());
+                db.put(Column::Default, &key, &bincode::serialize(&b.header).unwrap()).unwrap();
+                key[0] = b'b';
+                db.put(Column::Default, &key, &bincode::serialize(b).unwrap()).unwrap();
+                let mut key = vec![b'n'];
+                key.extend_from_slice(&b.height().to_be_bytes());
+                db.put(Column::Default, &key, b.hash().as_bytes()).unwrap();
+            }
+            db.put(Column::Default, b"HHEAD", block.hash().as_bytes()).unwrap();
+        }
+        (chain, genesis)
+    }
+
+    #[test]
+    fn test_legacy_not_migratable() {
+        let (kv, legacy_genesis) = legacy_fixture();
+        assert_eq!(db_version(&ChainDB::new(Arc::clone(&kv))), Some(LEGACY_VERSION));
+
+        // Genesis of this build commits to other roots and state
+        let mut fresh = BlockChain::new(memory_kv(), "".to_string());
+        fresh.load().unwrap();
+        assert_ne!(fresh.genesis_hash(), legacy_genesis.hash());
+
+        let mut chain = BlockChain::new(Arc::clone(&kv), "".to_string());
+        assert!(chain.load().is_err());
+        assert!(migrate(Arc::clone(&kv)).is_err());
+
+        // Database is left untouched
+        assert_eq!(db_version(&ChainDB::new(Arc::clone(&kv))), Some(LEGACY_VERSION));
+        assert!(kv.read().unwrap().iter(Column::Headers).next().is_none());
+        assert_eq!(kv.read().unwrap().iter(Column::Default).count(), 7);
+    }
+
+    fn mark_migrated(db: &mut dyn KVDB) -> io::Result<()> {
+        db.put(Column::Default, b"migrated", b"1")
+    }
+
+    #[test]
+    fn test_run_migrations() {
+        let kv = memory_kv();
+        let mut chain = BlockChain::new(Arc::clone(&kv), "".to_string());
+        chain.load().unwrap();
+        let genesis = chain.genesis_hash();
+        assert!(migrate(Arc::clone(&kv)).unwrap().is_empty());
+
+        let steps = vec![Migration {
+            version: SCHEMA_VERSION,
+            description: "mark database",
+            run: mark_migrated,
+        }];
+        let applied = run_migrations(Arc::clone(&kv), &steps, SCHEMA_VERSION + 1).unwrap();
+        assert_eq!(applied, vec!["mark database"]);
+        let meta = ChainDB::new(Arc::clone(&kv)).get_meta().unwrap();
+        assert_eq!(meta.version, SCHEMA_VERSION + 1);
+        assert_eq!(meta.genesis, genesis);
+        assert!(kv.read().unwrap().get(Column::Default, b"migrated").unwrap().is_some());
+
+        // Applied step is not run again and no step leads further
+        assert!(run_migrations(Arc::clone(&kv), &steps, SCHEMA_VERSION + 1).unwrap().is_empty());
+        assert!(run_migrations(Arc::clone(&kv), &steps, SCHEMA_VERSION + 2).is_err());
+    }
+
+    #[test]
+    fn test_check_meta() {
+        let kv = memory_kv();
+        let mut chain = BlockChain::new(Arc::clone(&kv), "".to_string());
+        chain.load().unwrap();
+        let genesis = chain.genesis_hash();
+
+        let mut db = ChainDB::new(Arc::clone(&kv));
+        assert!(check(&db, &genesis).is_ok());
+        assert!(check(&db, &Hash::default()).is_err());
+
+        db.write_meta(&DBMeta { version: SCHEMA_VERSION + 1, genesis: genesis }).unwrap();
+        assert!(check(&db, &genesis).is_err());
+        assert!(migrate(Arc::clone(&kv)).is_err());
+    }
+}
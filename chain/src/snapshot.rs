@@ -0,0 +1,258 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! State snapshot of a block, to bootstrap a node without replaying the chain.
+//!
+//! File layout: bincode encoded `Manifest`, then state entries as `Some((key, value))`
+//! in key order, terminated by `None`.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use errors::{Error, InternalErrorKind};
+use map_core::block::{Block, Header};
+use map_core::types::Hash;
+
+use crate::blockchain::BlockChain;
+use super::BlockChainErrorKind;
+
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"MAPS";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub magic: [u8; 4],
+    pub version: u32,
+    /// Block whose state is in snapshot
+    pub block: Block,
+    /// Canonical headers from genesis up to parent of block
+    pub headers: Vec<Header>,
+}
+
+type Entry = Option<(Hash, Vec<u8>)>;
+
+fn invalid<S: ToString>(reason: S) -> Error {
+    BlockChainErrorKind::InvalidSnapshot.reason(reason.to_string()).into()
+}
+
+/// Write state of canonical block at height to file, return number of entries
+pub fn export_snapshot(chain: &BlockChain, height: u64, path: &Path) -> Result<u64, Error> {
+    let block = match chain.get_block_by_number(height) {
+        Some(b) => b,
+        None => return Err(invalid(format!("no block at height {}", height))),
+    };
+    let mut headers = Vec::with_capacity(height as usize);
+    for num in 0..height {
+        match chain.get_header_by_number(num) {
+            Some(h) => headers.push(h),
+            None => return Err(invalid(format!("no header at height {}", num))),
+        }
+    }
+
+    let file = File::create(path)
+        .map_err(|e| InternalErrorKind::Other(format!("create snapshot {}", e)))?;
+    let mut writer = BufWriter::new(file);
+    let manifest = Manifest {
+        magic: SNAPSHOT_MAGIC,
+        version: SNAPSHOT_VERSION,
+        block: block.clone(),
+        headers: headers,
+    };
+    bincode::serialize_into(&mut writer, &manifest).map_err(|e| invalid(e))?;
+
+    let state = chain.state_at(block.state_root());
+    let mut count = 0u64;
+    let mut failure = None;
+    state.borrow().for_each(|key, value| {
+        if failure.is_some() {
+            return;
+        }
+        let entry: Entry = Some((key, value));
+        match bincode::serialize_into(&mut writer, &entry) {
+            Ok(_) => count += 1,
+            Err(e) => failure = Some(e),
+        }
+    })?;
+    if let Some(e) = failure {
+        return Err(invalid(e));
+    }
+    let end: Entry = None;
+    bincode::serialize_into(&mut writer, &end).map_err(|e| invalid(e))?;
+    writer.flush().map_err(|e| invalid(e))?;
+
+    info!("export snapshot height={} hash={} entries={}", height, block.hash(), count);
+    Ok(count)
+}
+
+/// Read manifest from snapshot and check its format
+pub fn read_manifest<R: Read>(reader: &mut R) -> Result<Manifest, Error> {
+    let manifest: Manifest = bincode::deserialize_from(reader).map_err(|e| invalid(e))?;
+    if manifest.magic != SNAPSHOT_MAGIC {
+        return Err(invalid("not a snapshot file"));
+    }
+    if manifest.version != SNAPSHOT_VERSION {
+        return Err(invalid(format!("unsupported version {}", manifest.version)));
+    }
+    Ok(manifest)
+}
+
+struct EntryReader<R> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: Read> Iterator for EntryReader<R> {
+    type Item = Result<(Hash, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match bincode::deserialize_from::<_, Entry>(&mut self.reader) {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(invalid(e)))
+            }
+        }
+    }
+}
+
+/// Rebuild state from snapshot file and set its block as chain head, return the block
+pub fn import_snapshot(chain: &mut BlockChain, path: &Path) -> Result<Block, Error> {
+    let file = File::open(path)
+        .map_err(|e| InternalErrorKind::Other(format!("open snapshot {}", e)))?;
+    let mut reader = BufReader::new(file);
+    let manifest = read_manifest(&mut reader)?;
+
+    let entries = EntryReader {
+        reader: reader,
+        done: false,
+    };
+    chain.import_snapshot(&manifest.headers, &manifest.block, entries)?;
+    Ok(manifest.block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Arc, RwLock};
+    use ed25519::generator::Generator;
+    use map_consensus::poa::POA;
+    use map_core::balance::Balance;
+    use map_core::block::BlockProof;
+    use map_core::test_helpers::{genesis_key, transfer_tx};
+    use map_core::types::Address;
+    use map_store::MemoryKV;
+    use crate::test_helpers::make_block;
+    use crate::BlockChainError;
+
+    // Snapshot file under the system temp dir, removed on drop even if the test fails
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str) -> Self {
+            TestFile(std::env::temp_dir().join(format!("map-{}-{}.snap", name, std::process::id())))
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut source = BlockChain::new(Arc::new(RwLock::new(MemoryKV::new())), "".to_string());
+        source.load().unwrap();
+        let key = genesis_key();
+        let receiver = Address([7; 20]);
+        let genesis = source.current_block();
+        let b1 = make_block(&source, &genesis, vec![transfer_tx(&key, 1, receiver, 100)], &key, 1);
+        source.insert_block_ref(&b1).unwrap();
+        let b2 = make_block(&source, &b1, Vec::new(), &key, 1);
+        source.insert_block_ref(&b2).unwrap();
+
+        let file = TestFile::new("snapshot-roundtrip");
+        let path = &file.0;
+        let count = export_snapshot(&source, 2, path).unwrap();
+        assert!(count > 0);
+        assert!(export_snapshot(&source, 100, path).is_err());
+
+        let mut target = BlockChain::new(Arc::new(RwLock::new(MemoryKV::new())), "".to_string());
+        target.load().unwrap();
+        let block = import_snapshot(&mut target, path).unwrap();
+        assert_eq!(block.hash(), b2.hash());
+        assert_eq!(target.current_block().hash(), b2.hash());
+        let mut entries = 0;
+        target.state_at(block.state_root()).borrow().for_each(|_, _| entries += 1).unwrap();
+        assert_eq!(entries, count);
+        let runner = target.interpreter_at(block.state_root());
+        assert_eq!(Balance::from_state(runner).balance(receiver), 100);
+        for num in 0..2 {
+            assert_eq!(target.get_header_by_number(num), source.get_header_by_number(num));
+        }
+        assert_eq!(target.get_header_by_number(1).unwrap().hash(), b1.hash());
+    }
+
+    #[test]
+    fn test_snapshot_unauthorized_seal() {
+        let mut source = BlockChain::new(Arc::new(RwLock::new(MemoryKV::new())), "".to_string());
+        source.load().unwrap();
+        let key = genesis_key();
+        let genesis = source.current_block();
+        let b1 = make_block(&source, &genesis, vec![transfer_tx(&key, 1, Address([7; 20]), 100)], &key, 1);
+        source.insert_block_ref(&b1).unwrap();
+
+        // Same block and state sealed by a key outside the authority set
+        let (other, _) = Generator::default().new();
+        let mut forged = Block::new(b1.header, b1.txs.clone(), Vec::new(), Vec::new());
+        forged.add_proof(BlockProof::new(0, &other.to_pubkey().unwrap().to_bytes()));
+        let forged = POA::new(Some(other.to_bytes())).finalize_block(forged, b1.state_root()).unwrap();
+
+        let mut entries = Vec::new();
+        source.state_at(b1.state_root()).borrow().for_each(|k, v| entries.push(Ok((k, v)))).unwrap();
+        let headers = vec![genesis.header];
+        let mut target = BlockChain::new(Arc::new(RwLock::new(MemoryKV::new())), "".to_string());
+        target.load().unwrap();
+        let e = target.import_snapshot(&headers, &forged, entries).unwrap_err();
+        assert_eq!(e.downcast_ref::<BlockChainError>().map(|e| e.kind().clone()), Some(BlockChainErrorKind::InvalidAuthority));
+        assert_eq!(target.current_block().hash(), target.genesis_hash());
+    }
+
+    #[test]
+    fn test_snapshot_version() {
+        let file = TestFile::new("snapshot-version");
+        let path = &file.0;
+        let manifest = Manifest {
+            magic: SNAPSHOT_MAGIC,
+            version: SNAPSHOT_VERSION + 1,
+            block: Block::default(),
+            headers: Vec::new(),
+        };
+        fs::write(path, bincode::serialize(&manifest).unwrap()).unwrap();
+        let mut reader = BufReader::new(File::open(path).unwrap());
+        assert!(read_manifest(&mut reader).is_err());
+    }
+}
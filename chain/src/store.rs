@@ -14,44 +14,99 @@
 // You should have received a copy of the GNU General Public License
 // along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
-use map_store::mapdb::MapDB;
-use map_store::Config;
-use map_store::Error;
+use std::io;
+use std::sync::{Arc, RwLock};
+use map_store::{KVDB, Column};
 use map_core::block::{Header, Block};
+use map_core::receipt::Receipt;
 use map_core::types::Hash;
+use serde::{Deserialize, Serialize};
 use bincode;
 
 const HEADER_PREFIX: u8 = 'h' as u8;
 const HEAD_PREFIX: u8 = 'H' as u8;
 const BLOCK_PREFIX: u8 = 'b' as u8;
 const HEADERHASH_PREFIX: u8 = 'n' as u8;
+const TX_INDEX_PREFIX: u8 = 't' as u8;
+const RECEIPTS_PREFIX: u8 = 'r' as u8;
+const WEIGHT_PREFIX: u8 = 'w' as u8;
 const HEAD_KEY: &str = "HEAD";
+const META_KEY: &[u8] = b"meta";
+
+/// Position of a transaction in the canonical chain
+#[derive(Serialize, Deserialize)]
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct TxLocation {
+    pub block_hash: Hash,
+    pub height: u64,
+    pub index: u32,
+}
 
+/// Schema version and genesis the database was written with
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DBMeta {
+    pub version: u32,
+    pub genesis: Hash,
+}
 
 /// Blockchain storage backend implement
 pub struct ChainDB {
-    db: MapDB,
+    db: Arc<RwLock<dyn KVDB>>,
 }
 
 impl ChainDB {
 
-    pub fn new(cfg: Config) -> Result<Self, Error> {
-        let m = MapDB::open(cfg).unwrap();
-        Ok(ChainDB{db: m})
+    /// Chain storage on db shared with state backend
+    pub fn new(db: Arc<RwLock<dyn KVDB>>) -> Self {
+        ChainDB{db: db}
+    }
+
+    /// Gather writes of chain and state until commit_batch
+    pub fn begin_batch(&self) {
+        self.db.write().unwrap().begin_batch();
+    }
+
+    pub fn commit_batch(&self) -> io::Result<()> {
+        self.db.write().unwrap().commit_batch()
+    }
+
+    pub fn discard_batch(&self) {
+        self.db.write().unwrap().discard_batch();
+    }
+
+    pub fn get_meta(&self) -> Option<DBMeta> {
+        self.get(Column::Default, META_KEY).map(|m| bincode::deserialize(&m).unwrap())
+    }
+
+    pub fn write_meta(&mut self, meta: &DBMeta) -> io::Result<()> {
+        let encoded: Vec<u8> = bincode::serialize(meta).unwrap();
+        self.put(Column::Default, META_KEY, &encoded)
+    }
+
+    fn get(&self, col: Column, key: &[u8]) -> Option<Vec<u8>> {
+        self.db.read().unwrap().get(col, key).expect("db read exception")
+    }
+
+    fn put(&mut self, col: Column, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.db.write().unwrap().put(col, key, value)
+    }
+
+    fn remove(&mut self, col: Column, key: &[u8]) -> io::Result<()> {
+        self.db.write().unwrap().remove(col, key)
     }
 
-    // Save block header by hash (hash --> blockHeader)
-    pub fn write_header(&mut self, h: &Header) -> Result<(), Error> {
+    // Save block header by hash (hash --> blockHeader), canonical index is written by caller
+    pub fn write_header(&mut self, h: &Header) -> io::Result<()> {
         let encoded: Vec<u8> = bincode::serialize(h).unwrap();
         let key = Self::header_key(&(h.hash().0));
-        self.write_header_hash(h.height, &h.hash())?;
-        self.db.put(&key, &encoded)
+        self.put(Column::Headers, &key, &encoded)
     }
 
     // Read block header by hash (hash --> blockHeader)
     pub fn get_header(&self, h: &Hash) -> Option<Header> {
         let key = Self::header_key(&(h.0));
-        let serialized = match self.db.get(&key.as_slice()) {
+        let serialized = match self.get(Column::Headers, &key.as_slice()) {
             Some(s) => s,
             None => return None,
         };
@@ -61,9 +116,9 @@ impl ChainDB {
     }
 
     // Delete a block header by hash (hash --> blockHeader)
-    pub fn delete_header(&mut self, h: &Hash) -> Result<(), Error> {
+    pub fn delete_header(&mut self, h: &Hash) -> io::Result<()> {
         let key = Self::header_key(&(h.0));
-        self.db.remove(&key[..])
+        self.remove(Column::Headers, &key[..])
     }
 
     pub fn get_header_by_number(&self, num: u64) -> Option<Header> {
@@ -81,7 +136,7 @@ impl ChainDB {
             None => return None,
         };
         let key = Self::header_key(&(header_hash.0));
-        let serialized = match self.db.get(&key.as_slice()) {
+        let serialized = match self.get(Column::Headers, &key.as_slice()) {
             Some(s) => s,
             None => return None,
         };
@@ -91,7 +146,7 @@ impl ChainDB {
     }
 
     pub fn head_hash(&self) -> Option<Hash> {
-        let h = match self.db.get(&Self::head_key()[..]) {
+        let h = match self.get(Column::Default, &Self::head_key()[..]) {
             Some(h) => h,
             None => return None,
         };
@@ -100,15 +155,15 @@ impl ChainDB {
         Some(hash)
     }
 
-    pub fn write_head_hash(&mut self, hash: Hash) -> Result<(), Error>{
+    pub fn write_head_hash(&mut self, hash: Hash) -> io::Result<()>{
         let key = Self::head_key();
-        self.db.put(&key, hash.to_slice())
+        self.put(Column::Default, &key, hash.to_slice())
     }
 
     // read block header hash to certain height (num --> hash)
     pub fn get_header_hash(&self, num: u64) -> Option<Hash> {
         let key = Self::header_hash_key(num);
-        self.db.get(&key).map(|h| {
+        self.get(Column::Index, &key).map(|h| {
             let mut hash: Hash = Default::default();
             hash.0.copy_from_slice(h.as_slice());
             hash
@@ -116,15 +171,15 @@ impl ChainDB {
     }
 
     // write header hash to num (num --> hash)
-    pub fn write_header_hash(&mut self, num: u64, hash: &Hash) -> Result<(), Error> {
+    pub fn write_header_hash(&mut self, num: u64, hash: &Hash) -> io::Result<()> {
         let key = Self::header_hash_key(num);
-        self.db.put(&key, hash.to_slice())
+        self.put(Column::Index, &key, hash.to_slice())
     }
 
     // remove the block assigned to certain height (num --> hash)
-    pub fn delete_header_height(&mut self, num: u64) -> Result<(), Error> {
+    pub fn delete_header_height(&mut self, num: u64) -> io::Result<()> {
         let key = Self::header_hash_key(num);
-        self.db.remove(&key)
+        self.remove(Column::Index, &key)
     }
 
     pub fn head_block(&self) -> Option<Block> {
@@ -138,7 +193,7 @@ impl ChainDB {
 
     pub fn get_block(&self, h: &Hash) -> Option<Block> {
         let key = Self::block_key(h);
-        let serialized = match self.db.get(&key[..]) {
+        let serialized = match self.get(Column::Bodies, &key[..]) {
             Some(s) => s,
             None => return None,
         };
@@ -192,22 +247,84 @@ impl ChainDB {
         }
     }
 
-    pub fn write_block(&mut self, block: &Block) -> Result<(), Error> {
+    pub fn write_block(&mut self, block: &Block) -> io::Result<()> {
         self.write_header(&block.header)?;
         let key = Self::block_key(&block.header.hash());
         let encoded: Vec<u8> = bincode::serialize(block).unwrap();
-        self.db.put(&key, &encoded)
+        self.put(Column::Bodies, &key, &encoded)
     }
 
     // Delete a block with header by hash
-    pub fn delete_block(&mut self, h: &Hash) -> Result<(), Error> {
+    pub fn delete_block(&mut self, h: &Hash) -> io::Result<()> {
         // Delete block body
         let key = Self::block_key(h);
-        self.db.remove(&key[..])?;
+        self.remove(Column::Bodies, &key[..])?;
         // Delete it's header
         self.delete_header(h)
     }
 
+    // Index transactions of a canonical block (tx hash --> location)
+    pub fn write_tx_index(&mut self, block: &Block) -> io::Result<()> {
+        let block_hash = block.hash();
+        for (i, tx) in block.txs.iter().enumerate() {
+            let location = TxLocation {
+                block_hash: block_hash,
+                height: block.height(),
+                index: i as u32,
+            };
+            let encoded: Vec<u8> = bincode::serialize(&location).unwrap();
+            self.put(Column::Index, &Self::tx_index_key(&tx.hash()), &encoded)?;
+        }
+        Ok(())
+    }
+
+    // Remove transaction index of a block which is no longer canonical
+    pub fn delete_tx_index(&mut self, block: &Block) -> io::Result<()> {
+        for tx in block.txs.iter() {
+            self.remove(Column::Index, &Self::tx_index_key(&tx.hash()))?;
+        }
+        Ok(())
+    }
+
+    pub fn get_tx_location(&self, h: &Hash) -> Option<TxLocation> {
+        let serialized = match self.get(Column::Index, &Self::tx_index_key(h)) {
+            Some(s) => s,
+            None => return None,
+        };
+
+        let location: TxLocation = bincode::deserialize(&serialized[..]).unwrap();
+        Some(location)
+    }
+
+    // Save transaction receipts of block (block hash --> receipts)
+    pub fn write_receipts(&mut self, h: &Hash, receipts: &Vec<Receipt>) -> io::Result<()> {
+        let encoded: Vec<u8> = bincode::serialize(receipts).unwrap();
+        self.put(Column::Bodies, &Self::receipts_key(h), &encoded)
+    }
+
+    pub fn get_receipts(&self, h: &Hash) -> Option<Vec<Receipt>> {
+        let serialized = match self.get(Column::Bodies, &Self::receipts_key(h)) {
+            Some(s) => s,
+            None => return None,
+        };
+
+        let receipts: Vec<Receipt> = bincode::deserialize(&serialized[..]).unwrap();
+        Some(receipts)
+    }
+
+    // Save total weight of chain ending with block (hash --> weight)
+    pub fn write_weight(&mut self, h: &Hash, weight: u128) -> io::Result<()> {
+        self.put(Column::Headers, &Self::weight_key(h), &weight.to_be_bytes())
+    }
+
+    pub fn get_weight(&self, h: &Hash) -> Option<u128> {
+        self.get(Column::Headers, &Self::weight_key(h)).map(|w| {
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(&w[..]);
+            u128::from_be_bytes(bytes)
+        })
+    }
+
     fn head_key() -> Vec<u8> {
         let mut pre = Vec::new();
         pre.push(HEAD_PREFIX);
@@ -236,4 +353,25 @@ impl ChainDB {
         pre.extend_from_slice(hash.to_slice());
         pre
     }
+
+    fn tx_index_key(hash: &Hash) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(TX_INDEX_PREFIX);
+        pre.extend_from_slice(hash.to_slice());
+        pre
+    }
+
+    fn receipts_key(hash: &Hash) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(RECEIPTS_PREFIX);
+        pre.extend_from_slice(hash.to_slice());
+        pre
+    }
+
+    fn weight_key(hash: &Hash) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(WEIGHT_PREFIX);
+        pre.extend_from_slice(hash.to_slice());
+        pre
+    }
 }
@@ -0,0 +1,40 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Block fixtures shared by tests across the workspace, built with feature `test-helpers`
+
+use ed25519::privkey::PrivKey;
+use map_consensus::poa::POA;
+use map_core::block::{Block, BlockProof, Header};
+use map_core::transaction::Transaction;
+use map_executor::Executor;
+
+use crate::blockchain::BlockChain;
+
+/// Block on parent executed and sealed by key, time sets sibling blocks apart
+pub fn make_block(chain: &BlockChain, parent: &Block, txs: Vec<Transaction>, key: &PrivKey, time: u64) -> Block {
+    let header = Header {
+        height: parent.height() + 1,
+        parent_hash: parent.hash(),
+        time: parent.header.time + time,
+        ..Default::default()
+    };
+    let mut block = Block::new(header, txs, Vec::new(), Vec::new());
+    block.add_proof(BlockProof::new(0, &key.to_pubkey().unwrap().to_bytes()));
+    let mut runner = chain.interpreter_at(parent.state_root());
+    let (root, _) = Executor::exc_txs_in_block(&block, &mut runner, &POA::get_default_miner()).unwrap();
+    POA::new(Some(key.to_bytes())).finalize_block(block, root).unwrap()
+}
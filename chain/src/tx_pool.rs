@@ -1,5 +1,8 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::fmt;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{channel, Receiver, Sender};
 
 use map_core::balance::Balance;
 use map_core::block::Block;
@@ -8,56 +11,423 @@ use map_core::types::{Address, Hash};
 use map_core::runtime::Interpreter;
 use crate::blockchain::BlockChain;
 
+/// Limits of transaction pool
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// Max number of transactions in pool
+    pub max_txs: usize,
+    /// Max number of transactions of one sender
+    pub max_per_account: usize,
+    /// Percent of gas price bump to replace transaction with same nonce
+    pub price_bump: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_txs: 4096,
+            max_per_account: 64,
+            price_bump: 10,
+        }
+    }
+}
+
+/// Reason transaction is not admitted to pool
+#[derive(Clone, Debug, PartialEq)]
+pub enum PoolError {
+    /// Signature does not match transaction, the sender made it up
+    InvalidSignature(String),
+    KnownTransaction(Hash),
+    NonceTooLow { nonce: u64, account_nonce: u64 },
+    InsufficientFunds { balance: u128, max_fee: u128 },
+    ReplacementUnderpriced { gas_price: u64, min_price: u128 },
+    SenderLimit(Address),
+    PoolFull,
+}
+
+impl PoolError {
+    /// Whether transaction could not be valid on any state, as opposed to rejected by pool policy
+    pub fn is_invalid(&self) -> bool {
+        match self {
+            PoolError::InvalidSignature(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolError::InvalidSignature(e) => write!(f, "invalid signature {}", e),
+            PoolError::KnownTransaction(hash) => write!(f, "known transaction {}", hash),
+            PoolError::NonceTooLow { nonce, account_nonce } =>
+                write!(f, "nonce too low {}, account nonce {}", nonce, account_nonce),
+            PoolError::InsufficientFunds { balance, max_fee } =>
+                write!(f, "not sufficient funds {}, tx max fee {}", balance, max_fee),
+            PoolError::ReplacementUnderpriced { gas_price, min_price } =>
+                write!(f, "replacement underpriced {}, require {}", gas_price, min_price),
+            PoolError::SenderLimit(sender) => write!(f, "too many transactions of sender {}", sender),
+            PoolError::PoolFull => write!(f, "transaction pool is full"),
+        }
+    }
+}
+
+fn max_fee(tx: &Transaction) -> u128 {
+    tx.gas as u128 * tx.gas_price as u128
+}
+
+// Transactions of one sender, pending ones are executable in nonce order,
+// future ones wait for the nonce gap to be filled
+#[derive(Clone, Default)]
+struct SenderQueue {
+    pending: BTreeMap<u64, Transaction>,
+    future: BTreeMap<u64, Transaction>,
+}
+
+impl SenderQueue {
+    fn len(&self) -> usize {
+        self.pending.len() + self.future.len()
+    }
+
+    fn get(&self, nonce: u64) -> Option<&Transaction> {
+        self.pending.get(&nonce).or_else(|| self.future.get(&nonce))
+    }
+
+    fn remove(&mut self, nonce: u64) -> Option<Transaction> {
+        self.pending.remove(&nonce).or_else(|| self.future.remove(&nonce))
+    }
+
+    // Expected nonce of next executable transaction
+    fn next_nonce(&self, account_nonce: u64) -> u64 {
+        match self.pending.keys().next_back() {
+            Some(n) => n + 1,
+            None => account_nonce + 1,
+        }
+    }
+
+    // Move transactions without nonce gap from future to pending
+    fn promote(&mut self, account_nonce: u64) {
+        let mut next = self.next_nonce(account_nonce);
+        while let Some(tx) = self.future.remove(&next) {
+            self.pending.insert(next, tx);
+            next += 1;
+        }
+    }
+}
+
+// Candidate in best-first merge of sender queues
+struct PricedTx {
+    price: u64,
+    sender: Address,
+    nonce: u64,
+}
+
+impl PartialEq for PricedTx {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PricedTx {}
+
+impl PartialOrd for PricedTx {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PricedTx {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Higher price first, tie broken by sender to keep result stable
+        self.price.cmp(&other.price).then_with(|| other.sender.cmp(&self.sender))
+    }
+}
+
+/// Transactions indexed by sender and nonce, independent of chain state
+#[derive(Clone, Default)]
+pub struct TxQueue {
+    config: PoolConfig,
+    senders: HashMap<Address, SenderQueue>,
+    hashes: HashMap<Hash, (Address, u64)>,
+}
+
+impl TxQueue {
+    pub fn new(config: PoolConfig) -> Self {
+        TxQueue {
+            config: config,
+            senders: HashMap::new(),
+            hashes: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.hashes.contains_key(hash)
+    }
+
+    /// Insert transaction validated against sender's nonce and balance of current state
+    pub fn add(&mut self, tx: Transaction, account_nonce: u64, balance: u128) -> Result<(), PoolError> {
+        let hash = tx.hash();
+        if self.hashes.contains_key(&hash) {
+            return Err(PoolError::KnownTransaction(hash));
+        }
+        if tx.nonce <= account_nonce {
+            return Err(PoolError::NonceTooLow { nonce: tx.nonce, account_nonce: account_nonce });
+        }
+        if balance < max_fee(&tx) {
+            return Err(PoolError::InsufficientFunds { balance: balance, max_fee: max_fee(&tx) });
+        }
+
+        let sender = tx.sender;
+        let nonce = tx.nonce;
+        let queue = self.senders.entry(sender).or_insert_with(SenderQueue::default);
+        let replaced = match queue.get(nonce) {
+            Some(old) => {
+                let min_price = old.gas_price as u128 * (100 + self.config.price_bump) as u128 / 100;
+                if tx.gas_price <= old.gas_price || (tx.gas_price as u128) < min_price {
+                    return Err(PoolError::ReplacementUnderpriced { gas_price: tx.gas_price, min_price: min_price });
+                }
+                Some(old.hash())
+            }
+            None => None,
+        };
+
+        match replaced {
+            Some(old) => {
+                self.hashes.remove(&old);
+                if queue.pending.contains_key(&nonce) {
+                    queue.pending.insert(nonce, tx);
+                } else {
+                    queue.future.insert(nonce, tx);
+                }
+            }
+            None => {
+                if queue.len() >= self.config.max_per_account {
+                    return Err(PoolError::SenderLimit(sender));
+                }
+                queue.future.insert(nonce, tx);
+                queue.promote(account_nonce);
+            }
+        }
+        self.hashes.insert(hash, (sender, nonce));
+
+        while self.hashes.len() > self.config.max_txs {
+            match self.evict() {
+                Some(evicted) if evicted == hash => return Err(PoolError::PoolFull),
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    // Drop cheapest future transaction, or the cheapest tail of pending queues
+    fn evict(&mut self) -> Option<Hash> {
+        let future = self.senders.iter()
+            .flat_map(|(addr, q)| q.future.values().map(move |tx| (tx.gas_price, *addr, tx.nonce)))
+            .min_by(|a, b| a.0.cmp(&b.0));
+        let victim = match future {
+            Some(v) => Some(v),
+            // Only the last pending transaction can be dropped without opening a nonce gap
+            None => self.senders.iter()
+                .filter_map(|(addr, q)| q.pending.values().next_back().map(|tx| (tx.gas_price, *addr, tx.nonce)))
+                .min_by(|a, b| a.0.cmp(&b.0)),
+        };
+        victim.and_then(|(_, sender, nonce)| self.remove(&sender, nonce)).map(|tx| tx.hash())
+    }
+
+    fn remove(&mut self, sender: &Address, nonce: u64) -> Option<Transaction> {
+        let removed = match self.senders.get_mut(sender) {
+            Some(queue) => queue.remove(nonce),
+            None => None,
+        };
+        if let Some(ref tx) = removed {
+            self.hashes.remove(&tx.hash());
+        }
+        if self.senders.get(sender).map_or(false, |q| q.len() == 0) {
+            self.senders.remove(sender);
+        }
+        removed
+    }
+
+    pub fn remove_by_hash(&mut self, hash: &Hash) -> Option<Transaction> {
+        match self.hashes.get(hash).cloned() {
+            Some((sender, nonce)) => self.remove(&sender, nonce),
+            None => None,
+        }
+    }
+
+    /// Executable transactions ordered by gas price, nonce order kept per sender
+    pub fn pending(&self) -> Vec<Transaction> {
+        let mut heap = BinaryHeap::new();
+        for (addr, queue) in self.senders.iter() {
+            if let Some(tx) = queue.pending.values().next() {
+                heap.push(PricedTx { price: tx.gas_price, sender: *addr, nonce: tx.nonce });
+            }
+        }
+
+        let mut txs = Vec::new();
+        while let Some(best) = heap.pop() {
+            let queue = &self.senders[&best.sender];
+            txs.push(queue.pending[&best.nonce].clone());
+            if let Some(next) = queue.pending.get(&(best.nonce + 1)) {
+                heap.push(PricedTx { price: next.gas_price, sender: best.sender, nonce: next.nonce });
+            }
+        }
+        txs
+    }
+
+    /// Senders holding transactions in pool
+    pub fn senders(&self) -> Vec<Address> {
+        self.senders.keys().cloned().collect()
+    }
+
+    /// Re-check transactions of sender against new account state,
+    /// stale or unaffordable ones are dropped and the rest queued again
+    pub fn revalidate(&mut self, sender: &Address, account_nonce: u64, balance: u128) {
+        let queue = match self.senders.remove(sender) {
+            Some(q) => q,
+            None => return,
+        };
+
+        let mut kept = SenderQueue::default();
+        for (nonce, tx) in queue.pending.into_iter().chain(queue.future.into_iter()) {
+            if nonce <= account_nonce || max_fee(&tx) > balance {
+                self.hashes.remove(&tx.hash());
+            } else {
+                kept.future.insert(nonce, tx);
+            }
+        }
+        kept.promote(account_nonce);
+        if kept.len() > 0 {
+            self.senders.insert(*sender, kept);
+        }
+    }
+
+    /// Nonce of last pending transaction of sender if any
+    pub fn last_nonce(&self, sender: &Address) -> Option<u64> {
+        self.senders.get(sender).and_then(|q| q.pending.keys().next_back().cloned())
+    }
+}
+
 #[derive(Clone)]
 pub struct TxPoolManager {
-    txs: HashMap<Hash, Transaction>,
+    txs: TxQueue,
     blockchain: Arc<RwLock<BlockChain>>,
+    admitted: Option<Sender<Transaction>>,
 }
 
 impl TxPoolManager {
-    pub fn submit_txs(&mut self, tx: Transaction) {
-        match self.validate_tx(&tx) {
-            Ok(_) => self.txs.insert(tx.hash(), tx),
-            Err(e) => {
-                return println!("submit_txs {}", e.as_str());
+    /// Submit local transaction, admitted ones are sent to subscriber for broadcast
+    pub fn submit_txs(&mut self, tx: Transaction) -> Result<(), PoolError> {
+        let ret = self.add_tx(tx.clone());
+        match ret {
+            Ok(_) => {
+                if let Some(ref sender) = self.admitted {
+                    let _ = sender.send(tx);
+                }
             }
-        };
+            Err(ref e) => warn!("submit_txs {}", e),
+        }
+        ret
+    }
+
+    /// Submit transaction received from peer, propagation is left to network
+    pub fn import_tx(&mut self, tx: Transaction) -> Result<(), PoolError> {
+        self.add_tx(tx)
+    }
+
+    fn add_tx(&mut self, tx: Transaction) -> Result<(), PoolError> {
+        let (nonce, balance) = self.validate_tx(&tx)?;
+        self.txs.add(tx, nonce, balance)
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.txs.contains(hash)
+    }
+
+    /// Receive transactions admitted by local submission
+    pub fn subscribe_admitted(&mut self) -> Receiver<Transaction> {
+        let (sender, receiver) = channel();
+        self.admitted = Some(sender);
+        receiver
     }
 
     pub fn get_txs(&self) -> Vec<Transaction> {
-        self.txs.values().cloned().collect()
+        self.txs.pending()
     }
 
     pub fn notify_block(&mut self, b: &Block) {
         for tx in &b.txs {
-            self.txs.remove(&tx.hash());
+            self.txs.remove_by_hash(&tx.hash());
+        }
+        self.revalidate();
+    }
+
+    // Return transactions of dropped blocks to the pool, remove those of added blocks
+    pub fn notify_reorg(&mut self, retracted: &[Block], enacted: &[Block]) {
+        let chain = self.blockchain.read().unwrap();
+        let runtime = Balance::new(chain.interpreter_at(chain.current_block().state_root()));
+        for b in retracted {
+            for tx in &b.txs {
+                let account = runtime.get_account(tx.sender);
+                let _ = self.txs.add(tx.clone(), account.get_nonce(), account.get_balance());
+            }
         }
+        drop(chain);
+
+        for b in enacted {
+            for tx in &b.txs {
+                self.txs.remove_by_hash(&tx.hash());
+            }
+        }
+        self.revalidate();
     }
 
     pub fn start(chain: Arc<RwLock<BlockChain>>) -> TxPoolManager {
+        TxPoolManager::with_config(chain, PoolConfig::default())
+    }
+
+    pub fn with_config(chain: Arc<RwLock<BlockChain>>, config: PoolConfig) -> TxPoolManager {
         TxPoolManager {
-            txs: HashMap::new(),
+            txs: TxQueue::new(config),
             blockchain: chain,
+            admitted: None,
         }
     }
 
-    fn validate_tx(&self, tx: &Transaction) -> Result<(), String> {
+    // Check signature and return sender's nonce and balance at current state
+    fn validate_tx(&self, tx: &Transaction) -> Result<(u64, u128), PoolError> {
+        if let Err(e) = tx.verify_sign() {
+            return Err(PoolError::InvalidSignature(format!("{:?}", e)));
+        }
+
         let chain = self.blockchain.read().unwrap();
         let state = chain.state_at(chain.current_block().state_root());
         let runtime = Balance::new(Interpreter::new(state));
         let account = runtime.get_account(tx.sender);
+        Ok((account.get_nonce(), account.get_balance()))
+    }
 
-        if account.get_balance() < tx.get_value() {
-            return Err(format!("not sufficient funds {}, tx value {}", account.get_balance(), tx.get_value()));
-        }
-
-        if account.get_nonce() + 1 != tx.get_nonce() {
-            return Err(format!("invalid nonce {}, tx value {}", account.get_nonce(), tx.get_nonce()));
+    // Drop transactions made invalid by new head state
+    fn revalidate(&mut self) {
+        let chain = self.blockchain.read().unwrap();
+        let runtime = Balance::new(chain.interpreter_at(chain.current_block().state_root()));
+        let senders: HashSet<Address> = self.txs.senders().into_iter().collect();
+        for sender in senders {
+            let account = runtime.get_account(sender);
+            self.txs.revalidate(&sender, account.get_nonce(), account.get_balance());
         }
-        Ok(())
     }
 
+    /// Latest nonce of account, including pending transactions in pool
     pub fn get_nonce(&self, addr: &Address) -> u64 {
+        if let Some(nonce) = self.txs.last_nonce(addr) {
+            return nonce;
+        }
         let chain = self.blockchain.read().unwrap();
         let state = chain.state_at(chain.current_block().state_root());
         let runtime = Balance::new(Interpreter::new(state));
@@ -65,4 +435,91 @@ impl TxPoolManager {
 
         account.get_nonce()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_tx(sender: u8, nonce: u64, gas_price: u64) -> Transaction {
+        let mut addr = Address::default();
+        addr.0[0] = sender;
+        Transaction::new(addr, nonce, gas_price, 100, b"balance.transfer".to_vec(), Vec::new())
+    }
+
+    #[test]
+    fn test_pending_future() {
+        let mut queue = TxQueue::new(PoolConfig::default());
+        assert!(queue.add(make_tx(1, 3, 1), 0, 10000).is_ok());
+        assert!(queue.pending().is_empty());
+
+        assert!(queue.add(make_tx(1, 1, 1), 0, 10000).is_ok());
+        assert_eq!(queue.pending().len(), 1);
+
+        assert!(queue.add(make_tx(1, 2, 1), 0, 10000).is_ok());
+        let nonces: Vec<u64> = queue.pending().iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![1, 2, 3]);
+
+        assert_eq!(queue.add(make_tx(1, 1, 5), 1, 10000), Err(PoolError::NonceTooLow { nonce: 1, account_nonce: 1 }));
+        assert_eq!(queue.add(make_tx(2, 1, 1), 0, 10), Err(PoolError::InsufficientFunds { balance: 10, max_fee: 100 }));
+    }
+
+    #[test]
+    fn test_price_order() {
+        let mut queue = TxQueue::new(PoolConfig::default());
+        queue.add(make_tx(1, 1, 2), 0, 10000).unwrap();
+        queue.add(make_tx(1, 2, 9), 0, 10000).unwrap();
+        queue.add(make_tx(2, 1, 5), 0, 10000).unwrap();
+
+        let order: Vec<(u8, u64)> = queue.pending().iter().map(|tx| (tx.sender.0[0], tx.nonce)).collect();
+        assert_eq!(order, vec![(2, 1), (1, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn test_replace_by_fee() {
+        let mut queue = TxQueue::new(PoolConfig::default());
+        queue.add(make_tx(1, 1, 10), 0, 100000).unwrap();
+        assert!(queue.add(make_tx(1, 1, 10), 0, 100000).is_err());
+        assert!(queue.add(make_tx(1, 1, 11), 0, 100000).is_ok());
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pending()[0].gas_price, 11);
+    }
+
+    #[test]
+    fn test_limits() {
+        let config = PoolConfig {
+            max_txs: 3,
+            max_per_account: 2,
+            price_bump: 10,
+        };
+        let mut queue = TxQueue::new(config);
+        queue.add(make_tx(1, 1, 1), 0, 10000).unwrap();
+        queue.add(make_tx(1, 2, 1), 0, 10000).unwrap();
+        assert_eq!(queue.add(make_tx(1, 3, 1), 0, 10000), Err(PoolError::SenderLimit(make_tx(1, 3, 1).sender)));
+
+        queue.add(make_tx(2, 1, 5), 0, 10000).unwrap();
+        // Cheapest tail of pending queues is evicted
+        queue.add(make_tx(3, 1, 7), 0, 10000).unwrap();
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.last_nonce(&make_tx(1, 1, 1).sender), Some(1));
+
+        assert_eq!(queue.add(make_tx(4, 1, 0), 0, 10000), Err(PoolError::PoolFull));
+        assert_eq!(queue.len(), 3);
+    }
+
+    #[test]
+    fn test_revalidate() {
+        let mut queue = TxQueue::new(PoolConfig::default());
+        let sender = make_tx(1, 1, 1).sender;
+        for nonce in 1..5 {
+            queue.add(make_tx(1, nonce, 1), 0, 10000).unwrap();
+        }
+        queue.revalidate(&sender, 2, 10000);
+        let nonces: Vec<u64> = queue.pending().iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![3, 4]);
+
+        queue.revalidate(&sender, 2, 50);
+        assert_eq!(queue.len(), 0);
+        assert!(queue.senders().is_empty());
+    }
+}
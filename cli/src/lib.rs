@@ -26,6 +26,9 @@ use parking_lot::{Condvar, Mutex};
 use std::sync::mpsc;
 use ed25519::{privkey::PrivKey};
 use network::{Multiaddr};
+use chain::blockchain::BlockChain;
+use chain::snapshot;
+use chain::schema;
 
 pub fn run() {
     let matches = App::new("map")
@@ -77,8 +80,57 @@ pub fn run() {
         .arg(Arg::with_name("seal_block")
             .long("seal")
             .help("Auto generate block"))
+        .arg(Arg::with_name("pruning")
+            .long("pruning")
+            .takes_value(true)
+            .possible_values(&["archive", "fast"])
+            .default_value("archive")
+            .help("Keep state of all blocks (archive) or drop state older than pruning history (fast)"))
+        .arg(Arg::with_name("pruning_history")
+            .long("pruning-history")
+            .takes_value(true)
+            .default_value("256")
+            .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|_| format!("invalid pruning history: {}", v)))
+            .help("Number of recent blocks to keep state in fast pruning mode"))
+        .arg(Arg::with_name("db")
+            .long("db")
+            .takes_value(true)
+            .possible_values(&["memory", "rocksdb"])
+            .default_value("rocksdb")
+            .help("Database backend, memory keeps nothing on disk"))
+        .arg(Arg::with_name("state_cache")
+            .long("state-cache")
+            .takes_value(true)
+            .default_value("65536")
+            .validator(|v| v.parse::<usize>().map(|_| ()).map_err(|_| format!("invalid state cache size: {}", v)))
+            .help("Number of trie nodes and of values kept in state cache"))
         .subcommand(SubCommand::with_name("clean")
             .about("Remove the whole chain data"))
+        .subcommand(SubCommand::with_name("snapshot")
+            .about("Export or import state snapshot of a block")
+            .subcommand(SubCommand::with_name("export")
+                .about("Write state of canonical block to file")
+                .arg(Arg::with_name("block")
+                    .long("block")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Height of block to export"))
+                .arg(Arg::with_name("file")
+                    .long("file")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Snapshot file path")))
+            .subcommand(SubCommand::with_name("import")
+                .about("Bootstrap empty chain from snapshot file")
+                .arg(Arg::with_name("file")
+                    .long("file")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Snapshot file path"))))
+        .subcommand(SubCommand::with_name("db")
+            .about("Database maintenance")
+            .subcommand(SubCommand::with_name("migrate")
+                .about("Upgrade database to the current schema version")))
         .get_matches();
 
     let mut config = NodeConfig::default();
@@ -149,6 +201,26 @@ pub fn run() {
         config.seal_block = true;
     }
 
+    if let Some(pruning) = matches.value_of("pruning") {
+        config.pruning = pruning.to_string();
+    }
+    if let Some(history) = matches.value_of("pruning_history") {
+        config.pruning_history = history.parse::<u64>()
+            .map_err(|_| format!("Invalid pruning history: {}", history)).unwrap();
+    }
+    if let Some(db) = matches.value_of("db") {
+        config.db = db.to_string();
+    }
+    if let Some(size) = matches.value_of("state_cache") {
+        config.state_cache = size.parse::<usize>()
+            .map_err(|_| format!("Invalid state cache size: {}", size)).unwrap();
+    }
+
+    if let Err(e) = config.validate() {
+        println!("Invalid option: {}", e);
+        return;
+    }
+
     if matches.is_present("single") {
         println!("Run map with single node");
     }
@@ -158,6 +230,16 @@ pub fn run() {
         return;
     }
 
+    if let Some(sub) = matches.subcommand_matches("snapshot") {
+        run_snapshot(&config, sub);
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("db") {
+        run_db(&config, sub);
+        return;
+    }
+
     let exit = Arc::new((Mutex::new(()), Condvar::new()));
     let node = Service::new_service(config.clone());
     let (tx, th_handle) = node.start(config.clone());
@@ -167,6 +249,55 @@ pub fn run() {
     th_handle.join().unwrap();
 }
 
+fn run_snapshot(config: &NodeConfig, matches: &clap::ArgMatches) {
+    let mut chain = BlockChain::with_pruning(
+        config.open_db(), config.poa_privkey.clone(), config.pruning_mode().expect("invalid pruning option"));
+    if let Err(e) = chain.load() {
+        println!("Load chain failed: {}", e);
+        return;
+    }
+
+    if let Some(export) = matches.subcommand_matches("export") {
+        let height = export.value_of("block").unwrap();
+        let height = match height.parse::<u64>() {
+            Ok(h) => h,
+            Err(_) => {
+                println!("Invalid block height: {}", height);
+                return;
+            }
+        };
+        let file = PathBuf::from(export.value_of("file").unwrap());
+        match snapshot::export_snapshot(&chain, height, &file) {
+            Ok(count) => println!("Exported {} state entries of block {} to {}", count, height, file.display()),
+            Err(e) => println!("Export snapshot failed: {}", e),
+        }
+    } else if let Some(import) = matches.subcommand_matches("import") {
+        let file = PathBuf::from(import.value_of("file").unwrap());
+        match snapshot::import_snapshot(&mut chain, &file) {
+            Ok(block) => println!("Imported snapshot at block {} hash={}", block.height(), block.hash()),
+            Err(e) => println!("Import snapshot failed: {}", e),
+        }
+    } else {
+        println!("{}", matches.usage());
+    }
+}
+
+fn run_db(config: &NodeConfig, matches: &clap::ArgMatches) {
+    if matches.subcommand_matches("migrate").is_some() {
+        match schema::migrate(config.open_db()) {
+            Ok(applied) => {
+                for step in applied.iter() {
+                    println!("Applied migration: {}", step);
+                }
+                println!("Database is at schema version {}", schema::SCHEMA_VERSION);
+            }
+            Err(e) => println!("Migrate database failed: {}", e),
+        }
+    } else {
+        println!("{}", matches.usage());
+    }
+}
+
 pub fn wait_exit(exit: Arc<(Mutex<()>, Condvar)>, tx : mpsc::Sender<i32>) {
     let e = Arc::<(Mutex<()>, Condvar)>::clone(&exit);
     let _ = ctrlc::set_handler(move || {
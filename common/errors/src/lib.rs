@@ -86,6 +86,7 @@ pub enum InternalErrorKind {
     InvalidTxNonce,
     NoneSign,
     Execute,
+    OutOfGas,
     Other(String),
 }
 
@@ -22,51 +22,261 @@ pub type WriteBatch = rocksdb::WriteBatch;
 use std::path::PathBuf;
 use std::env;
 use std::io;
-use std::sync::RwLock;
-use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::collections::{BTreeMap, HashMap};
+
+/// Column family of database, each column has its own key space
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Column {
+    /// Chain head and other metadata
+    Default,
+    Headers,
+    /// Block bodies and receipts
+    Bodies,
+    /// State trie nodes and journals
+    State,
+    /// Height and transaction indexes
+    Index,
+}
+
+pub const COLUMNS: [Column; 5] = [Column::Default, Column::Headers, Column::Bodies, Column::State, Column::Index];
+
+impl Column {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Default => "default",
+            Column::Headers => "headers",
+            Column::Bodies => "bodies",
+            Column::State => "state",
+            Column::Index => "index",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchOp {
+    Put(Column, Vec<u8>, Vec<u8>),
+    Delete(Column, Vec<u8>),
+}
+
+/// Operations written to db atomically
+#[derive(Clone, Debug, Default)]
+pub struct Batch {
+    pub ops: Vec<BatchOp>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Batch::default()
+    }
+
+    pub fn put(&mut self, col: Column, key: &[u8], value: &[u8]) {
+        self.ops.push(BatchOp::Put(col, key.to_vec(), value.to_vec()));
+    }
+
+    pub fn delete(&mut self, col: Column, key: &[u8]) {
+        self.ops.push(BatchOp::Delete(col, key.to_vec()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// Writes gathered while a batch is open, None marks deletion
+#[derive(Default)]
+pub struct Overlay {
+    changes: HashMap<(Column, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl Overlay {
+    /// Gathered value of key, Some(None) if it is deleted
+    pub fn get(&self, col: Column, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        self.changes.get(&(col, key.to_vec())).cloned()
+    }
+
+    pub fn put(&mut self, col: Column, key: &[u8], value: &[u8]) {
+        self.changes.insert((col, key.to_vec()), Some(value.to_vec()));
+    }
+
+    pub fn delete(&mut self, col: Column, key: &[u8]) {
+        self.changes.insert((col, key.to_vec()), None);
+    }
+
+    pub fn extend(&mut self, batch: Batch) {
+        for op in batch.ops.into_iter() {
+            match op {
+                BatchOp::Put(col, key, value) => self.changes.insert((col, key), Some(value)),
+                BatchOp::Delete(col, key) => self.changes.insert((col, key), None),
+            };
+        }
+    }
+
+    pub fn into_batch(self) -> Batch {
+        let mut batch = Batch::new();
+        for ((col, key), value) in self.changes.into_iter() {
+            match value {
+                Some(v) => batch.ops.push(BatchOp::Put(col, key, v)),
+                None => batch.ops.push(BatchOp::Delete(col, key)),
+            }
+        }
+        batch
+    }
+}
+
+/// Key value pairs of a column in key order
+pub type KVIter<'a> = Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
 
 pub trait KVDB: Sync + Send {
-    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>>;
+    fn get(&self, col: Column, key: &[u8]) -> io::Result<Option<Vec<u8>>>;
+
+    fn put(&mut self, col: Column, key: &[u8], value: &[u8]) -> io::Result<()>;
+
+    fn remove(&mut self, col: Column, key: &[u8]) -> io::Result<()>;
 
-    fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()>;
+    /// Apply all operations of batch at once
+    fn write(&mut self, batch: Batch) -> io::Result<()>;
 
-    fn remove(&mut self, key: &[u8]) -> io::Result<()>;
+    /// Committed entries of column, writes of an open batch are not visited
+    fn iter(&self, col: Column) -> KVIter;
+
+    /// Committed entries of column with key prefix
+    fn iter_prefix(&self, col: Column, prefix: &[u8]) -> KVIter {
+        let start = prefix.to_vec();
+        let prefix = prefix.to_vec();
+        Box::new(self.iter(col)
+            .skip_while(move |(k, _)| !k.starts_with(&start))
+            .take_while(move |(k, _)| k.starts_with(&prefix)))
+    }
+
+    /// Gather following writes until the batch is committed, reads see gathered writes
+    fn begin_batch(&mut self);
+
+    /// Write gathered changes atomically
+    fn commit_batch(&mut self) -> io::Result<()>;
+
+    /// Drop gathered changes
+    fn discard_batch(&mut self);
 }
 
 #[derive(Default)]
 pub struct MemoryKV {
-    db: RwLock<HashMap<Vec<u8>, Vec<u8>>>,
+    columns: HashMap<Column, BTreeMap<Vec<u8>, Vec<u8>>>,
+    pending: Option<Overlay>,
 }
 
 impl MemoryKV {
     pub fn new() -> Self {
-        MemoryKV {
-            db: RwLock::new(HashMap::new()),
+        MemoryKV::default()
+    }
+
+    fn apply(&mut self, batch: Batch) {
+        for op in batch.ops.into_iter() {
+            match op {
+                BatchOp::Put(col, key, value) => {
+                    self.columns.entry(col).or_insert_with(BTreeMap::new).insert(key, value);
+                }
+                BatchOp::Delete(col, key) => {
+                    if let Some(column) = self.columns.get_mut(&col) {
+                        column.remove(&key);
+                    }
+                }
+            }
         }
     }
 }
 
 impl KVDB for MemoryKV {
-    fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
-        let mut db = self.db.write().unwrap();
-        db.insert(key.into(), value.into());
+    fn get(&self, col: Column, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        if let Some(value) = self.pending.as_ref().and_then(|o| o.get(col, key)) {
+            return Ok(value);
+        }
+        Ok(self.columns.get(&col).and_then(|c| c.get(key).cloned()))
+    }
+
+    fn put(&mut self, col: Column, key: &[u8], value: &[u8]) -> io::Result<()> {
+        let mut batch = Batch::new();
+        batch.put(col, key, value);
+        self.write(batch)
+    }
+
+    fn remove(&mut self, col: Column, key: &[u8]) -> io::Result<()> {
+        let mut batch = Batch::new();
+        batch.delete(col, key);
+        self.write(batch)
+    }
+
+    fn write(&mut self, batch: Batch) -> io::Result<()> {
+        match self.pending.as_mut() {
+            Some(overlay) => overlay.extend(batch),
+            None => self.apply(batch),
+        }
         Ok(())
     }
 
-    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
-        let db = self.db.read().unwrap();
-        let ret = match db.get(key) {
-            Some(v) => Some(v.clone()),
-            None => None,
-        };
-        Ok(ret)
+    fn iter(&self, col: Column) -> KVIter {
+        Box::new(self.columns.get(&col).into_iter()
+            .flat_map(|c| c.iter().map(|(k, v)| (k.clone(), v.clone()))))
+    }
+
+    fn iter_prefix(&self, col: Column, prefix: &[u8]) -> KVIter {
+        let prefix = prefix.to_vec();
+        Box::new(self.columns.get(&col).into_iter()
+            .flat_map(move |c| {
+                let prefix = prefix.clone();
+                c.range(prefix.clone()..)
+                    .take_while(move |(k, _)| k.starts_with(&prefix))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+            }))
     }
 
-    fn remove(&mut self, key: &[u8]) -> io::Result<()> {
-        let mut db = self.db.write().unwrap();
-        db.remove(key);
+    fn begin_batch(&mut self) {
+        if self.pending.is_none() {
+            self.pending = Some(Overlay::default());
+        }
+    }
+
+    fn commit_batch(&mut self) -> io::Result<()> {
+        if let Some(overlay) = self.pending.take() {
+            self.apply(overlay.into_batch());
+        }
         Ok(())
     }
+
+    fn discard_batch(&mut self) {
+        self.pending = None;
+    }
+}
+
+/// Database backend of node
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DBKind {
+    /// Nothing is written to disk, data is lost on exit
+    Memory,
+    RocksDB,
+}
+
+impl DBKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "memory" => Some(DBKind::Memory),
+            "rocksdb" => Some(DBKind::RocksDB),
+            _ => None,
+        }
+    }
+}
+
+/// Open database shared by chain and state
+pub fn open_db(kind: DBKind, cfg: Config) -> Result<Arc<RwLock<dyn KVDB>>, Error> {
+    let db: Arc<RwLock<dyn KVDB>> = match kind {
+        DBKind::Memory => Arc::new(RwLock::new(MemoryKV::new())),
+        DBKind::RocksDB => Arc::new(RwLock::new(mapdb::MapDB::open(cfg)?)),
+    };
+    Ok(db)
 }
 
 #[derive(Clone,Debug)]
@@ -93,20 +303,59 @@ impl Config {
     }
 }
 
+#[cfg(test)]
 mod tests {
-    use super::{MemoryKV, KVDB};
+    use super::{MemoryKV, KVDB, Column, Batch};
 
     #[test]
     fn test_memdb() {
         let mut db = MemoryKV::new();
 
-        db.put(b"key1", b"a").unwrap();
-        assert_eq!(db.get(b"key1").unwrap().unwrap(), b"a");
+        db.put(Column::Default, b"key1", b"a").unwrap();
+        assert_eq!(db.get(Column::Default, b"key1").unwrap().unwrap(), b"a");
+
+        db.put(Column::Default, b"key1", b"b").unwrap();
+        assert_eq!(db.get(Column::Default, b"key1").unwrap().unwrap(), b"b");
+        assert_eq!(db.get(Column::State, b"key1").unwrap(), None);
 
-        db.put(b"key1", b"b").unwrap();
-        assert_eq!(db.get(b"key1").unwrap().unwrap(), b"b");
+        db.remove(Column::Default, b"key1").unwrap();
+        assert_eq!(db.get(Column::Default, b"key1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_memdb_batch() {
+        let mut db = MemoryKV::new();
+
+        db.begin_batch();
+        db.put(Column::Headers, b"k1", b"v1").unwrap();
+        assert_eq!(db.get(Column::Headers, b"k1").unwrap().unwrap(), b"v1");
+        db.discard_batch();
+        assert_eq!(db.get(Column::Headers, b"k1").unwrap(), None);
+
+        let mut batch = Batch::new();
+        batch.put(Column::Headers, b"k1", b"v1");
+        batch.put(Column::Headers, b"k2", b"v2");
+        db.begin_batch();
+        db.write(batch).unwrap();
+        db.remove(Column::Headers, b"k2").unwrap();
+        assert!(db.iter(Column::Headers).next().is_none());
+        db.commit_batch().unwrap();
+        assert_eq!(db.get(Column::Headers, b"k1").unwrap().unwrap(), b"v1");
+        assert_eq!(db.get(Column::Headers, b"k2").unwrap(), None);
+    }
+
+    #[test]
+    fn test_memdb_iter() {
+        let mut db = MemoryKV::new();
+        db.put(Column::Index, b"a1", b"1").unwrap();
+        db.put(Column::Index, b"b2", b"2").unwrap();
+        db.put(Column::Index, b"b1", b"3").unwrap();
+        db.put(Column::Index, b"c1", b"4").unwrap();
+        db.put(Column::State, b"b3", b"5").unwrap();
 
-        db.remove(b"key1").unwrap();
-        assert_eq!(db.get(b"key1").unwrap(), None);
+        let keys: Vec<Vec<u8>> = db.iter(Column::Index).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"a1".to_vec(), b"b1".to_vec(), b"b2".to_vec(), b"c1".to_vec()]);
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = db.iter_prefix(Column::Index, b"b").collect();
+        assert_eq!(entries, vec![(b"b1".to_vec(), b"3".to_vec()), (b"b2".to_vec(), b"2".to_vec())]);
     }
 }
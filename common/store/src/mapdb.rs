@@ -16,73 +16,202 @@
 
 use std::sync::{Arc, RwLock};
 use std::io;
-use rocksdb::{DB,WriteBatch};
-use crate::{Config, KVDB};
+use rocksdb::{DB, ColumnFamily, Direction, IteratorMode, Options, WriteBatch};
+use crate::{Config, KVDB, Column, COLUMNS, Batch, BatchOp, Overlay, KVIter};
 use super::Error;
 
+fn io_error(e: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{}", e))
+}
+
+/// RocksDB with one column family of each column.
+/// Handles cloned from the same db share the open batch
+#[derive(Clone)]
 pub struct MapDB{
-    inner:     Arc<RwLock<DB>>,
+    inner:     Arc<DB>,
+    pending:   Arc<RwLock<Option<Overlay>>>,
 }
 
 impl MapDB {
     pub fn open(cfg: Config) -> Result<Self, Error> {
-        let db = DB::open_default(&cfg.path).unwrap();
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let names: Vec<&str> = COLUMNS.iter().map(|c| c.name()).collect();
+        let db = DB::open_cf(&opts, &cfg.path, &names)?;
         Ok(MapDB{
-            inner:     Arc::new(RwLock::new(db)),
+            inner:     Arc::new(db),
+            pending:   Arc::new(RwLock::new(None)),
         })
     }
 
+    fn cf(&self, col: Column) -> &ColumnFamily {
+        self.inner.cf_handle(col.name()).expect("missing column family")
+    }
+
+    pub fn put_cf(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(),Error> {
+        if let Some(overlay) = self.pending.write().unwrap().as_mut() {
+            overlay.put(col, key, value);
+            return Ok(());
+        }
+        self.inner.put_cf(self.cf(col), key, value)
+    }
+
+    pub fn get_cf(&self, col: Column, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(overlay) = self.pending.read().unwrap().as_ref() {
+            if let Some(value) = overlay.get(col, key) {
+                return value;
+            }
+        }
+        self.inner.get_cf(self.cf(col), key).unwrap().map(|v| v.to_vec())
+    }
+
+    pub fn remove_cf(&self, col: Column, key: &[u8]) -> Result<(),Error> {
+        if let Some(overlay) = self.pending.write().unwrap().as_mut() {
+            overlay.delete(col, key);
+            return Ok(());
+        }
+        self.inner.delete_cf(self.cf(col), key)
+    }
+
     pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(),Error> {
-        let db = self.inner.write().unwrap();
-        db.put(key, value)
+        self.put_cf(Column::Default, key, value)
     }
 
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        let db = self.inner.read().unwrap();
-        db.get(key).unwrap()
+        self.get_cf(Column::Default, key)
     }
 
     pub fn remove(&mut self, key: &[u8]) -> Result<(),Error> {
-        let db = self.inner.write().unwrap();
-        db.delete(key)
+        self.remove_cf(Column::Default, key)
     }
 
     pub fn exists(&self, key: &[u8]) -> Result<bool, Error> {
-        let db = self.inner.read().unwrap();
-        db.get(key)
-            .map_err(Into::into)
-            .and_then(|val| Ok(val.is_some()))
+        Ok(self.get(key).is_some())
     }
+
     pub fn write_batch(&mut self,wb :WriteBatch) -> Result<(),Error> {
-        let db = self.inner.write().unwrap();
-        db.write(wb)
+        self.inner.write(wb)
+    }
+
+    /// Write operations of batch atomically, or gather them if a batch is open
+    pub fn apply_batch(&self, batch: Batch) -> Result<(),Error> {
+        if let Some(overlay) = self.pending.write().unwrap().as_mut() {
+            overlay.extend(batch);
+            return Ok(());
+        }
+        self.write_ops(batch)
+    }
+
+    fn write_ops(&self, batch: Batch) -> Result<(),Error> {
+        let mut wb = WriteBatch::default();
+        for op in batch.ops.into_iter() {
+            match op {
+                BatchOp::Put(col, key, value) => wb.put_cf(self.cf(col), &key, &value)?,
+                BatchOp::Delete(col, key) => wb.delete_cf(self.cf(col), &key)?,
+            }
+        }
+        self.inner.write(wb)
+    }
+
+    /// Gather following writes of all handles until the batch is committed
+    pub fn begin_batch(&self) {
+        let mut pending = self.pending.write().unwrap();
+        if pending.is_none() {
+            *pending = Some(Overlay::default());
+        }
+    }
+
+    /// Write gathered changes atomically
+    pub fn commit_batch(&self) -> Result<(),Error> {
+        let overlay = match self.pending.write().unwrap().take() {
+            Some(o) => o,
+            None => return Ok(()),
+        };
+        self.write_ops(overlay.into_batch())
+    }
+
+    /// Drop gathered changes
+    pub fn discard_batch(&self) {
+        self.pending.write().unwrap().take();
+    }
+
+    fn iter_from(&self, col: Column, mode: IteratorMode) -> KVIter {
+        match self.inner.iterator_cf(self.cf(col), mode) {
+            Ok(iter) => Box::new(iter.map(|(k, v)| (k.to_vec(), v.to_vec()))),
+            Err(e) => panic!("db iterator exception {}", e),
+        }
     }
 }
 
 impl KVDB for MapDB {
-    fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
-        let db = self.inner.write().unwrap();
-        db.put(key, value).expect("db write exception");
-        Ok(())
+    fn get(&self, col: Column, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.get_cf(col, key))
+    }
+
+    fn put(&mut self, col: Column, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.put_cf(col, key, value).map_err(io_error)
     }
 
-    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
-        let db = self.inner.read().unwrap();
-        Ok(db.get(key).unwrap())
+    fn remove(&mut self, col: Column, key: &[u8]) -> io::Result<()> {
+        self.remove_cf(col, key).map_err(io_error)
     }
 
-    fn remove(&mut self, key: &[u8]) -> io::Result<()> {
-        let db = self.inner.write().unwrap();
-        db.delete(key).expect("db remove exception");
-        Ok(())
+    fn write(&mut self, batch: Batch) -> io::Result<()> {
+        self.apply_batch(batch).map_err(io_error)
+    }
+
+    fn iter(&self, col: Column) -> KVIter {
+        self.iter_from(col, IteratorMode::Start)
+    }
+
+    fn iter_prefix(&self, col: Column, prefix: &[u8]) -> KVIter {
+        let start = prefix.to_vec();
+        Box::new(self.iter_from(col, IteratorMode::From(prefix, Direction::Forward))
+            .take_while(move |(k, _)| k.starts_with(&start)))
+    }
+
+    fn begin_batch(&mut self) {
+        MapDB::begin_batch(self)
+    }
+
+    fn commit_batch(&mut self) -> io::Result<()> {
+        MapDB::commit_batch(self).map_err(io_error)
+    }
+
+    fn discard_batch(&mut self) {
+        MapDB::discard_batch(self)
     }
 }
 
+// Directory of a test db under the system temp dir, removed on drop even if the test fails
+#[cfg(test)]
+struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("map-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        TestDir(path)
+    }
+
+    fn config(&self) -> Config {
+        Config::new(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
 
 #[test]
 fn test_set_value() {
-    let cfg = Config::default();
-    let mut m = MapDB::open(cfg).unwrap();
+    let dir = TestDir::new("value");
+    let mut m = MapDB::open(dir.config()).unwrap();
 
     assert!(m.put(b"k1", b"v1111").is_ok());
 
@@ -92,4 +221,40 @@ fn test_set_value() {
     assert!(m.remove(b"k1").is_ok());
     assert!(m.get(b"k1").is_none());
     assert!(!m.exists(b"k1").unwrap());
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_batch_value() {
+    let dir = TestDir::new("batch");
+    let mut m = MapDB::open(dir.config()).unwrap();
+    let shared = m.clone();
+
+    m.begin_batch();
+    assert!(m.put(b"k2", b"v2").is_ok());
+    assert_eq!(shared.get(b"k2").unwrap(), b"v2");
+    m.discard_batch();
+    assert!(shared.get(b"k2").is_none());
+
+    m.begin_batch();
+    assert!(m.put(b"k2", b"v2").is_ok());
+    assert!(m.commit_batch().is_ok());
+    assert_eq!(shared.get(b"k2").unwrap(), b"v2");
+    assert!(m.remove(b"k2").is_ok());
+}
+
+#[test]
+fn test_column_iter() {
+    let dir = TestDir::new("columns");
+    let mut m = MapDB::open(dir.config()).unwrap();
+
+    KVDB::put(&mut m, Column::Index, b"p1", b"v1").unwrap();
+    KVDB::put(&mut m, Column::Index, b"p2", b"v2").unwrap();
+    KVDB::put(&mut m, Column::Index, b"q1", b"v3").unwrap();
+    assert!(KVDB::get(&m, Column::State, b"p1").unwrap().is_none());
+
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = m.iter_prefix(Column::Index, b"p").collect();
+    assert_eq!(entries, vec![(b"p1".to_vec(), b"v1".to_vec()), (b"p2".to_vec(), b"v2".to_vec())]);
+    for key in [b"p1", b"p2", b"q1"].iter() {
+        KVDB::remove(&mut m, Column::Index, &key[..]).unwrap();
+    }
+}
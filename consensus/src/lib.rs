@@ -40,6 +40,7 @@ pub enum ConsensusErrorKind {
     NoneSign,
     AnotherPk,
     InvalidProof,
+    MismatchSealHash,
     InvalidKey,
     NotMatchEpochID,
     NoValidatorsInEpoch,
@@ -23,7 +23,6 @@ use map_core::types::{Hash,Address};
 use map_core::genesis::{ed_genesis_priv_key,ed_genesis_pub_key};
 use ed25519::{pubkey::Pubkey,privkey::PrivKey,signature::SignatureInfo};
 use std::fmt;
-use std::cmp::Ordering;
 use errors::Error;
 
 const poa_Version: u32 = 1;
@@ -68,39 +67,25 @@ impl POA {
             None
         }
     }
-    fn is_poa_sign(&self,pk: Vec<u8>) -> bool {
-        if let Some(lpk) = self.get_local_pk() {
-            if lpk.len() == pk.len() && Ordering::Equal == lpk.cmp(&pk) {
-                return true
-            }
+    pub fn is_poa_sign(&self,pk: &[u8]) -> bool {
+        match self.get_local_pk() {
+            Some(lpk) => lpk.as_slice() == pk,
+            None => false,
         }
-        false
-    }
-    pub fn sign_block(t: u8,pkey: Option<PrivKey>,mut b: Block) -> Result<Block,Error> {
-        let h = b.get_hash();
-        match pkey {
-            Some(p) => {
-                if t == 0u8 {
-                    let h = b.get_hash();
-                    let signs = p.sign(h.to_slice())?;
-                    info!("sign block with genesis privkey, height={}, hash={}", b.height(), h);
-                    POA::add_signs_to_block(h,signs,b)
-                } else {
-                    Ok(b)
-                }
-            },
-            None => {
-                if t == 0u8 {
-                    let pkey = PrivKey::from_bytes(&ed_genesis_priv_key);
-                    let h = b.get_hash();
-                    let signs = pkey.sign(h.to_slice())?;
-                    info!("sign block with genesis privkey, height={}, hash={}", b.height(), h);
-                    POA::add_signs_to_block(h,signs,b)
-                } else {
-                    Ok(b)
-                }
-            },
+    }
+    // Validators sign the seal hash, block hash covers the signatures
+    pub fn sign_block(t: u8,pkey: Option<PrivKey>,b: Block) -> Result<Block,Error> {
+        if t != 0u8 {
+            return Ok(b);
         }
+        let p = match pkey {
+            Some(p) => p,
+            None => PrivKey::from_bytes(&ed_genesis_priv_key),
+        };
+        let h = b.seal_hash();
+        let signs = p.sign(h.to_slice())?;
+        info!("sign block, height={}, seal={}", b.height(), h);
+        POA::add_signs_to_block(h,signs,b)
     }
     fn add_signs_to_block(h:Hash,signs: SignatureInfo,mut b: Block) -> Result<Block,Error> {
         let signs = VerificationItem::new(h,signs);
@@ -121,24 +106,29 @@ impl POA {
         POA::sign_block(0u8,Some(PrivKey::from_bytes(&self.validator[..])),b)
     }
     pub fn verify(&self,b: &Block) -> Result<(),Error> {
-        let proof = b.proof_one();
-        match proof {
-            Some(&v) => {
-                let sign_info = b.sign_one();
-                match sign_info {
-                    Some(&v2) => self.poa_verify(&v,&v2),
-                    None => Err(ConsensusErrorKind::NoneSign.into()),
-                }
-            },
+        self.verify_with(b,|pk| self.is_poa_sign(pk))
+    }
+    /// Verify the seal, the proposer must pass `authorized`
+    pub fn verify_with<F: Fn(&[u8]) -> bool>(&self,b: &Block,authorized: F) -> Result<(),Error> {
+        let sign_info = match b.sign_one() {
+            Some(&v) => v,
+            None => return Err(ConsensusErrorKind::NoneSign.into()),
+        };
+        if sign_info.msg != b.seal_hash() {
+            return Err(ConsensusErrorKind::MismatchSealHash.into());
+        }
+        if b.header.sign_root != block::get_hash_from_signs(b.get_signs()) {
+            return Err(ConsensusErrorKind::MismatchSealHash.into());
+        }
+
+        match b.proof_one() {
+            // signed by the proposer in proof
+            Some(&v) => self.poa_verify(&v,&sign_info,&authorized),
             None => {
                 // get proof from genesis
                 if let Some(pk) = self.get_local_pk() {
                     let proof = BlockProof::new(0u8,pk.as_slice());
-                    let sign_info = b.sign_one();
-                    match sign_info {
-                        Some(&v2) => self.poa_verify(&proof,&v2),
-                        None => Err(ConsensusErrorKind::NoneSign.into()),
-                    }
+                    self.poa_verify(&proof,&sign_info,&authorized)
                 } else {
                     Err(ConsensusErrorKind::InvalidProof.into())
                 }
@@ -146,12 +136,11 @@ impl POA {
         }
     }
 
-    fn poa_verify(&self,proof: &BlockProof,vInfo: &VerificationItem) -> Result<(),Error> {
+    fn poa_verify<F: Fn(&[u8]) -> bool>(&self,proof: &BlockProof,vInfo: &VerificationItem,authorized: &F) -> Result<(),Error> {
         let pk0 = &mut [0u8;64];
         let t = proof.get_pk(pk0);
         if t == 0u8 {       // ed25519
-            let p_pk = pk0[0..32].to_vec();
-            if !self.is_poa_sign(p_pk) {
+            if !authorized(&pk0[0..32]) {
                 return Err(ConsensusErrorKind::AnotherPk.into());
             }
             let mut a1 = [0u8;32];
@@ -176,6 +165,7 @@ impl POA {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ConsensusError;
     use std::fmt;
     #[test]
     fn test_verify() {
@@ -193,6 +183,39 @@ mod tests {
         }
         println!("end verify");
     }
+    #[test]
+    fn test_verify_sealed_block() {
+        let f = POA::new(None);
+        let mut b = Block::default();
+        b.header.height = 1;
+        let sealed = f.finalize_block(b, Hash([1u8;32])).unwrap();
+        assert_eq!(sealed.sign_one().unwrap().msg, sealed.seal_hash());
+        assert_ne!(sealed.seal_hash(), sealed.get_hash());
+        assert!(f.verify(&sealed).is_ok());
+
+        let mut forged = sealed.clone();
+        forged.header.state_root = Hash([2u8;32]);
+        assert!(f.verify(&forged).is_err());
+    }
+
+    #[test]
+    fn test_reject_foreign_signer() {
+        let f = POA::new(None);
+        let key = [9u8;32];
+        let pk = PrivKey::from_bytes(&key).to_pubkey().unwrap().to_bytes();
+        let mut b = Block::default();
+        b.header.height = 1;
+        b.add_proof(BlockProof::new(0u8,&pk));
+        let sealed = POA::new(Some(key)).finalize_block(b, Hash([1u8;32])).unwrap();
+
+        // Signature is valid, but the signer is not the authority
+        assert!(f.verify_with(&sealed,|_| true).is_ok());
+        let e = f.verify(&sealed).unwrap_err();
+        let kind = e.downcast_ref::<ConsensusError>().map(|e| e.kind().clone());
+        assert_eq!(kind, Some(ConsensusErrorKind::AnotherPk));
+        assert!(f.verify_with(&sealed,|p| p == &pk[..]).is_ok());
+    }
+
     #[test]
     pub fn test_cmp() {
         let f = POA::new_from_string("2afa6bd56b12f68f95129addfb6a98e4d49aa423b73cec6ca160d2259c4b3d04".to_string());
@@ -0,0 +1,311 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Assets issued on chain besides the native balance, e.g. tokens wrapped from
+//! other chains. An asset is minted by its issuer and held in per account balances.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde::{Serialize, Deserialize};
+use bincode;
+use errors::{Error, InternalErrorKind};
+use crate::types::{Address, Hash};
+use crate::storage::StorageIndexedMap;
+use crate::transaction::asset_msg::{MsgCreate, MsgMint, MsgBurn, MsgTransfer};
+use crate::state::StateDB;
+use crate::runtime::Interpreter;
+
+/// Max length of asset symbol
+pub const MAX_SYMBOL_LEN: usize = 16;
+/// Max decimals of asset amount
+pub const MAX_DECIMALS: u8 = 36;
+
+const ASSETS: &[u8] = b"asset.assets";
+const BALANCES: &[u8] = b"asset.balances";
+
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssetInfo {
+    pub id: Hash,
+    pub issuer: Address,
+    pub symbol: String,
+    pub decimals: u8,
+    pub total_supply: u128,
+}
+
+pub struct Asset {
+    assets: StorageIndexedMap<Hash, AssetInfo>,
+    pub state_db: Rc<RefCell<StateDB>>,
+    pub interpreter: Interpreter,
+}
+
+impl Asset {
+    pub fn from_state(runner: Interpreter) -> Self {
+        let state_db = runner.statedb();
+        Asset {
+            assets: StorageIndexedMap::new(state_db.clone(), ASSETS),
+            state_db: state_db,
+            interpreter: runner,
+        }
+    }
+
+    /// Id of asset, unique by issuer and symbol
+    pub fn asset_id(issuer: &Address, symbol: &str) -> Hash {
+        Hash::make_hash(&bincode::serialize(&(issuer, symbol)).unwrap())
+    }
+
+    pub fn get_asset(&self, id: &Hash) -> Option<AssetInfo> {
+        self.assets.get(id)
+    }
+
+    pub fn asset_count(&self) -> u64 {
+        self.assets.len()
+    }
+
+    /// Assets in range [offset, offset + limit) of creation order
+    pub fn asset_page(&self, offset: u64, limit: u64) -> Vec<AssetInfo> {
+        self.assets.page(offset, limit)
+    }
+
+    // Non-zero balances of account by asset id, each entry is stored apart so
+    // changing one balance costs the same however many assets account holds
+    fn balances(&self, addr: &Address) -> StorageIndexedMap<Hash, u128> {
+        StorageIndexedMap::new(self.state_db.clone(), &[BALANCES, addr.as_slice()].concat())
+    }
+
+    pub fn balance_of(&self, id: &Hash, addr: &Address) -> u128 {
+        self.balances(addr).get(id).unwrap_or(0)
+    }
+
+    /// Assets held by account with its balances
+    pub fn holdings(&self, addr: &Address) -> Vec<(AssetInfo, u128)> {
+        let balances = self.balances(addr);
+        balances.keys()
+            .filter_map(|id| {
+                let balance = balances.get(&id).unwrap_or(0);
+                self.assets.get(&id).map(|info| (info, balance))
+            })
+            .collect()
+    }
+
+    fn set_balance(&mut self, id: &Hash, addr: &Address, amount: u128) {
+        let mut balances = self.balances(addr);
+        if amount == 0 {
+            balances.remove(id);
+        } else {
+            balances.insert(id, &amount);
+        }
+    }
+
+    pub fn create(&mut self, issuer: &Address, symbol: String, decimals: u8) -> Result<Hash, Error> {
+        if symbol.is_empty() || symbol.len() > MAX_SYMBOL_LEN {
+            return Err(InternalErrorKind::Other("invalid asset symbol".to_string()).into());
+        }
+        if decimals > MAX_DECIMALS {
+            return Err(InternalErrorKind::Other("asset decimals too large".to_string()).into());
+        }
+        let id = Asset::asset_id(issuer, &symbol);
+        if self.assets.contains(&id) {
+            return Err(InternalErrorKind::Other("asset exists".to_string()).into());
+        }
+        let info = AssetInfo {
+            id: id,
+            issuer: *issuer,
+            symbol: symbol,
+            decimals: decimals,
+            total_supply: 0,
+        };
+        self.assets.insert(&id, &info);
+        Ok(id)
+    }
+
+    /// Issue new amount of asset to receiver, only allowed to issuer
+    pub fn mint(&mut self, addr: &Address, id: &Hash, receiver: &Address, amount: u128) -> Result<(), Error> {
+        let mut info = match self.get_asset(id) {
+            Some(i) => i,
+            None => return Err(InternalErrorKind::Other("asset not found".to_string()).into()),
+        };
+        if info.issuer != *addr {
+            return Err(InternalErrorKind::Other("not asset issuer".to_string()).into());
+        }
+        let supply = match info.total_supply.checked_add(amount) {
+            Some(s) => s,
+            None => return Err(InternalErrorKind::Other("asset supply overflow".to_string()).into()),
+        };
+        info.total_supply = supply;
+        self.assets.insert(id, &info);
+        let balance = self.balance_of(id, receiver) + amount;
+        self.set_balance(id, receiver, balance);
+        Ok(())
+    }
+
+    /// Destroy amount of asset held by account, e.g. when it moves back to origin chain
+    pub fn burn(&mut self, addr: &Address, id: &Hash, amount: u128) -> Result<(), Error> {
+        let mut info = match self.get_asset(id) {
+            Some(i) => i,
+            None => return Err(InternalErrorKind::Other("asset not found".to_string()).into()),
+        };
+        let balance = self.balance_of(id, addr);
+        if balance < amount {
+            return Err(InternalErrorKind::BalanceNotEnough.into());
+        }
+        info.total_supply -= amount;
+        self.assets.insert(id, &info);
+        self.set_balance(id, addr, balance - amount);
+        Ok(())
+    }
+
+    pub fn transfer(&mut self, from: &Address, id: &Hash, to: &Address, amount: u128) -> Result<(), Error> {
+        if !self.assets.contains(id) {
+            return Err(InternalErrorKind::Other("asset not found".to_string()).into());
+        }
+        let balance = self.balance_of(id, from);
+        if balance < amount {
+            return Err(InternalErrorKind::BalanceNotEnough.into());
+        }
+        if from == to {
+            return Ok(());
+        }
+        self.set_balance(id, from, balance - amount);
+        let received = self.balance_of(id, to) + amount;
+        self.set_balance(id, to, received);
+        Ok(())
+    }
+
+    pub fn exec_create(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: MsgCreate = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        self.create(addr, msg.symbol, msg.decimals).map(|_| ())
+    }
+
+    pub fn exec_mint(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: MsgMint = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        self.mint(addr, &msg.asset, &msg.receiver, msg.amount)
+    }
+
+    pub fn exec_burn(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: MsgBurn = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        self.burn(addr, &msg.asset, msg.amount)
+    }
+
+    pub fn exec_transfer(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: MsgTransfer = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        self.transfer(addr, &msg.asset, &msg.receiver, msg.amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use bincode;
+    use map_store::{MemoryKV, KVDB};
+    use crate::state::{ArchiveDB, StateDB};
+    use crate::types::Address;
+    use crate::trie::NULL_ROOT;
+    use crate::runtime::Interpreter;
+    use crate::transaction::asset_msg::{MsgCreate, MsgTransfer};
+    use super::Asset;
+
+    #[test]
+    fn test_asset_lifecycle() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let runner = Interpreter::new(Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT))));
+        let (issuer, a, b) = (Address([1; 20]), Address([2; 20]), Address([3; 20]));
+
+        let mut assets = Asset::from_state(runner.clone());
+        let id = assets.create(&issuer, "WETH".to_string(), 18).unwrap();
+        assert!(assets.create(&issuer, "WETH".to_string(), 18).is_err());
+        assert!(assets.create(&issuer, "".to_string(), 18).is_err());
+        assert_eq!(assets.asset_count(), 1);
+
+        assert!(assets.mint(&a, &id, &a, 100).is_err());
+        assets.mint(&issuer, &id, &a, 100).unwrap();
+        assets.transfer(&a, &id, &b, 30).unwrap();
+        assert!(assets.transfer(&a, &id, &b, 71).is_err());
+        assets.burn(&b, &id, 10).unwrap();
+        assert!(assets.burn(&b, &id, 21).is_err());
+
+        assert_eq!(assets.balance_of(&id, &a), 70);
+        assert_eq!(assets.balance_of(&id, &b), 20);
+        assert_eq!(assets.get_asset(&id).unwrap().total_supply, 90);
+
+        let held = assets.holdings(&b);
+        assert_eq!(held.len(), 1);
+        assert_eq!((held[0].0.symbol.as_str(), held[0].1), ("WETH", 20));
+        assets.transfer(&b, &id, &a, 20).unwrap();
+        assert!(assets.holdings(&b).is_empty());
+        assert_eq!(assets.holdings(&a)[0].1, 90);
+    }
+
+    #[test]
+    fn test_asset_holdings() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let runner = Interpreter::new(Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT))));
+        let (issuer, a, b) = (Address([1; 20]), Address([2; 20]), Address([3; 20]));
+
+        let mut assets = Asset::from_state(runner.clone());
+        let ids: Vec<_> = ["A", "B", "C"].iter().map(|symbol| {
+            let id = assets.create(&issuer, symbol.to_string(), 0).unwrap();
+            assets.mint(&issuer, &id, &a, 1).unwrap();
+            id
+        }).collect();
+
+        // Emptied holding is replaced by the last one
+        assets.transfer(&a, &ids[0], &b, 1).unwrap();
+        let held: Vec<_> = assets.holdings(&a).iter().map(|(info, balance)| (info.id, *balance)).collect();
+        assert_eq!(held, vec![(ids[2], 1), (ids[1], 1)]);
+        assert_eq!(assets.balance_of(&ids[0], &a), 0);
+        assert_eq!(assets.holdings(&b)[0].0.id, ids[0]);
+
+        assets.burn(&a, &ids[2], 1).unwrap();
+        assets.burn(&a, &ids[1], 1).unwrap();
+        assert!(assets.holdings(&a).is_empty());
+    }
+
+    #[test]
+    fn test_asset_call() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let mut runner = Interpreter::new(Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT))));
+        let (issuer, a) = (Address([1; 20]), Address([2; 20]));
+
+        let create = MsgCreate { symbol: "WBTC".to_string(), decimals: 8 };
+        runner.call(&issuer, b"asset.create".to_vec(), bincode::serialize(&create).unwrap()).unwrap();
+        let id = Asset::asset_id(&issuer, "WBTC");
+        Asset::from_state(runner.clone()).mint(&issuer, &id, &issuer, 5).unwrap();
+
+        let transfer = MsgTransfer { asset: id, receiver: a, amount: 2 };
+        runner.call(&issuer, b"asset.transfer".to_vec(), bincode::serialize(&transfer).unwrap()).unwrap();
+        assert!(runner.call(&issuer, b"asset.unknown".to_vec(), Vec::new()).is_err());
+        assert_eq!(Asset::from_state(runner.clone()).balance_of(&id, &a), 2);
+    }
+}
@@ -18,15 +18,15 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use serde::{Serialize, Deserialize};
 use bincode;
-use hash;
+use errors::{Error, InternalErrorKind};
 use crate::types::{Hash, Address};
 use crate::state::StateDB;
+use crate::storage::{self, StorageMap};
 use crate::trie::NULL_ROOT;
 use crate::transaction;
 use crate::runtime::Interpreter;
 
-const BALANCE_POS: u64 = 1;
-const NONCE_POS: u64 = 2;
+const ACCOUNTS: &[u8] = b"balance.accounts";
 
 #[derive(Serialize, Deserialize)]
 #[derive(Default, Copy, Clone, Debug, PartialEq)]
@@ -51,6 +51,7 @@ impl Account {
 #[allow(dead_code)]
 pub struct Balance {
     // cache: HashMap<Hash, Account>,
+    accounts: StorageMap<Address, Account>,
     treedb: Rc<RefCell<StateDB>>,
     interpreter: Interpreter,
     root_hash: Hash,
@@ -60,6 +61,7 @@ impl Balance {
     pub fn new(runner: Interpreter) -> Self {
         Balance {
             // cache: HashMap::new(),
+            accounts: StorageMap::new(runner.statedb(), ACCOUNTS),
             treedb: runner.statedb(),
             interpreter: runner,
             root_hash: NULL_ROOT,
@@ -69,6 +71,7 @@ impl Balance {
     pub fn from_state(runner: Interpreter) -> Self {
         Balance {
             // cache: HashMap::new(),
+            accounts: StorageMap::new(runner.statedb(), ACCOUNTS),
             treedb: runner.statedb(),
             interpreter: runner,
             root_hash: NULL_ROOT,
@@ -202,9 +205,16 @@ impl Balance {
         }
     }
 
-    pub fn exec_transfer(&mut self, from_addr: Address, input: Vec<u8>) {
-        let msg: transaction::balance_msg::MsgTransfer = bincode::deserialize(&input).unwrap();
+    pub fn exec_transfer(&mut self, from_addr: Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: transaction::balance_msg::MsgTransfer = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        if self.balance(from_addr) < msg.value {
+            return Err(InternalErrorKind::BalanceNotEnough.into());
+        }
         self.transfer(from_addr, msg.receiver, msg.value);
+        Ok(())
     }
 
     pub fn commit(&mut self) -> Hash {
@@ -219,22 +229,11 @@ impl Balance {
     }
 
     pub fn load_account(&self, addr: Address) -> Account {
-        // let serialized = match self.cache.get(&Self::address_key(addr)) {
-        //     Some(s) => s,
-        //     None => return Account::default(),
-        // };
-        let serialized = match self.treedb.borrow().get_storage(&Self::address_key(addr)) {
-            Some(s) => s,
-            None => return Account::default(),
-        };
-
-        let obj: Account = bincode::deserialize(&serialized).unwrap();
-        obj
+        self.accounts.get(&addr).unwrap_or_default()
     }
 
     pub fn set_account(&mut self, addr: Address, account: &Account) {
-        let encoded: Vec<u8> = bincode::serialize(account).unwrap();
-        self.treedb.borrow_mut().set_storage(Self::address_key(addr), &encoded);
+        self.accounts.insert(&addr, account);
     }
 
     pub fn load_root(&mut self, root: Hash) {
@@ -243,36 +242,7 @@ impl Balance {
 
     /// Storage hash key of account
     pub fn address_key(addr: Address) -> Hash {
-        let h = Hash::from_bytes(addr.as_slice());
-        Hash(hash::blake2b_256(&h.to_slice()))
-    }
-
-    /// Storage hash key of account balance
-    pub fn balance_key(addr: Address) -> Hash {
-        let mut raw = vec![];
-        {
-            let h = Hash::from_bytes(addr.as_slice());
-            raw.extend_from_slice(h.to_slice());
-        }
-        {
-            let h = Hash::from_bytes(&BALANCE_POS.to_be_bytes()[..]);
-            raw.extend_from_slice(h.to_slice());
-        }
-        Hash(hash::blake2b_256(&raw))
-    }
-
-    /// Storage hash key of account nonce
-    pub fn nonce_key(addr: Address) -> Hash {
-        let mut raw = vec![];
-        {
-            let h = Hash::from_bytes(addr.as_slice());
-            raw.extend_from_slice(h.to_slice());
-        }
-        {
-            let h = Hash::from_bytes(&NONCE_POS.to_be_bytes()[..]);
-            raw.extend_from_slice(h.to_slice());
-        }
-        Hash(hash::blake2b_256(&raw))
+        storage::map_key(ACCOUNTS, &addr)
     }
 }
 
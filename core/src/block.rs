@@ -25,6 +25,10 @@ use ed25519::{signature::SignatureInfo,Message,pubkey::Pubkey};
 // use hash;
 use bincode;
 
+/// Max number of transactions in block
+pub const MAX_BLOCK_TXS: usize = 512;
+/// Max total gas of transactions in block
+pub const BLOCK_GAS_LIMIT: u64 = 1_000_000;
 
 /// Block header
 #[derive(Serialize, Deserialize, Debug,PartialEq, Eq, Hash)]
@@ -56,6 +60,13 @@ impl Header {
         let encoded: Vec<u8> = bincode::serialize(&self).unwrap();
         Hash(hash::blake2b_256(encoded))
     }
+
+    /// Hash of header without seal fields, the message signed by validators
+    pub fn seal_hash(&self) -> Hash {
+        let mut unsealed = self.clone();
+        unsealed.sign_root = Hash::default();
+        unsealed.hash()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -110,13 +121,135 @@ impl BlockProof {
     }
 }
 
+const MERKLE_LEAF_PREFIX: u8 = 0;
+const MERKLE_NODE_PREFIX: u8 = 1;
+const MERKLE_ROOT_PREFIX: u8 = 2;
+
+/// Inclusion proof of an item in binary merkle tree
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Position of the item
+    pub index: u32,
+    /// Number of items in tree
+    pub leaves: u32,
+    /// Sibling hashes from the leaf up to root
+    pub path: Vec<Hash>,
+}
+
+pub fn merkle_leaf(data: &[u8]) -> Hash {
+    let mut raw = Vec::with_capacity(data.len() + 1);
+    raw.push(MERKLE_LEAF_PREFIX);
+    raw.extend_from_slice(data);
+    Hash(hash::blake2b_256(&raw))
+}
+
+fn merkle_node(left: &Hash, right: &Hash) -> Hash {
+    let mut raw = Vec::with_capacity(65);
+    raw.push(MERKLE_NODE_PREFIX);
+    raw.extend_from_slice(left.as_bytes());
+    raw.extend_from_slice(right.as_bytes());
+    Hash(hash::blake2b_256(&raw))
+}
+
+// Root commits to number of leaves as well, so index and leaves of a proof
+// could not be reshaped into another position over the same tree
+fn merkle_commit(leaves: u32, tree_root: &Hash) -> Hash {
+    let mut raw = Vec::with_capacity(37);
+    raw.push(MERKLE_ROOT_PREFIX);
+    raw.extend_from_slice(&leaves.to_be_bytes());
+    raw.extend_from_slice(tree_root.as_bytes());
+    Hash(hash::blake2b_256(&raw))
+}
+
+// Hash pairs of nodes, the odd node of a level is promoted as is
+fn merkle_level(nodes: &[Hash]) -> Vec<Hash> {
+    nodes.chunks(2).map(|pair| {
+        if pair.len() == 2 {
+            merkle_node(&pair[0], &pair[1])
+        } else {
+            pair[0]
+        }
+    }).collect()
+}
+
+/// Root of binary merkle tree built on leaf hashes along with their number,
+/// empty tree has zero root
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return Hash::default();
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = merkle_level(&level);
+    }
+    merkle_commit(leaves.len() as u32, &level[0])
+}
+
+pub fn merkle_proof(leaves: &[Hash], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut path = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut pos = index;
+    while level.len() > 1 {
+        let sibling = pos ^ 1;
+        if sibling < level.len() {
+            path.push(level[sibling]);
+        }
+        level = merkle_level(&level);
+        pos /= 2;
+    }
+    Some(MerkleProof {
+        index: index as u32,
+        leaves: leaves.len() as u32,
+        path: path,
+    })
+}
+
+pub fn verify_merkle_proof(root: &Hash, leaf: &Hash, proof: &MerkleProof) -> bool {
+    if proof.index >= proof.leaves {
+        return false;
+    }
+    let mut path = proof.path.iter();
+    let mut node = *leaf;
+    let mut pos = proof.index;
+    let mut width = proof.leaves;
+    while width > 1 {
+        if pos % 2 == 1 {
+            node = match path.next() {
+                Some(h) => merkle_node(h, &node),
+                None => return false,
+            };
+        } else if pos + 1 < width {
+            node = match path.next() {
+                Some(h) => merkle_node(&node, h),
+                None => return false,
+            };
+        }
+        pos /= 2;
+        width = (width + 1) / 2;
+    }
+    path.next().is_none() && merkle_commit(proof.leaves, &node) == *root
+}
+
+fn tx_leaves(txs: &Vec<Transaction>) -> Vec<Hash> {
+    txs.iter().map(|tx| merkle_leaf(&bincode::serialize(tx).unwrap())).collect()
+}
+
 pub fn get_hash_from_txs(txs: &Vec<Transaction>) -> Hash {
-    let data = bincode::serialize(txs).unwrap();
-    Hash(hash::blake2b_256(data))
+    merkle_root(&tx_leaves(txs))
 }
 pub fn get_hash_from_signs(signs: Vec<VerificationItem>) -> Hash {
-    let data = bincode::serialize(&signs).unwrap();
-    Hash(hash::blake2b_256(data))
+    let leaves: Vec<Hash> = signs.iter().map(|s| merkle_leaf(&bincode::serialize(s).unwrap())).collect();
+    merkle_root(&leaves)
+}
+
+/// Check transaction is included in block of the tx_root
+pub fn verify_tx_proof(tx_root: &Hash, tx: &Transaction, proof: &MerkleProof) -> bool {
+    let leaf = merkle_leaf(&bincode::serialize(tx).unwrap());
+    verify_merkle_proof(tx_root, &leaf, proof)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize,PartialEq, Eq, Hash)]
@@ -168,6 +301,9 @@ impl  Block {
     pub fn get_hash(&self) -> Hash {
        self.header.hash()
     }
+    pub fn seal_hash(&self) -> Hash {
+        self.header.seal_hash()
+    }
     pub fn add_proof(&mut self,proof: BlockProof) {
         self.proofs.push(proof);
     }
@@ -189,6 +325,10 @@ impl  Block {
     pub fn get_txs(&self) -> &Vec<Transaction> {
         &self.txs
     }
+    /// Inclusion proof of transaction at index against tx_root
+    pub fn tx_proof(&self, index: usize) -> Option<MerkleProof> {
+        merkle_proof(&tx_leaves(&self.txs), index)
+    }
 }
 
 pub fn is_equal_hash(hash1: Option<Hash>,hash2: Option<Hash>) -> bool {
@@ -207,6 +347,69 @@ mod tests {
         assert_eq!(encoded, vec![0; 48]);
     }
 
+    #[test]
+    fn test_seal_hash() {
+        let mut header = Header::default();
+        header.height = 1;
+        let seal = header.seal_hash();
+        let hash = header.hash();
+
+        header.sign_root = Hash([1u8; 32]);
+        assert_eq!(header.seal_hash(), seal);
+        assert_ne!(header.hash(), hash);
+
+        header.state_root = Hash([2u8; 32]);
+        assert_ne!(header.seal_hash(), seal);
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        for n in 1..10u8 {
+            let leaves: Vec<Hash> = (0..n).map(|i| merkle_leaf(&[i])).collect();
+            let root = merkle_root(&leaves);
+            for i in 0..n as usize {
+                let proof = merkle_proof(&leaves, i).unwrap();
+                assert!(verify_merkle_proof(&root, &leaves[i], &proof));
+                assert!(!verify_merkle_proof(&root, &merkle_leaf(&[100]), &proof));
+            }
+            assert!(merkle_proof(&leaves, n as usize).is_none());
+        }
+    }
+
+    #[test]
+    fn test_merkle_proof_shape() {
+        let leaves: Vec<Hash> = (0..3u8).map(|i| merkle_leaf(&[i])).collect();
+        let root = merkle_root(&leaves);
+        let proof = merkle_proof(&leaves, 2).unwrap();
+        assert_eq!(proof.path, vec![merkle_node(&leaves[0], &leaves[1])]);
+        assert!(verify_merkle_proof(&root, &leaves[2], &proof));
+
+        // Same path read as the second of two leaves hashes to the same tree
+        let reshaped = MerkleProof {
+            index: 1,
+            leaves: 2,
+            path: proof.path.clone(),
+        };
+        assert!(!verify_merkle_proof(&root, &leaves[2], &reshaped));
+        assert!(!verify_merkle_proof(&root, &leaves[2], &MerkleProof { leaves: 4, ..proof }));
+    }
+
+    #[test]
+    fn test_tx_proof() {
+        let txs: Vec<Transaction> = (1..6u64).map(|nonce| {
+            Transaction::new(Address::default(), nonce, 1, 1000, b"balance.transfer".to_vec(), Vec::new())
+        }).collect();
+        let block = Block::new(Header::default(), txs.clone(), Vec::new(), Vec::new());
+
+        let proof = block.tx_proof(3).unwrap();
+        assert!(verify_tx_proof(&block.header.tx_root, &txs[3], &proof));
+        assert!(!verify_tx_proof(&block.header.tx_root, &txs[2], &proof));
+
+        let mut forged = proof.clone();
+        forged.path.pop();
+        assert!(!verify_tx_proof(&block.header.tx_root, &txs[3], &forged));
+    }
+
     #[test]
     fn test_encode_option() {
         // The object that we will serialize.
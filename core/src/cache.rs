@@ -0,0 +1,169 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Cache of bounded number of entries, the least recently used entry is evicted first
+pub struct LruCache<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+    // Last use tick of each key, oldest first
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity: capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Value of key, marking it as most recently used
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let tick = self.next_tick();
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                self.order.remove(&entry.1);
+                entry.1 = tick;
+                self.order.insert(tick, key.clone());
+                Some(&entry.0)
+            }
+            None => None,
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.next_tick();
+        if let Some((_, old)) = self.entries.insert(key.clone(), (value, tick)) {
+            self.order.remove(&old);
+        }
+        self.order.insert(tick, key);
+        self.evict();
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, tick) = self.entries.remove(key)?;
+        self.order.remove(&tick);
+        Some(value)
+    }
+
+    /// Keep only the entries of keys matching predicate
+    pub fn retain<F: FnMut(&K) -> bool>(&mut self, mut keep: F) {
+        let order = &mut self.order;
+        self.entries.retain(|key, entry| {
+            let kept = keep(key);
+            if !kept {
+                order.remove(&entry.1);
+            }
+            kept
+        });
+    }
+
+    /// Change capacity, evicting entries over it
+    pub fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(t) => *t,
+                None => return,
+            };
+            if let Some(key) = self.order.remove(&oldest) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LruCache;
+
+    #[test]
+    fn test_lru_evict() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(&1), Some(&"a"));
+        cache.insert(3, "c");
+        assert!(cache.contains(&1));
+        assert!(!cache.contains(&2));
+        assert!(cache.contains(&3));
+
+        cache.insert(1, "d");
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), Some(&"d"));
+        cache.resize(1);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains(&1));
+        assert_eq!(cache.remove(&1), Some("d"));
+        assert!(cache.is_empty());
+
+        cache.resize(3);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        cache.insert(3, "c");
+        cache.retain(|k| k % 2 == 1);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(&2));
+        // Retained entries keep their order
+        cache.insert(4, "d");
+        cache.insert(5, "e");
+        assert!(!cache.contains(&1));
+        assert!(cache.contains(&3) && cache.contains(&4) && cache.contains(&5));
+
+        let mut none = LruCache::new(0);
+        none.insert(1, 1);
+        assert!(none.get(&1).is_none());
+    }
+}
@@ -0,0 +1,518 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use bincode;
+use hash;
+use parity_wasm::elements::{self, MemoryType};
+use pwasm_utils::{self, rules};
+use wasmi::{self, Externals, FuncInstance, FuncRef, HostError, ImportsBuilder, MemoryRef,
+    ModuleImportResolver, ModuleInstance, RuntimeArgs, RuntimeValue, Signature, Trap, TrapKind, ValueType};
+use errors::{Error, InternalErrorKind};
+use crate::types::{Hash, Address};
+use crate::state::StateDB;
+use crate::storage::{self, StorageMap};
+use crate::balance::Balance;
+use crate::runtime::Interpreter;
+use crate::transaction::contract_msg::{MsgDeploy, MsgCall};
+
+const CODES: &[u8] = b"contract.codes";
+const SLOTS: &[u8] = b"contract.slots";
+
+/// Linear memory limit of contract, in 64KiB pages
+pub const MAX_MEMORY_PAGES: u32 = 16;
+/// Stack height limit of contract execution
+pub const MAX_STACK_HEIGHT: u32 = 64 * 1024;
+
+// Indexes of host functions
+const GAS_FUNC: usize = 0;
+const STORAGE_READ_FUNC: usize = 1;
+const STORAGE_WRITE_FUNC: usize = 2;
+const BALANCE_FUNC: usize = 3;
+const TRANSFER_FUNC: usize = 4;
+const CALLER_FUNC: usize = 5;
+const ADDRESS_FUNC: usize = 6;
+const VALUE_FUNC: usize = 7;
+const INPUT_LENGTH_FUNC: usize = 8;
+const FETCH_INPUT_FUNC: usize = 9;
+
+const I32: ValueType = ValueType::I32;
+
+#[derive(Debug)]
+struct ContractTrap(&'static str);
+
+impl fmt::Display for ContractTrap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl HostError for ContractTrap {}
+
+fn trap(reason: &'static str) -> Trap {
+    Trap::new(TrapKind::Host(Box::new(ContractTrap(reason))))
+}
+
+/// Host functions imported by contract from module "env"
+struct Resolver;
+
+impl ModuleImportResolver for Resolver {
+    fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, wasmi::Error> {
+        let (index, params, ret): (usize, &'static [ValueType], Option<ValueType>) = match field_name {
+            "gas" => (GAS_FUNC, &[I32], None),
+            "storage_read" => (STORAGE_READ_FUNC, &[I32, I32], None),
+            "storage_write" => (STORAGE_WRITE_FUNC, &[I32, I32], None),
+            "balance" => (BALANCE_FUNC, &[I32, I32], None),
+            "transfer" => (TRANSFER_FUNC, &[I32, I32], Some(I32)),
+            "caller" => (CALLER_FUNC, &[I32], None),
+            "address" => (ADDRESS_FUNC, &[I32], None),
+            "value" => (VALUE_FUNC, &[I32], None),
+            "input_length" => (INPUT_LENGTH_FUNC, &[], Some(I32)),
+            "fetch_input" => (FETCH_INPUT_FUNC, &[I32], None),
+            _ => return Err(wasmi::Error::Instantiation(format!("unknown host function {}", field_name))),
+        };
+        let expected = Signature::new(params, ret);
+        if signature != &expected {
+            return Err(wasmi::Error::Instantiation(format!("invalid signature of {}", field_name)));
+        }
+        Ok(FuncInstance::alloc_host(expected, index))
+    }
+}
+
+/// Execution context of a contract invocation.
+/// Addresses are 20 bytes, storage keys and values 32 bytes, balances 16 bytes little endian.
+struct Runtime {
+    interpreter: Interpreter,
+    state_db: Rc<RefCell<StateDB>>,
+    memory: Option<MemoryRef>,
+    address: Address,
+    caller: Address,
+    value: u128,
+    input: Vec<u8>,
+}
+
+impl Runtime {
+    fn charge(&self, amount: u64) -> Result<(), Trap> {
+        self.interpreter.charge_gas(amount).map_err(|_| trap("out of gas"))
+    }
+
+    fn read(&self, ptr: u32, buf: &mut [u8]) -> Result<(), Trap> {
+        match self.memory {
+            Some(ref m) => m.get_into(ptr, buf).map_err(|_| trap("memory access out of bounds")),
+            None => Err(trap("contract has no memory")),
+        }
+    }
+
+    fn write(&self, ptr: u32, buf: &[u8]) -> Result<(), Trap> {
+        match self.memory {
+            Some(ref m) => m.set(ptr, buf).map_err(|_| trap("memory access out of bounds")),
+            None => Err(trap("contract has no memory")),
+        }
+    }
+
+    fn read_hash(&self, ptr: u32) -> Result<Hash, Trap> {
+        let mut h = Hash::default();
+        self.read(ptr, &mut h.0)?;
+        Ok(h)
+    }
+
+    fn read_address(&self, ptr: u32) -> Result<Address, Trap> {
+        let mut addr = Address::default();
+        self.read(ptr, &mut addr.0)?;
+        Ok(addr)
+    }
+
+    fn read_value(&self, ptr: u32) -> Result<u128, Trap> {
+        let mut buf = [0u8; 16];
+        self.read(ptr, &mut buf)?;
+        Ok(u128::from_le_bytes(buf))
+    }
+}
+
+impl Externals for Runtime {
+    fn invoke_index(&mut self, index: usize, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
+        match index {
+            GAS_FUNC => {
+                let amount: u32 = args.nth_checked(0)?;
+                self.charge(amount as u64)?;
+                Ok(None)
+            }
+            STORAGE_READ_FUNC => {
+                self.charge(self.interpreter.schedule().storage_read)?;
+                let key = self.read_hash(args.nth_checked(0)?)?;
+                let value = Contract::load_storage(&self.state_db, &self.address, &key);
+                self.write(args.nth_checked(1)?, &value.0)?;
+                Ok(None)
+            }
+            STORAGE_WRITE_FUNC => {
+                self.charge(self.interpreter.schedule().storage_write)?;
+                let key = self.read_hash(args.nth_checked(0)?)?;
+                let value = self.read_hash(args.nth_checked(1)?)?;
+                Contract::store_storage(&self.state_db, &self.address, &key, &value);
+                Ok(None)
+            }
+            BALANCE_FUNC => {
+                self.charge(self.interpreter.schedule().storage_read)?;
+                let addr = self.read_address(args.nth_checked(0)?)?;
+                let balance = Balance::from_state(self.interpreter.clone()).balance(addr);
+                self.write(args.nth_checked(1)?, &balance.to_le_bytes())?;
+                Ok(None)
+            }
+            TRANSFER_FUNC => {
+                self.charge(self.interpreter.schedule().transfer)?;
+                let to = self.read_address(args.nth_checked(0)?)?;
+                let value = self.read_value(args.nth_checked(1)?)?;
+                let mut state = Balance::from_state(self.interpreter.clone());
+                if state.balance(self.address) < value {
+                    return Ok(Some(RuntimeValue::I32(1)));
+                }
+                state.transfer(self.address, to, value);
+                Ok(Some(RuntimeValue::I32(0)))
+            }
+            CALLER_FUNC => {
+                self.write(args.nth_checked(0)?, &self.caller.0)?;
+                Ok(None)
+            }
+            ADDRESS_FUNC => {
+                self.write(args.nth_checked(0)?, &self.address.0)?;
+                Ok(None)
+            }
+            VALUE_FUNC => {
+                self.write(args.nth_checked(0)?, &self.value.to_le_bytes())?;
+                Ok(None)
+            }
+            INPUT_LENGTH_FUNC => Ok(Some(RuntimeValue::I32(self.input.len() as i32))),
+            FETCH_INPUT_FUNC => {
+                self.write(args.nth_checked(0)?, &self.input)?;
+                Ok(None)
+            }
+            _ => Err(trap("unknown host function")),
+        }
+    }
+}
+
+/// WASM contracts, the code and storage of each contract live in state of its address
+pub struct Contract {
+    state_db: Rc<RefCell<StateDB>>,
+    interpreter: Interpreter,
+}
+
+impl Contract {
+    pub fn from_state(runner: Interpreter) -> Self {
+        Contract {
+            state_db: runner.statedb(),
+            interpreter: runner,
+        }
+    }
+
+    /// Address of contract deployed by sender with transaction nonce
+    pub fn contract_address(sender: &Address, nonce: u64) -> Address {
+        let mut raw = sender.as_slice().to_vec();
+        raw.extend_from_slice(&nonce.to_be_bytes());
+        let mut addr = Address::default();
+        addr.0.copy_from_slice(&(hash::blake2b_256(&raw)[12..]));
+        addr
+    }
+
+    /// Storage hash key of contract code
+    pub fn code_key(addr: &Address) -> Hash {
+        storage::map_key(CODES, addr)
+    }
+
+    /// Storage hash key of contract storage slot
+    pub fn storage_key(addr: &Address, key: &Hash) -> Hash {
+        storage::map_key(SLOTS, &(*addr, *key))
+    }
+
+    fn codes(state_db: &Rc<RefCell<StateDB>>) -> StorageMap<Address, Vec<u8>> {
+        StorageMap::new(state_db.clone(), CODES)
+    }
+
+    fn slots(state_db: &Rc<RefCell<StateDB>>) -> StorageMap<(Address, Hash), Hash> {
+        StorageMap::new(state_db.clone(), SLOTS)
+    }
+
+    pub fn code(&self, addr: &Address) -> Option<Vec<u8>> {
+        Self::codes(&self.state_db).get(addr)
+    }
+
+    /// Value of contract storage slot, zero if not set
+    pub fn storage(&self, addr: &Address, key: &Hash) -> Hash {
+        Self::load_storage(&self.state_db, addr, key)
+    }
+
+    fn load_storage(state_db: &Rc<RefCell<StateDB>>, addr: &Address, key: &Hash) -> Hash {
+        Self::slots(state_db).get(&(*addr, *key)).unwrap_or_default()
+    }
+
+    // Zero value clears the slot
+    fn store_storage(state_db: &Rc<RefCell<StateDB>>, addr: &Address, key: &Hash, value: &Hash) {
+        let mut slots = Self::slots(state_db);
+        if *value == Hash::default() {
+            slots.remove(&(*addr, *key));
+        } else {
+            slots.insert(&(*addr, *key), value);
+        }
+    }
+
+    /// Validate module and instrument it with gas metering and stack limit
+    pub fn prepare_code(code: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut module = elements::deserialize_buffer::<elements::Module>(code)
+            .map_err(|e| InternalErrorKind::Other(format!("invalid wasm {:?}", e)))?;
+        if module.start_section().is_some() {
+            return Err(InternalErrorKind::Other("start function is not allowed".to_string()).into());
+        }
+        if let Some(section) = module.memory_section_mut() {
+            for entry in section.entries_mut().iter_mut() {
+                let initial = entry.limits().initial();
+                if initial > MAX_MEMORY_PAGES {
+                    return Err(InternalErrorKind::Other("memory exceeds limit".to_string()).into());
+                }
+                *entry = MemoryType::new(initial, Some(MAX_MEMORY_PAGES));
+            }
+        }
+
+        // Float instructions are rejected for deterministic execution
+        let rules = rules::Set::default().with_forbidden_floats();
+        let module = pwasm_utils::inject_gas_counter(module, &rules)
+            .map_err(|_| InternalErrorKind::Other("gas metering failed".to_string()))?;
+        let module = pwasm_utils::stack_height::inject_limiter(module, MAX_STACK_HEIGHT)
+            .map_err(|e| InternalErrorKind::Other(format!("stack limiter failed {:?}", e)))?;
+        let prepared = elements::serialize(module)
+            .map_err(|e| InternalErrorKind::Other(format!("serialize wasm {:?}", e)))?;
+        wasmi::Module::from_buffer(&prepared)
+            .map_err(|e| InternalErrorKind::Other(format!("invalid wasm {:?}", e)))?;
+        Ok(prepared)
+    }
+
+    // Invoke export of contract, a missing optional export is a no-op
+    fn run(&mut self, addr: Address, caller: Address, value: u128, input: Vec<u8>,
+        export: &str, optional: bool) -> Result<(), Error> {
+        let code = match self.code(&addr) {
+            Some(c) => c,
+            None => return Err(InternalErrorKind::Execute.into()),
+        };
+        let module = wasmi::Module::from_buffer(&code)
+            .map_err(|e| InternalErrorKind::Other(format!("invalid wasm {:?}", e)))?;
+        let mut runtime = Runtime {
+            interpreter: self.interpreter.clone(),
+            state_db: self.state_db.clone(),
+            memory: None,
+            address: addr,
+            caller: caller,
+            value: value,
+            input: input,
+        };
+        let instance = ModuleInstance::new(&module, &ImportsBuilder::new().with_resolver("env", &Resolver))
+            .map_err(|e| InternalErrorKind::Other(format!("instantiate contract {:?}", e)))?
+            .run_start(&mut runtime)
+            .map_err(|e| InternalErrorKind::Other(format!("instantiate contract {:?}", e)))?;
+        if instance.export_by_name(export).is_none() {
+            if optional {
+                return Ok(());
+            }
+            return Err(InternalErrorKind::Other(format!("contract has no {} export", export)).into());
+        }
+        runtime.memory = instance.export_by_name("memory").and_then(|e| e.as_memory().cloned());
+
+        if let Err(e) = instance.invoke_export(export, &[], &mut runtime) {
+            debug!("contract {} trapped, {:?}", addr, e);
+            return Err(InternalErrorKind::Execute.into());
+        }
+        Ok(())
+    }
+
+    fn transfer_value(&mut self, from: Address, to: Address, value: u128) -> Result<(), Error> {
+        if value == 0 {
+            return Ok(());
+        }
+        let mut state = Balance::from_state(self.interpreter.clone());
+        if state.balance(from) < value {
+            return Err(InternalErrorKind::BalanceNotEnough.into());
+        }
+        state.transfer(from, to, value);
+        Ok(())
+    }
+
+    /// Store contract code at address derived from caller nonce, then run its deploy export
+    pub fn exec_deploy(&mut self, caller: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: MsgDeploy = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        let byte_cost = self.interpreter.schedule().code_byte.saturating_mul(msg.code.len() as u64);
+        self.interpreter.charge_gas(byte_cost)?;
+
+        let code = Self::prepare_code(&msg.code)?;
+        let nonce = Balance::from_state(self.interpreter.clone()).nonce(*caller);
+        let addr = Self::contract_address(caller, nonce);
+        if self.code(&addr).is_some() {
+            return Err(InternalErrorKind::Other(format!("contract {} exists", addr)).into());
+        }
+        Self::codes(&self.state_db).insert(&addr, &code);
+        self.transfer_value(*caller, addr, msg.value)?;
+        info!("deploy contract {} by {}", addr, caller);
+        self.run(addr, *caller, msg.value, msg.input, "deploy", true)
+    }
+
+    /// Move value to contract and run its call export
+    pub fn exec_call(&mut self, caller: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: MsgCall = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        if self.code(&msg.contract).is_none() {
+            return Err(InternalErrorKind::Other(format!("no contract at {}", msg.contract)).into());
+        }
+        self.transfer_value(*caller, msg.contract, msg.value)?;
+        self.run(msg.contract, *caller, msg.value, msg.input, "call", false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use bincode;
+    use parity_wasm::elements::{CodeSection, ExportEntry, ExportSection, External, Func, FuncBody,
+        FunctionSection, FunctionType, ImportEntry, ImportSection, Instruction, Instructions, Internal,
+        MemorySection, MemoryType, Module, Section, Type, TypeSection, ValueType};
+    use map_store::{MemoryKV, KVDB};
+    use crate::state::{ArchiveDB, StateDB};
+    use crate::types::{Hash, Address};
+    use crate::trie::NULL_ROOT;
+    use crate::balance::Balance;
+    use crate::runtime::Interpreter;
+    use crate::transaction::contract_msg::{MsgDeploy, MsgCall};
+    use super::Contract;
+
+    // Contract whose call export stores 32 bytes input at slot of caller address
+    fn store_input_contract() -> Vec<u8> {
+        let types = TypeSection::with_types(vec![
+            Type::Function(FunctionType::new(vec![ValueType::I32], None)),
+            Type::Function(FunctionType::new(vec![ValueType::I32, ValueType::I32], None)),
+            Type::Function(FunctionType::new(vec![], None)),
+        ]);
+        let imports = ImportSection::with_entries(vec![
+            ImportEntry::new("env".into(), "fetch_input".into(), External::Function(0)),
+            ImportEntry::new("env".into(), "caller".into(), External::Function(0)),
+            ImportEntry::new("env".into(), "storage_write".into(), External::Function(1)),
+        ]);
+        let body = Instructions::new(vec![
+            Instruction::I32Const(32),
+            Instruction::Call(0),
+            Instruction::I32Const(12),
+            Instruction::Call(1),
+            Instruction::I32Const(0),
+            Instruction::I32Const(32),
+            Instruction::Call(2),
+            Instruction::End,
+        ]);
+        let module = Module::new(vec![
+            Section::Type(types),
+            Section::Import(imports),
+            Section::Function(FunctionSection::with_entries(vec![Func::new(2)])),
+            Section::Memory(MemorySection::with_entries(vec![MemoryType::new(1, None)])),
+            Section::Export(ExportSection::with_entries(vec![
+                ExportEntry::new("call".into(), Internal::Function(3)),
+                ExportEntry::new("memory".into(), Internal::Memory(0)),
+            ])),
+            Section::Code(CodeSection::with_bodies(vec![FuncBody::new(vec![], body)])),
+        ]);
+        parity_wasm::serialize(module).unwrap()
+    }
+
+    fn runner() -> Interpreter {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        Interpreter::new(Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT))))
+    }
+
+    #[test]
+    fn test_deploy_and_call() {
+        let mut runner = runner();
+        let sender = Address([1; 20]);
+        Balance::from_state(runner.clone()).add_balance(sender, 1000);
+        Balance::from_state(runner.clone()).inc_nonce(sender);
+
+        let deploy = MsgDeploy {
+            code: store_input_contract(),
+            value: 100,
+            input: Vec::new(),
+        };
+        runner.call(&sender, b"contract.deploy".to_vec(), bincode::serialize(&deploy).unwrap()).unwrap();
+        let contract = Contract::contract_address(&sender, 1);
+        assert!(Contract::from_state(runner.clone()).code(&contract).is_some());
+        assert_eq!(Balance::from_state(runner.clone()).balance(contract), 100);
+        // Same nonce derives the same address
+        assert!(runner.call(&sender, b"contract.deploy".to_vec(), bincode::serialize(&deploy).unwrap()).is_err());
+
+        let call = MsgCall {
+            contract: contract,
+            value: 0,
+            input: vec![7u8; 32],
+        };
+        runner.call(&sender, b"contract.call".to_vec(), bincode::serialize(&call).unwrap()).unwrap();
+        let slot = Hash::from_bytes(sender.as_slice());
+        assert_eq!(Contract::from_state(runner.clone()).storage(&contract, &slot), Hash([7u8; 32]));
+
+        // No contract at address
+        let call = MsgCall {
+            contract: Address([2; 20]),
+            value: 0,
+            input: vec![7u8; 32],
+        };
+        assert!(runner.call(&sender, b"contract.call".to_vec(), bincode::serialize(&call).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_contract_gas() {
+        let mut runner = runner();
+        let sender = Address([1; 20]);
+        let deploy = MsgDeploy {
+            code: store_input_contract(),
+            value: 0,
+            input: Vec::new(),
+        };
+        runner.call(&sender, b"contract.deploy".to_vec(), bincode::serialize(&deploy).unwrap()).unwrap();
+        let call = MsgCall {
+            contract: Contract::contract_address(&sender, 0),
+            value: 0,
+            input: vec![7u8; 32],
+        };
+        let input = bincode::serialize(&call).unwrap();
+
+        // Storage write can not be paid
+        let limit = runner.schedule().call + runner.schedule().storage_write - 1;
+        runner.set_gas_limit(limit);
+        assert!(runner.call(&sender, b"contract.call".to_vec(), input.clone()).is_err());
+        assert_eq!(runner.gas_used(), limit);
+
+        runner.set_gas_limit(limit + 1000);
+        assert!(runner.call(&sender, b"contract.call".to_vec(), input).is_ok());
+        assert!(runner.gas_used() > runner.schedule().call + runner.schedule().storage_write);
+    }
+
+    #[test]
+    fn test_reject_invalid_code() {
+        assert!(Contract::prepare_code(b"not wasm").is_err());
+        assert!(Contract::prepare_code(&store_input_contract()).is_ok());
+    }
+}
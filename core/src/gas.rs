@@ -0,0 +1,199 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use errors::{Error, InternalErrorKind};
+use crate::state::StateDB;
+use crate::storage::StorageValue;
+
+const SCHEDULE: &[u8] = b"gas.schedule";
+
+/// Gas costs of transaction execution, tuned by chain
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct GasSchedule {
+    /// Base cost of every transaction
+    pub tx_base: u64,
+    /// Cost per byte of transaction data
+    pub tx_data_byte: u64,
+    /// Cost of balance.transfer
+    pub transfer: u64,
+    /// Cost of staking.validate
+    pub validate: u64,
+    /// Cost of staking.deposit
+    pub deposit: u64,
+    /// Cost of staking.exit
+    pub exit: u64,
+    /// Cost of staking.withdraw
+    pub withdraw: u64,
+    /// Cost of staking.report_equivocation
+    pub report_equivocation: u64,
+    /// Cost of staking.delegate
+    pub delegate: u64,
+    /// Cost of staking.undelegate
+    pub undelegate: u64,
+    /// Cost of staking.claim
+    pub claim: u64,
+    /// Base cost of contract.deploy
+    pub deploy: u64,
+    /// Cost per byte of deployed contract code
+    pub code_byte: u64,
+    /// Base cost of contract.call
+    pub call: u64,
+    /// Cost of contract reading a storage slot
+    pub storage_read: u64,
+    /// Cost of contract writing a storage slot
+    pub storage_write: u64,
+    /// Cost of asset.create
+    pub asset_create: u64,
+    /// Cost of asset.mint
+    pub asset_mint: u64,
+    /// Cost of asset.burn
+    pub asset_burn: u64,
+    /// Cost of asset.transfer
+    pub asset_transfer: u64,
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        GasSchedule {
+            tx_base: 200,
+            tx_data_byte: 1,
+            transfer: 300,
+            validate: 600,
+            deposit: 400,
+            exit: 400,
+            withdraw: 400,
+            report_equivocation: 1000,
+            delegate: 400,
+            undelegate: 400,
+            claim: 400,
+            deploy: 2000,
+            code_byte: 10,
+            call: 500,
+            storage_read: 200,
+            storage_write: 500,
+            asset_create: 1000,
+            asset_mint: 400,
+            asset_burn: 400,
+            asset_transfer: 300,
+        }
+    }
+}
+
+impl GasSchedule {
+    /// Schedule recorded in state by genesis
+    pub fn from_state(state: Rc<RefCell<StateDB>>) -> Option<GasSchedule> {
+        StorageValue::new(state, SCHEDULE).get()
+    }
+
+    pub fn store(&self, state: Rc<RefCell<StateDB>>) {
+        StorageValue::new(state, SCHEDULE).set(self)
+    }
+
+    /// Gas charged before transaction message executed
+    pub fn intrinsic_gas(&self, data_len: usize) -> u64 {
+        self.tx_base.saturating_add(self.tx_data_byte.saturating_mul(data_len as u64))
+    }
+
+    /// Gas cost of module operation, None for unknown call
+    pub fn call_cost(&self, msg: &[u8]) -> Option<u64> {
+        match msg {
+            b"balance.transfer" => Some(self.transfer),
+            b"staking.validate" => Some(self.validate),
+            b"staking.deposit" => Some(self.deposit),
+            b"staking.exit" => Some(self.exit),
+            b"staking.withdraw" => Some(self.withdraw),
+            b"staking.report_equivocation" => Some(self.report_equivocation),
+            b"staking.delegate" => Some(self.delegate),
+            b"staking.undelegate" => Some(self.undelegate),
+            b"staking.claim" => Some(self.claim),
+            b"contract.deploy" => Some(self.deploy),
+            b"contract.call" => Some(self.call),
+            b"asset.create" => Some(self.asset_create),
+            b"asset.mint" => Some(self.asset_mint),
+            b"asset.burn" => Some(self.asset_burn),
+            b"asset.transfer" => Some(self.asset_transfer),
+            _ => None,
+        }
+    }
+}
+
+/// Gas accounting of single transaction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GasMeter {
+    limit: u64,
+    used: u64,
+}
+
+impl Default for GasMeter {
+    fn default() -> Self {
+        GasMeter::new(u64::max_value())
+    }
+}
+
+impl GasMeter {
+    pub fn new(limit: u64) -> Self {
+        GasMeter {
+            limit: limit,
+            used: 0,
+        }
+    }
+
+    /// Consume gas, all gas is used up when limit exceeded
+    pub fn charge(&mut self, amount: u64) -> Result<(), Error> {
+        let used = self.used.saturating_add(amount);
+        if used > self.limit {
+            self.used = self.limit;
+            return Err(InternalErrorKind::OutOfGas.into());
+        }
+        self.used = used;
+        Ok(())
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.limit - self.used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GasMeter, GasSchedule};
+
+    #[test]
+    fn meter_out_of_gas() {
+        let mut meter = GasMeter::new(1000);
+        assert!(meter.charge(600).is_ok());
+        assert_eq!(meter.remaining(), 400);
+        assert!(meter.charge(600).is_err());
+        assert_eq!(meter.used(), 1000);
+    }
+
+    #[test]
+    fn schedule_cost() {
+        let schedule = GasSchedule::default();
+        assert_eq!(schedule.call_cost(b"balance.transfer"), Some(schedule.transfer));
+        assert_eq!(schedule.call_cost(b"balance.unknown"), None);
+        assert_eq!(schedule.intrinsic_gas(10), schedule.tx_base + 10 * schedule.tx_data_byte);
+    }
+}
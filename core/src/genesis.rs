@@ -23,6 +23,8 @@ use super::types::{Hash, Address};
 use super::block;
 use super::balance::Balance;
 use super::block::{Block, BlockProof};
+use super::gas::GasSchedule;
+use super::reward::IssuanceSchedule;
 use super::runtime::Interpreter;
 use super::state::{ArchiveDB, StateDB};
 use super::staking::{Validator, Staking};
@@ -70,19 +72,18 @@ pub fn setup_allocation(db: Rc<RefCell<StateDB>>) -> Hash {
         let interpreter = Interpreter::new(db.clone());
         let mut state = Staking::new(interpreter);
         for &(addr, pk, value) in validators {
+            let address = Address::from_hex(addr).unwrap();
             let validator = Validator {
-                address: Address::from_hex(addr).unwrap(),
                 pubkey: Pubkey::from_hex(pk).to_bytes(),
-                balance: 0,
                 effective_balance: value,
-                activate_height: 0,
-                exit_height: 0,
-                deposit_queue: Vec::new(),
-                unlocked_queue: Vec::new(),
+                ..Validator::create(address)
             };
             state.insert(&validator);
         }
     }
+    // Chain parameters are fixed by genesis state
+    GasSchedule::default().store(db.clone());
+    IssuanceSchedule::default().store(db.clone());
     db.borrow_mut().commit();
     db.borrow().root()
 }
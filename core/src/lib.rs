@@ -20,10 +20,18 @@ pub mod types;
 pub mod block;
 pub mod genesis;
 pub mod transaction;
+pub mod receipt;
 pub mod balance;
 pub mod staking;
 pub mod storage;
 pub mod trie;
+pub mod cache;
 pub mod state;
 pub mod runtime;
-pub mod traits;
\ No newline at end of file
+pub mod contract;
+pub mod asset;
+pub mod reward;
+pub mod gas;
+pub mod traits;
+#[cfg(feature = "test-helpers")]
+pub mod test_helpers;
@@ -0,0 +1,76 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use crate::types::Hash;
+
+/// Execution result of a transaction
+#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReceiptStatus {
+    Failed = 0,
+    Success = 1,
+}
+
+impl Default for ReceiptStatus {
+    fn default() -> Self {
+        ReceiptStatus::Success
+    }
+}
+
+/// Represents the outcome of a transaction included in block
+#[derive(Serialize, Deserialize)]
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct Receipt {
+    /// Whether the transaction executed successfully
+    pub status: ReceiptStatus,
+    /// Fee paid by the sender
+    pub fee: u128,
+    /// Gas consumed by the transaction
+    pub gas_used: u64,
+    /// State root after the transaction applied
+    pub state_root: Hash,
+}
+
+impl Receipt {
+    pub fn new(status: ReceiptStatus, fee: u128, gas_used: u64, state_root: Hash) -> Self {
+        Receipt {
+            status: status,
+            fee: fee,
+            gas_used: gas_used,
+            state_root: state_root,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == ReceiptStatus::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode;
+    use super::*;
+
+    #[test]
+    fn receipt_encode() {
+        let receipt = Receipt::new(ReceiptStatus::Failed, 10000, 21000, Hash([1u8; 32]));
+        let encoded: Vec<u8> = bincode::serialize(&receipt).unwrap();
+        let decoded: Receipt = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(decoded, receipt);
+        assert!(!decoded.is_success());
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde::{Serialize, Deserialize};
+use crate::types::Address;
+use crate::state::StateDB;
+use crate::storage::{StorageValue, StorageMap};
+use crate::balance::Balance;
+use crate::runtime::Interpreter;
+use crate::staking::{epoch_of, Staking};
+
+const ISSUANCE: &[u8] = b"reward.issuance";
+const SCHEDULE: &[u8] = b"reward.schedule";
+const EPOCHS: &[u8] = b"reward.epochs";
+
+/// Block reward minted to proposers, tuned by chain
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct IssuanceSchedule {
+    /// Reward of each block before the first halving
+    pub initial_reward: u128,
+    /// Blocks between reward halvings, zero keeps the reward constant
+    pub halving_interval: u64,
+    /// Limit of total minted rewards
+    pub max_issuance: u128,
+}
+
+impl Default for IssuanceSchedule {
+    fn default() -> Self {
+        IssuanceSchedule {
+            initial_reward: 1_000_000_000,
+            halving_interval: 2_000_000,
+            max_issuance: 1_000_000_000_000_000_000,
+        }
+    }
+}
+
+impl IssuanceSchedule {
+    /// Schedule recorded in state by genesis
+    pub fn from_state(state: Rc<RefCell<StateDB>>) -> Option<IssuanceSchedule> {
+        StorageValue::new(state, SCHEDULE).get()
+    }
+
+    pub fn store(&self, state: Rc<RefCell<StateDB>>) {
+        StorageValue::new(state, SCHEDULE).set(self)
+    }
+
+    /// Reward of block at height, genesis has no reward
+    pub fn block_reward(&self, height: u64) -> u128 {
+        if height == 0 {
+            return 0;
+        }
+        if self.halving_interval == 0 {
+            return self.initial_reward;
+        }
+        let halvings = (height - 1) / self.halving_interval;
+        if halvings >= 128 {
+            return 0;
+        }
+        self.initial_reward >> halvings
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct ProposerReward {
+    pub address: Address,
+    pub blocks: u64,
+    pub minted: u128,
+    pub fees: u128,
+}
+
+/// Rewards paid in an epoch
+#[derive(Serialize, Deserialize)]
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct EpochReward {
+    pub blocks: u64,
+    pub minted: u128,
+    pub fees: u128,
+    pub proposers: Vec<ProposerReward>,
+}
+
+pub struct Rewards {
+    // Total minted rewards
+    issuance: StorageValue<u128>,
+    epochs: StorageMap<u64, EpochReward>,
+    interpreter: Interpreter,
+}
+
+impl Rewards {
+    pub fn from_state(runner: Interpreter) -> Self {
+        let state_db = runner.statedb();
+        Rewards {
+            issuance: StorageValue::new(state_db.clone(), ISSUANCE),
+            epochs: StorageMap::new(state_db, EPOCHS),
+            interpreter: runner,
+        }
+    }
+
+    pub fn total_issuance(&self) -> u128 {
+        self.issuance.get_or_default()
+    }
+
+    pub fn epoch_reward(&self, epoch: u64) -> EpochReward {
+        self.epochs.get(&epoch).unwrap_or_default()
+    }
+
+    /// Mint block reward to proposer and record it with the fees proposer got in block
+    pub fn reward_block(&mut self, height: u64, proposer: &Address, fees: u128) -> u128 {
+        let issued = self.total_issuance();
+        let schedule = self.interpreter.issuance();
+        let minted = std::cmp::min(schedule.block_reward(height), schedule.max_issuance.saturating_sub(issued));
+        if minted > 0 {
+            // Delegators part is kept in staking until claimed
+            let paid = Staking::from_state(self.interpreter.clone()).distribute_reward(proposer, minted);
+            Balance::from_state(self.interpreter.clone()).add_balance(*proposer, paid);
+            self.issuance.set(&(issued + minted));
+        }
+
+        let epoch = epoch_of(height);
+        let mut record = self.epoch_reward(epoch);
+        record.blocks += 1;
+        record.minted += minted;
+        record.fees += fees;
+        match record.proposers.iter_mut().find(|p| p.address == *proposer) {
+            Some(p) => {
+                p.blocks += 1;
+                p.minted += minted;
+                p.fees += fees;
+            }
+            None => record.proposers.push(ProposerReward {
+                address: *proposer,
+                blocks: 1,
+                minted: minted,
+                fees: fees,
+            }),
+        }
+        self.epochs.insert(&epoch, &record);
+        minted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use map_store::{MemoryKV, KVDB};
+    use crate::state::{ArchiveDB, StateDB};
+    use crate::types::Address;
+    use crate::trie::NULL_ROOT;
+    use crate::balance::Balance;
+    use crate::runtime::Interpreter;
+    use crate::staking::EPOCH_LENGTH;
+    use super::{IssuanceSchedule, Rewards};
+
+    #[test]
+    fn test_block_reward() {
+        let schedule = IssuanceSchedule {
+            initial_reward: 100,
+            halving_interval: 10,
+            max_issuance: 1000,
+        };
+        assert_eq!(schedule.block_reward(0), 0);
+        assert_eq!(schedule.block_reward(1), 100);
+        assert_eq!(schedule.block_reward(10), 100);
+        assert_eq!(schedule.block_reward(11), 50);
+        assert_eq!(schedule.block_reward(10 * 200), 0);
+    }
+
+    #[test]
+    fn test_reward_block() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let mut runner = Interpreter::new(Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT))));
+        runner.set_issuance(IssuanceSchedule {
+            initial_reward: 100,
+            halving_interval: 0,
+            max_issuance: 250,
+        });
+        let (a, b) = (Address([1; 20]), Address([2; 20]));
+
+        let mut rewards = Rewards::from_state(runner.clone());
+        assert_eq!(rewards.reward_block(1, &a, 5), 100);
+        assert_eq!(rewards.reward_block(2, &b, 0), 100);
+        assert_eq!(rewards.reward_block(3, &a, 7), 50);
+        assert_eq!(rewards.reward_block(EPOCH_LENGTH, &a, 0), 0);
+        assert_eq!(rewards.total_issuance(), 250);
+        assert_eq!(Balance::from_state(runner.clone()).balance(a), 150);
+
+        let epoch = rewards.epoch_reward(0);
+        assert_eq!((epoch.blocks, epoch.minted, epoch.fees), (3, 250, 12));
+        assert_eq!(epoch.proposers.len(), 2);
+        assert_eq!((epoch.proposers[0].blocks, epoch.proposers[0].minted), (2, 150));
+        assert_eq!(rewards.epoch_reward(1).blocks, 1);
+    }
+}
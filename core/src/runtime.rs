@@ -17,9 +17,14 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use errors::{Error, InternalErrorKind};
 use crate::state::{StateDB};
 use crate::staking::Staking;
 use crate::balance::Balance;
+use crate::contract::Contract;
+use crate::asset::Asset;
+use crate::gas::{GasMeter, GasSchedule};
+use crate::reward::IssuanceSchedule;
 use crate::types::Address;
 
 // pub trait Contract: {
@@ -31,12 +36,26 @@ use crate::types::Address;
 #[derive(Clone)]
 pub struct Interpreter {
     state_db: Rc<RefCell<StateDB>>,
+    schedule: GasSchedule,
+    issuance: IssuanceSchedule,
+    // Shared with module clones of the interpreter
+    gas: Rc<RefCell<GasMeter>>,
+    // Height of the block being executed
+    height: u64,
 }
 
 impl Interpreter {
     pub fn new(backend: Rc<RefCell<StateDB>>) -> Self {
+        Interpreter::with_schedule(backend, GasSchedule::default())
+    }
+
+    pub fn with_schedule(backend: Rc<RefCell<StateDB>>, schedule: GasSchedule) -> Self {
         Interpreter {
             state_db: backend.clone(),
+            schedule: schedule,
+            issuance: IssuanceSchedule::default(),
+            gas: Rc::new(RefCell::new(GasMeter::default())),
+            height: 0,
         }
     }
 
@@ -44,29 +63,97 @@ impl Interpreter {
         self.state_db.clone()
     }
 
-    pub fn call(&mut self, caller: &Address, msg: Vec<u8>, input: Vec<u8>) {
-        let sep = msg.iter().position(|&x| x == '.' as u8);
-        if sep.is_none() {
-            warn!("invalid msg in transaction");
-            return
-        }
-        let (module, func) = msg.split_at(sep.unwrap());
+    pub fn schedule(&self) -> &GasSchedule {
+        &self.schedule
+    }
+
+    pub fn set_issuance(&mut self, issuance: IssuanceSchedule) {
+        self.issuance = issuance;
+    }
+
+    pub fn issuance(&self) -> &IssuanceSchedule {
+        &self.issuance
+    }
+
+    /// Set height of the block whose transactions are executed
+    pub fn set_height(&mut self, height: u64) {
+        self.height = height;
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Reset gas meter for a new transaction
+    pub fn set_gas_limit(&mut self, limit: u64) {
+        *self.gas.borrow_mut() = GasMeter::new(limit);
+    }
+
+    pub fn charge_gas(&self, amount: u64) -> Result<(), Error> {
+        self.gas.borrow_mut().charge(amount)
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.gas.borrow().used()
+    }
+
+    pub fn call(&mut self, caller: &Address, msg: Vec<u8>, input: Vec<u8>) -> Result<(), Error> {
+        let cost = match self.schedule.call_cost(&msg) {
+            Some(c) => c,
+            None => {
+                warn!("unsupport msg call");
+                return Err(InternalErrorKind::Execute.into());
+            }
+        };
+        self.charge_gas(cost)?;
+
+        let sep = match msg.iter().position(|&x| x == '.' as u8) {
+            Some(i) => i,
+            None => {
+                warn!("invalid msg in transaction");
+                return Err(InternalErrorKind::Execute.into());
+            }
+        };
+        let (module, func) = (&msg[..sep], &msg[sep + 1..]);
 
         if module == b"balance" {
             let mut state = Balance::from_state(self.clone());
             match func {
                 b"transfer" => state.exec_transfer(*caller, input),
-                _ => warn!("invalid balance call"),
+                _ => Err(InternalErrorKind::Execute.into()),
             }
         } else if module == b"staking" {
             let mut state = Staking::from_state(self.clone());
             match func {
                 b"validate" => state.exec_validate(caller, input),
                 b"deposit" => state.exec_deposit(caller, input),
-                _ => warn!("invalid staking call"),
+                b"exit" => state.exec_exit(caller, input),
+                b"withdraw" => state.exec_withdraw(caller, input),
+                b"report_equivocation" => state.exec_report_equivocation(caller, input),
+                b"delegate" => state.exec_delegate(caller, input),
+                b"undelegate" => state.exec_undelegate(caller, input),
+                b"claim" => state.exec_claim(caller, input),
+                _ => Err(InternalErrorKind::Execute.into()),
+            }
+        } else if module == b"contract" {
+            let mut state = Contract::from_state(self.clone());
+            match func {
+                b"deploy" => state.exec_deploy(caller, input),
+                b"call" => state.exec_call(caller, input),
+                _ => Err(InternalErrorKind::Execute.into()),
+            }
+        } else if module == b"asset" {
+            let mut state = Asset::from_state(self.clone());
+            match func {
+                b"create" => state.exec_create(caller, input),
+                b"mint" => state.exec_mint(caller, input),
+                b"burn" => state.exec_burn(caller, input),
+                b"transfer" => state.exec_transfer(caller, input),
+                _ => Err(InternalErrorKind::Execute.into()),
             }
         } else {
             warn!("unsupport msg call");
+            Err(InternalErrorKind::Execute.into())
         }
     }
 }
@@ -93,6 +180,8 @@ mod tests {
     use crate::state::{ArchiveDB, StateDB};
     use crate::types::Address;
     use crate::trie::NULL_ROOT;
+    use crate::balance::Balance;
+    use crate::transaction::balance_msg::MsgTransfer;
     use super::{Interpreter};
 
     #[test]
@@ -101,6 +190,35 @@ mod tests {
         let db = ArchiveDB::new(Arc::clone(&backend));
         let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
         let mut runner = Interpreter::new(state_db.clone());
-        runner.call(&Address::default(), b"staking.deposit".to_vec(), bincode::serialize(&1u128).unwrap());
+        let sender = Address::default();
+        let receiver = Address([1; 20]);
+        Balance::from_state(runner.clone()).add_balance(sender, 10);
+
+        // deposit without joining validator
+        assert!(runner.call(&sender, b"staking.deposit".to_vec(), bincode::serialize(&1u128).unwrap()).is_err());
+        assert!(runner.call(&sender, b"balance.unknown".to_vec(), Vec::new()).is_err());
+
+        let input = bincode::serialize(&MsgTransfer{receiver: receiver, value: 6}).unwrap();
+        assert!(runner.call(&sender, b"balance.transfer".to_vec(), input.clone()).is_ok());
+        assert!(runner.call(&sender, b"balance.transfer".to_vec(), input).is_err());
+        assert_eq!(Balance::from_state(runner.clone()).balance(receiver), 6);
+    }
+
+    #[test]
+    fn interpreter_gas() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db.clone());
+        let cost = runner.schedule().transfer;
+        let input = bincode::serialize(&MsgTransfer{receiver: Address([1; 20]), value: 0}).unwrap();
+
+        runner.set_gas_limit(cost);
+        assert!(runner.call(&Address::default(), b"balance.transfer".to_vec(), input.clone()).is_ok());
+        assert_eq!(runner.gas_used(), cost);
+
+        runner.set_gas_limit(cost - 1);
+        assert!(runner.call(&Address::default(), b"balance.transfer".to_vec(), input).is_err());
+        assert_eq!(runner.gas_used(), cost - 1);
     }
 }
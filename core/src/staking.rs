@@ -19,17 +19,45 @@ use std::rc::Rc;
 
 use serde::{Serialize, Deserialize};
 use bincode;
-use hash;
-use crate::types::{Hash, Address};
-use crate::storage::{List, ListEntry};
+use errors::{Error, InternalErrorKind};
+use ed25519::pubkey::Pubkey;
+use crate::types::Address;
+use crate::storage::{StorageMap, StorageList, StorageIndexedMap};
+use crate::transaction::staking_msg::{SignedHeader, MsgEquivocation, MsgDelegate};
 use crate::state::StateDB;
 use crate::balance::Balance;
 use crate::runtime::Interpreter;
 
-#[derive(Copy, Clone)]
-enum StatePrefix {
-    /// Validators list key
-    Validator = 2,
+/// Blocks of a staking epoch
+pub const EPOCH_LENGTH: u64 = 64;
+/// Epochs between exit request and the epoch validator leaves the set
+pub const EXIT_DELAY_EPOCHS: u64 = 1;
+/// Blocks exited stake stays locked before it can be withdrawn
+pub const UNBONDING_PERIOD: u64 = 4 * EPOCH_LENGTH;
+/// Percent of effective balance slashed for equivocation
+pub const SLASH_PERCENT: u128 = 10;
+/// Percent of slashed stake paid to reporter, the rest is burnt
+pub const REPORTER_REWARD_PERCENT: u128 = 10;
+/// Precision of delegator reward per stake
+pub const REWARD_SCALE: u128 = 1_000_000_000_000;
+/// Smallest stake bonded by a single delegation
+pub const MIN_DELEGATION: u128 = 1000;
+
+const VALIDATORS: &[u8] = b"staking.validators";
+const DELEGATIONS: &[u8] = b"staking.delegations";
+const PENDING_DELEGATIONS: &[u8] = b"staking.pending_delegations";
+
+pub fn epoch_of(height: u64) -> u64 {
+    height / EPOCH_LENGTH
+}
+
+pub fn epoch_start(epoch: u64) -> u64 {
+    epoch * EPOCH_LENGTH
+}
+
+/// Staking changes take effect at the first block of each epoch
+pub fn is_epoch_start(height: u64) -> bool {
+    height > 0 && height % EPOCH_LENGTH == 0
 }
 
 #[derive(Serialize, Deserialize)]
@@ -50,6 +78,43 @@ pub struct Validator {
     pub exit_height: u64,
     pub deposit_queue: Vec<LockingBalance>,
     pub unlocked_queue: Vec<LockingBalance>,
+    /// Slashed for misbehavior and forced to exit
+    pub jailed: bool,
+    /// Percent of delegators reward taken by validator
+    pub commission: u8,
+    /// Stake bonded by delegators, part of effective balance while validator is active
+    pub delegated: u128,
+    /// Accumulated delegator reward per delegated stake, scaled by REWARD_SCALE
+    pub reward_per_stake: u128,
+}
+
+/// Stake of delegator bonded to a validator
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delegation {
+    pub delegator: Address,
+    pub validator: Address,
+    pub amount: u128,
+    /// Validator reward_per_stake when rewards were last settled
+    pub reward_index: u128,
+    /// Settled rewards not claimed yet
+    pub rewards: u128,
+    pub unlocked_queue: Vec<LockingBalance>,
+    /// Stake waiting for next epoch to be bonded, listed under the epoch in pending delegations
+    pub pending: u128,
+}
+
+impl Delegation {
+    fn is_empty(&self) -> bool {
+        self.amount == 0 && self.pending == 0 && self.rewards == 0 && self.unlocked_queue.is_empty()
+    }
+
+    // Move rewards accumulated since last settlement into rewards
+    fn settle(&mut self, validator: &Validator) {
+        let accrued = self.amount * (validator.reward_per_stake - self.reward_index) / REWARD_SCALE;
+        self.rewards += accrued;
+        self.reward_index = validator.reward_per_stake;
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -57,6 +122,7 @@ pub struct Validator {
 pub struct MsgValidatorCreate {
     pub pubkey: Vec<u8>,
     pub amount: u128,
+    pub commission: u8,
 }
 
 impl Validator {
@@ -70,253 +136,497 @@ impl Validator {
             exit_height: 0,
             deposit_queue: Vec::new(),
             unlocked_queue: Vec::new(),
+            jailed: false,
+            commission: 0,
+            delegated: 0,
+            reward_per_stake: 0,
         }
     }
 
-    pub fn map_key(&self) -> Hash {
-        let mut raw = vec![];
-        raw.extend_from_slice(Hash::from_bytes(self.address.as_slice()).as_bytes());
-        let position = Hash::from_bytes(&(StatePrefix::Validator as u64).to_be_bytes()[..]);
-        raw.extend_from_slice(position.as_bytes());
-
-        Hash(hash::blake2b_256(&raw))
+    /// Validator takes part in consensus at height
+    pub fn is_active(&self, height: u64) -> bool {
+        !self.jailed && self.activate_height <= height && (self.exit_height == 0 || height < self.exit_height)
     }
 
-    pub fn key_index(addr: &Address) -> Hash {
-        let mut raw = vec![];
-        raw.extend_from_slice(Hash::from_bytes(addr.as_slice()).as_bytes());
-        let position = Hash::from_bytes(&(StatePrefix::Validator as u64).to_be_bytes()[..]);
-        raw.extend_from_slice(position.as_bytes());
-
-        Hash(hash::blake2b_256(&raw))
+    pub fn is_exiting(&self) -> bool {
+        self.exit_height != 0
     }
 }
 
 pub struct Staking {
-    pub validators: List<Validator>,
+    pub validators: StorageIndexedMap<Address, Validator>,
+    delegations: StorageMap<(Address, Address), Delegation>,
     pub state_db: Rc<RefCell<StateDB>>,
     pub interpreter: Interpreter,
 }
 
 impl Staking {
     pub fn new(runner: Interpreter) -> Self {
-        let head_key = Hash::from_bytes(&(StatePrefix::Validator as u64).to_be_bytes()[..]);
-        Staking {
-            validators: List::new(head_key),
-            state_db: runner.statedb(),
-            interpreter: runner,
-        }
+        Staking::from_state(runner)
     }
 
     pub fn from_state(runner: Interpreter) -> Self {
-        let head_key = Hash::from_bytes(&(StatePrefix::Validator as u64).to_be_bytes()[..]);
+        let state_db = runner.statedb();
         Staking {
-            validators: List::new(head_key),
-            state_db: runner.statedb(),
+            validators: StorageIndexedMap::new(state_db.clone(), VALIDATORS),
+            delegations: StorageMap::new(state_db.clone(), DELEGATIONS),
+            state_db: state_db,
             interpreter: runner,
         }
     }
 
     pub fn insert(&mut self, item: &Validator) {
-        let head = self.state_db.borrow_mut().get_storage(&self.validators.head_key);
-        if head.is_none() {
-            let entry = ListEntry {
-                pre: None,
-                next: None,
-                payload: item,
-            };
-            let encoded: Vec<u8> = bincode::serialize(&entry).unwrap();
-            self.state_db.borrow_mut().set_storage(item.map_key(), &encoded);
-            self.state_db.borrow_mut().set_storage(self.validators.head_key, item.map_key().as_bytes());
-        } else {
-            let head_ref = Hash::from_bytes(&head.unwrap()[..]);
-
-            let entry = ListEntry {
-                pre: None,
-                next: Some(head_ref),
-                payload: item,
-            };
-            self.state_db.borrow_mut().set_storage(item.map_key(), &bincode::serialize(&entry).unwrap());
-            {
-                // replace next entry of inserted item
-                let encoded = self.state_db.borrow().get_storage(&head_ref).unwrap();
-                let mut next: ListEntry<Validator> = bincode::deserialize(&encoded).unwrap();
-                next.pre = Some(item.map_key());
-                let serialized: Vec<u8> = bincode::serialize(&next).unwrap();
-                self.state_db.borrow_mut().set_storage(next.payload.map_key(), &serialized);
-            }
-            // place reference of first item at head
-            self.state_db.borrow_mut().set_storage(self.validators.head_key, item.map_key().as_bytes());
-        }
+        self.validators.insert(&item.address, item);
     }
 
     pub fn set_item(&mut self, item: &Validator) {
-        let encoded = self.state_db.borrow().get_storage(&item.map_key());
-        if encoded.is_none() {
-            self.insert(item);
-        } else {
-            let mut entry: ListEntry<Validator> = bincode::deserialize(&encoded.unwrap()).unwrap();
-            entry.payload = item.clone();
-            self.state_db.borrow_mut().set_storage(item.map_key(), &bincode::serialize(&entry).unwrap());
-        }
+        self.insert(item)
     }
 
     pub fn delete(&mut self, addr: &Address) {
-        let encoded = match self.state_db.borrow().get_storage(&Validator::key_index(addr)) {
-            Some(i) => i,
-            None => return,
-        };
-
-        let item: ListEntry<Validator> = bincode::deserialize(&encoded).unwrap();
-        if item.pre.is_some() {
-            let encoded = self.state_db.borrow().get_storage(&item.pre.unwrap()).unwrap();
-            let mut pre_node: ListEntry<Validator> = bincode::deserialize(&encoded).unwrap();
-            pre_node.next = item.next;
-            self.state_db.borrow_mut().set_storage(item.pre.unwrap(), &bincode::serialize(&pre_node).unwrap());
-        } else {
-            if let Some(next) = item.next {
-                // set head to next item
-                self.state_db.borrow_mut().set_storage(self.validators.head_key, next.as_bytes());
-            } else {
-                // EMPTY list, remove head ref
-                self.state_db.borrow_mut().remove_storage(self.validators.head_key);
-            }
-        }
+        self.validators.remove(addr);
+    }
 
-        if item.next.is_some() {
-            let encoded = self.state_db.borrow().get_storage(&item.next.unwrap()).unwrap();
-            let mut next_node: ListEntry<Validator> = bincode::deserialize(&encoded).unwrap();
-            next_node.pre = item.pre;
-            self.state_db.borrow_mut().set_storage(item.next.unwrap(), &bincode::serialize(&next_node).unwrap());
-        }
-        // delete target from trie db
-        self.state_db.borrow_mut().remove_storage(Validator::key_index(addr));
+    pub fn validator_count(&self) -> u64 {
+        self.validators.len()
     }
 
     pub fn validator_set(&self) -> Vec<Validator> {
-        let mut items = Vec::new();
-
-        let head_ref = match self.state_db.borrow().get_storage(&self.validators.head_key) {
-            Some(i) => i,
-            None => return items,
-        };
+        self.validators.values()
+    }
 
-        // iterate list items
-        let mut next_ref = Some(Hash::from_bytes(&head_ref));
-        while next_ref.is_some() {
-            let encoded = self.state_db.borrow().get_storage(&next_ref.unwrap()).unwrap();
-            let item: ListEntry<Validator> = bincode::deserialize(&encoded).unwrap();
-            items.push(item.payload);
-            next_ref = item.next;
-        }
-        items
+    /// Validators in range [offset, offset + limit) of the set
+    pub fn validator_page(&self, offset: u64, limit: u64) -> Vec<Validator> {
+        self.validators.page(offset, limit)
     }
 
     pub fn get_validator(&self, addr: &Address) -> Option<Validator> {
-        // let head = self.state().get_storage(&self.validators.head_key);
-        // if head.is_none() {
-        //     return
-        // }
-        let encoded = match self.state_db.borrow().get_storage(&Validator::key_index(addr)) {
-            Some(i) => i,
-            None => return None,
-        };
-        let obj: ListEntry<Validator> = bincode::deserialize(&encoded).unwrap();
-        Some(obj.payload)
+        self.validators.get(addr)
     }
 
-    #[allow(unused_variables)]
-    pub fn validate(&mut self, addr: &Address, pubkey: Vec<u8>, amount: u128) {
+    pub fn validate(&mut self, addr: &Address, pubkey: Vec<u8>, amount: u128, commission: u8) -> Result<(), Error> {
+        if commission > 100 {
+            return Err(InternalErrorKind::Other("commission exceeds 100 percent".to_string()).into());
+        }
         if self.get_validator(addr).is_some() {
             // the address already joined the validator
-            return
+            return Err(InternalErrorKind::Other("validator exists".to_string()).into());
+        }
+        if Balance::from_state(self.interpreter.clone()).balance(*addr) < amount {
+            return Err(InternalErrorKind::BalanceNotEnough.into());
         }
-        // mark the epoch in which validator take effect
-        let activate: u64 = 0;
-        // create and initialize validator
+        // validator and its stake take effect from next epoch
+        let activate = epoch_start(epoch_of(self.interpreter.height()) + 1);
         let validator = Validator {
             address: *addr,
             pubkey: pubkey,
             balance: amount,
-            effective_balance: amount,
-            activate_height: 0,
+            effective_balance: 0,
+            activate_height: activate,
             exit_height: 0,
-            deposit_queue: Vec::new(),
+            deposit_queue: vec![LockingBalance{amount: amount, height: activate}],
             unlocked_queue: Vec::new(),
+            jailed: false,
+            commission: commission,
+            delegated: 0,
+            reward_per_stake: 0,
         };
         self.insert(&validator);
 
-        // self.interpreter.lock_balance(*addr, amount);
         {
             let mut state = Balance::from_state(self.interpreter.clone());
             state.lock_balance(*addr, amount);
         }
+        Ok(())
     }
 
-    pub fn deposit(&mut self, addr: &Address, amount: u128) {
+    pub fn deposit(&mut self, addr: &Address, amount: u128) -> Result<(), Error> {
         let mut validator = match self.get_validator(&addr) {
             Some(i) => i,
-            None => return,
+            None => return Err(InternalErrorKind::Other("validator not found".to_string()).into()),
         };
-        validator.deposit_queue.push(LockingBalance{amount: amount, height: 0});
+        if validator.is_exiting() {
+            return Err(InternalErrorKind::Other("validator is exiting".to_string()).into());
+        }
+        if Balance::from_state(self.interpreter.clone()).balance(*addr) < amount {
+            return Err(InternalErrorKind::BalanceNotEnough.into());
+        }
+        let activate = epoch_start(epoch_of(self.interpreter.height()) + 1);
+        validator.deposit_queue.push(LockingBalance{amount: amount, height: activate});
         validator.balance += amount;
         self.set_item(&validator);
 
-        // self.interpreter.lock_balance(*addr, amount);
         {
             let mut state = Balance::from_state(self.interpreter.clone());
             state.lock_balance(*addr, amount);
         }
+        Ok(())
     }
 
-    pub fn activate_deposit(&mut self, addr: &Address) {
-        let mut validator = match self.get_validator(&addr) {
-            Some(i) => i,
-            None => return,
-        };
-        // available deposit take queue util active epoch
-        let epoch: u64 = 0;
-        let offset: usize = 0;
+    // Move deposits reaching activation height into effective balance
+    fn activate_deposit(validator: &mut Validator, height: u64) -> bool {
+        let mut offset: usize = 0;
         while offset < validator.deposit_queue.len() {
-            if validator.deposit_queue[offset].height > epoch {
+            if validator.deposit_queue[offset].height > height {
                 break;
             }
             validator.effective_balance += validator.deposit_queue[offset].amount;
+            offset += 1;
+        }
+        validator.deposit_queue = validator.deposit_queue[offset..].to_vec();
+        offset > 0
+    }
+
+    // Delegations bonded at start of epoch, each (delegator, validator) pair is listed once
+    fn pending_delegations(&self, epoch: u64) -> StorageList<(Address, Address)> {
+        let name = [PENDING_DELEGATIONS, &epoch.to_be_bytes()[..]].concat();
+        StorageList::new(self.state_db.clone(), &name)
+    }
+
+    // Bond delegations pending for the epoch, rewards accrue to them from now on
+    fn activate_delegations(&mut self, height: u64) {
+        let mut pending = self.pending_delegations(epoch_of(height));
+        while let Some((delegator, addr)) = pending.pop() {
+            let mut delegation = match self.get_delegation(&delegator, &addr) {
+                Some(i) => i,
+                None => continue,
+            };
+            let amount = delegation.pending;
+            delegation.pending = 0;
+            match self.get_validator(&addr) {
+                Some(mut validator) => {
+                    delegation.settle(&validator);
+                    delegation.amount += amount;
+                    validator.delegated += amount;
+                    validator.effective_balance += amount;
+                    self.set_item(&validator);
+                }
+                // Stake of removed validator is returned unbonded
+                None => delegation.unlocked_queue.push(LockingBalance{amount: amount, height: height}),
+            }
+            self.set_delegation(&delegation);
+        }
+    }
+
+    // Remove exited validator stake from the set and start unbonding it
+    fn process_exit(validator: &mut Validator, height: u64) -> bool {
+        if !validator.is_exiting() || validator.exit_height > height {
+            return false;
+        }
+        if validator.balance == 0 && validator.effective_balance == 0 {
+            return false;
+        }
+        if validator.balance > 0 {
+            validator.unlocked_queue.push(LockingBalance{
+                amount: validator.balance,
+                height: height + UNBONDING_PERIOD,
+            });
+        }
+        validator.balance = 0;
+        validator.effective_balance = 0;
+        validator.deposit_queue.clear();
+        true
+    }
+
+    /// Epoch transition, activates pending deposits and delegations and applies due exits
+    pub fn on_epoch(&mut self) {
+        let height = self.interpreter.height();
+        self.activate_delegations(height);
+        for mut validator in self.validator_set() {
+            let activated = Self::activate_deposit(&mut validator, height);
+            let exited = Self::process_exit(&mut validator, height);
+            if exited {
+                info!("validator {} exit at height {}", validator.address, height);
+            }
+            if activated || exited {
+                self.set_item(&validator);
+            }
+        }
+    }
+
+    /// Request exit, validator leaves the set after the exit delay
+    pub fn exit(&mut self, addr: &Address) -> Result<(), Error> {
+        let mut validator = match self.get_validator(&addr) {
+            Some(i) => i,
+            None => return Err(InternalErrorKind::Other("validator not found".to_string()).into()),
+        };
+        if validator.is_exiting() {
+            return Err(InternalErrorKind::Other("validator is exiting".to_string()).into());
         }
-        validator.deposit_queue = validator.deposit_queue[..offset].to_vec();
+
+        validator.exit_height = epoch_start(epoch_of(self.interpreter.height()) + 1 + EXIT_DELAY_EPOCHS);
         self.set_item(&validator);
+        Ok(())
     }
 
-    pub fn exit(&mut self, addr: &Address) {
+    /// Unlock unbonded stake, validator is removed once its stake is withdrawn
+    pub fn withdraw(&mut self, addr: &Address) -> Result<(), Error> {
         let mut validator = match self.get_validator(&addr) {
             Some(i) => i,
-            None => return,
+            None => return Err(InternalErrorKind::Other("validator not found".to_string()).into()),
+        };
+        let height = self.interpreter.height();
+        let (ready, pending): (Vec<LockingBalance>, Vec<LockingBalance>) = validator.unlocked_queue
+            .iter().cloned().partition(|l| l.height <= height);
+        let amount: u128 = ready.iter().map(|l| l.amount).sum();
+        if amount == 0 {
+            return Err(InternalErrorKind::Other("no unbonded balance".to_string()).into());
+        }
+        validator.unlocked_queue = pending;
+
+        {
+            let mut state = Balance::from_state(self.interpreter.clone());
+            state.unlock_balance(*addr, amount);
+        }
+        // Kept while delegators still have stake bonded to it
+        if validator.is_exiting() && validator.balance == 0 && validator.unlocked_queue.is_empty()
+            && validator.delegated == 0 {
+            self.delete(addr);
+        } else {
+            self.set_item(&validator);
+        }
+        Ok(())
+    }
+
+    // Signature of header seal hash made by pubkey
+    fn verify_signed_header(pubkey: &[u8], signed: &SignedHeader) -> bool {
+        if pubkey.len() != 32 || signed.sign.msg != signed.header.seal_hash() {
+            return false;
+        }
+        // Malformed signature is rejected before verification
+        if signed.sign.signs.to_signature().is_err() {
+            return false;
+        }
+        Pubkey::from_bytes(pubkey).verify(&signed.sign.to_msg(), &signed.sign.signs).is_ok()
+    }
+
+    /// Slash and jail validator signing two different headers at the same height, reporter is rewarded
+    pub fn report_equivocation(&mut self, reporter: &Address, evidence: &MsgEquivocation) -> Result<(), Error> {
+        let mut validator = match self.get_validator(&evidence.offender) {
+            Some(i) => i,
+            None => return Err(InternalErrorKind::Other("validator not found".to_string()).into()),
+        };
+        if validator.jailed {
+            return Err(InternalErrorKind::Other("validator is jailed".to_string()).into());
+        }
+        let (first, second) = (&evidence.first, &evidence.second);
+        if first.header.height != second.header.height || first.header.height < validator.activate_height {
+            return Err(InternalErrorKind::Other("invalid evidence height".to_string()).into());
+        }
+        if first.header.seal_hash() == second.header.seal_hash() {
+            return Err(InternalErrorKind::Other("headers are not conflicting".to_string()).into());
+        }
+        if !Self::verify_signed_header(&validator.pubkey, first) || !Self::verify_signed_header(&validator.pubkey, second) {
+            return Err(InternalErrorKind::InvalidSignData.into());
+        }
+
+        let slashed = validator.effective_balance * SLASH_PERCENT / 100;
+        validator.effective_balance -= slashed;
+        // Genesis stake is not backed by locked balance
+        let burnt = std::cmp::min(slashed, validator.balance);
+        validator.balance -= burnt;
+        let reward = burnt * REPORTER_REWARD_PERCENT / 100;
+        validator.jailed = true;
+        if !validator.is_exiting() {
+            validator.exit_height = epoch_start(epoch_of(self.interpreter.height()) + 1);
+        }
+        self.set_item(&validator);
+
+        {
+            let mut state = Balance::from_state(self.interpreter.clone());
+            state.slash(evidence.offender, burnt);
+            state.add_balance(*reporter, reward);
+        }
+        info!("slash validator {} amount={} for equivocation at height {}, reporter={}",
+            evidence.offender, slashed, first.header.height, reporter);
+        Ok(())
+    }
+
+    pub fn get_delegation(&self, delegator: &Address, validator: &Address) -> Option<Delegation> {
+        self.delegations.get(&(*delegator, *validator))
+    }
+
+    // Empty delegation is removed from state
+    fn set_delegation(&mut self, delegation: &Delegation) {
+        let key = (delegation.delegator, delegation.validator);
+        if delegation.is_empty() {
+            self.delegations.remove(&key);
+        } else {
+            self.delegations.insert(&key, delegation);
+        }
+    }
+
+    /// Bond stake of delegator to validator, it counts in effective balance from next epoch
+    pub fn delegate(&mut self, delegator: &Address, addr: &Address, amount: u128) -> Result<(), Error> {
+        let validator = match self.get_validator(addr) {
+            Some(i) => i,
+            None => return Err(InternalErrorKind::Other("validator not found".to_string()).into()),
+        };
+        if validator.is_exiting() {
+            return Err(InternalErrorKind::Other("validator is exiting".to_string()).into());
+        }
+        if amount < MIN_DELEGATION {
+            return Err(InternalErrorKind::Other("delegation below minimum".to_string()).into());
+        }
+        if Balance::from_state(self.interpreter.clone()).balance(*delegator) < amount {
+            return Err(InternalErrorKind::BalanceNotEnough.into());
+        }
+
+        let mut delegation = self.get_delegation(delegator, addr).unwrap_or(Delegation {
+            delegator: *delegator,
+            validator: *addr,
+            amount: 0,
+            reward_index: validator.reward_per_stake,
+            rewards: 0,
+            unlocked_queue: Vec::new(),
+            pending: 0,
+        });
+        if delegation.pending == 0 {
+            self.pending_delegations(epoch_of(self.interpreter.height()) + 1).push(&(*delegator, *addr));
+        }
+        delegation.pending += amount;
+        self.set_delegation(&delegation);
+
+        {
+            let mut state = Balance::from_state(self.interpreter.clone());
+            state.lock_balance(*delegator, amount);
+        }
+        Ok(())
+    }
+
+    /// Start unbonding part of delegation, it is claimable after the unbonding period
+    pub fn undelegate(&mut self, delegator: &Address, addr: &Address, amount: u128) -> Result<(), Error> {
+        let mut delegation = match self.get_delegation(delegator, addr) {
+            Some(i) => i,
+            None => return Err(InternalErrorKind::Other("delegation not found".to_string()).into()),
+        };
+        if amount == 0 || amount > delegation.amount {
+            return Err(InternalErrorKind::BalanceNotEnough.into());
+        }
+        let mut validator = match self.get_validator(addr) {
+            Some(i) => i,
+            None => return Err(InternalErrorKind::Other("validator not found".to_string()).into()),
+        };
+        delegation.settle(&validator);
+        delegation.amount -= amount;
+        delegation.unlocked_queue.push(LockingBalance{
+            amount: amount,
+            height: self.interpreter.height() + UNBONDING_PERIOD,
+        });
+        validator.delegated -= amount;
+        // Exited validator has no effective balance left
+        validator.effective_balance = validator.effective_balance.saturating_sub(amount);
+        self.set_delegation(&delegation);
+        self.set_item(&validator);
+        Ok(())
+    }
+
+    /// Pay delegation rewards and unlock unbonded stake
+    pub fn claim(&mut self, delegator: &Address, addr: &Address) -> Result<(), Error> {
+        let mut delegation = match self.get_delegation(delegator, addr) {
+            Some(i) => i,
+            None => return Err(InternalErrorKind::Other("delegation not found".to_string()).into()),
         };
+        // Validator is removed only after all delegated stake is unbonded
+        if let Some(validator) = self.get_validator(addr) {
+            delegation.settle(&validator);
+        }
+
+        let height = self.interpreter.height();
+        let (ready, pending): (Vec<LockingBalance>, Vec<LockingBalance>) = delegation.unlocked_queue
+            .iter().cloned().partition(|l| l.height <= height);
+        let unlocked: u128 = ready.iter().map(|l| l.amount).sum();
+        let rewards = delegation.rewards;
+        if unlocked == 0 && rewards == 0 {
+            return Err(InternalErrorKind::Other("nothing to claim".to_string()).into());
+        }
+        delegation.unlocked_queue = pending;
+        delegation.rewards = 0;
+        self.set_delegation(&delegation);
+
+        {
+            let mut state = Balance::from_state(self.interpreter.clone());
+            state.unlock_balance(*delegator, unlocked);
+            state.add_balance(*delegator, rewards);
+        }
+        Ok(())
+    }
 
-        // mark the epoch in which validator exit make block
-        validator.exit_height = 0;
+    /// Split block reward of proposer by stake, delegators share is charged with commission.
+    /// Returns the part paid to proposer, delegators part is paid when claimed.
+    pub fn distribute_reward(&mut self, proposer: &Address, reward: u128) -> u128 {
+        let mut validator = match self.get_validator(proposer) {
+            Some(i) => i,
+            None => return reward,
+        };
+        if validator.delegated == 0 || validator.effective_balance == 0 {
+            return reward;
+        }
+        let delegated = std::cmp::min(validator.delegated, validator.effective_balance);
+        let share = reward * delegated / validator.effective_balance;
+        let commission = share * validator.commission as u128 / 100;
+        let to_delegators = share - commission;
+        validator.reward_per_stake += to_delegators * REWARD_SCALE / validator.delegated;
         self.set_item(&validator);
+        reward - to_delegators
     }
 
-    pub fn exec_validate(&mut self, addr: &Address, input: Vec<u8>) {
+    pub fn exec_validate(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
         let msg: MsgValidatorCreate = match bincode::deserialize(&input) {
             Ok(m) => m,
-            Err(_) => return,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
         };
-        self.validate(addr, msg.pubkey, msg.amount);
+        self.validate(addr, msg.pubkey, msg.amount, msg.commission)
     }
 
-    pub fn exec_deposit(&mut self, addr: &Address, input: Vec<u8>) {
+    pub fn exec_deposit(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
         let msg: u128 = match bincode::deserialize(&input) {
             Ok(m) => m,
-            Err(_) => return,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
         };
-        self.deposit(addr, msg);
+        self.deposit(addr, msg)
     }
 
     #[allow(unused_variables)]
-    pub fn exec_exit(&mut self, addr: &Address, input: Vec<u8>) {
-        self.exit(addr);
+    pub fn exec_exit(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        self.exit(addr)
+    }
+
+    #[allow(unused_variables)]
+    pub fn exec_withdraw(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        self.withdraw(addr)
+    }
+
+    pub fn exec_delegate(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: MsgDelegate = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        self.delegate(addr, &msg.validator, msg.amount)
+    }
+
+    pub fn exec_undelegate(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: MsgDelegate = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        self.undelegate(addr, &msg.validator, msg.amount)
+    }
+
+    pub fn exec_claim(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let validator: Address = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        self.claim(addr, &validator)
+    }
+
+    pub fn exec_report_equivocation(&mut self, addr: &Address, input: Vec<u8>) -> Result<(), Error> {
+        let msg: MsgEquivocation = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return Err(InternalErrorKind::Execute.into()),
+        };
+        self.report_equivocation(addr, &msg)
     }
 }
 
@@ -331,7 +641,20 @@ mod tests {
     use crate::state::{ArchiveDB, StateDB};
     use crate::types::Address;
     use crate::trie::NULL_ROOT;
-    use super::{Validator, Staking};
+    use ed25519::{generator::Generator, privkey::PrivKey};
+    use crate::balance::Balance;
+    use crate::block::{Header, VerificationItem};
+    use crate::transaction::staking_msg::{SignedHeader, MsgEquivocation};
+    use crate::reward::{IssuanceSchedule, Rewards};
+    use super::{Validator, Staking, EPOCH_LENGTH, UNBONDING_PERIOD, MIN_DELEGATION};
+
+    fn sign_header(key: &PrivKey, header: Header) -> SignedHeader {
+        let msg = header.seal_hash();
+        SignedHeader {
+            header: header,
+            sign: VerificationItem::new(msg, key.sign(msg.to_slice()).unwrap()),
+        }
+    }
 
     #[test]
     fn validator_insert() {
@@ -343,28 +666,18 @@ mod tests {
         let first_addr = Address::from_hex("0x0000000000000000000000000000000000000001").unwrap();
 
         let validator = Validator {
-            address: addr,
-            pubkey: Vec::new(),
             balance: 1,
-            effective_balance: 0,
             activate_height: 1,
-            exit_height: 0,
-            deposit_queue: Vec::new(),
-            unlocked_queue: Vec::new(),
+            ..Validator::create(addr)
         };
 
         let mut stake = Staking::new(Interpreter::new(state_db.clone()));
         stake.insert(&validator);
 
         let first = Validator {
-            address: first_addr,
-            pubkey: Vec::new(),
             balance: 1,
-            effective_balance: 0,
             activate_height: 1,
-            exit_height: 0,
-            deposit_queue: Vec::new(),
-            unlocked_queue: Vec::new(),
+            ..Validator::create(first_addr)
         };
 
         stake.insert(&first);
@@ -382,14 +695,9 @@ mod tests {
         let addr = Address::default();
 
         let validator = Validator {
-            address: addr,
-            pubkey: Vec::new(),
             balance: 1,
-            effective_balance: 0,
-            exit_height: 0,
             activate_height: 1,
-            deposit_queue: Vec::new(),
-            unlocked_queue: Vec::new(),
+            ..Validator::create(addr)
         };
 
         let mut stake = Staking::new(Interpreter::new(state_db.clone()));
@@ -410,25 +718,15 @@ mod tests {
         let addr_1 = Address::from_hex("0x0000000000000000000000000000000000000001").unwrap();
 
         let validator = Validator {
-            address: addr,
-            pubkey: Vec::new(),
             balance: 1,
-            effective_balance: 0,
             activate_height: 1,
-            exit_height: 0,
-            deposit_queue: Vec::new(),
-            unlocked_queue: Vec::new(),
+            ..Validator::create(addr)
         };
 
         let validator_1 = Validator {
-            address: addr_1,
-            pubkey: Vec::new(),
             balance: 1,
-            effective_balance: 0,
             activate_height: 1,
-            exit_height: 0,
-            deposit_queue: Vec::new(),
-            unlocked_queue: Vec::new(),
+            ..Validator::create(addr_1)
         };
 
         let mut stake = Staking::new(Interpreter::new(state_db.clone()));
@@ -445,4 +743,211 @@ mod tests {
         assert_eq!(stake.get_validator(&addr), None);
         assert_eq!(stake.get_validator(&addr_1), None);
     }
+
+    #[test]
+    fn validator_lifecycle() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db.clone());
+        let addr = Address([1; 20]);
+        Balance::from_state(runner.clone()).add_balance(addr, 1000);
+
+        runner.set_height(1);
+        Staking::from_state(runner.clone()).validate(&addr, Vec::new(), 600, 0).unwrap();
+        Staking::from_state(runner.clone()).deposit(&addr, 100).unwrap();
+        let validator = Staking::from_state(runner.clone()).get_validator(&addr).unwrap();
+        assert_eq!(validator.effective_balance, 0);
+        assert_eq!(validator.activate_height, EPOCH_LENGTH);
+        assert!(!validator.is_active(1));
+        assert_eq!(Balance::from_state(runner.clone()).locked(addr), 700);
+
+        // Deposit of current epoch waits for the next one
+        runner.set_height(EPOCH_LENGTH + 1);
+        Staking::from_state(runner.clone()).deposit(&addr, 200).unwrap();
+        runner.set_height(EPOCH_LENGTH);
+        Staking::from_state(runner.clone()).on_epoch();
+        let validator = Staking::from_state(runner.clone()).get_validator(&addr).unwrap();
+        assert_eq!(validator.effective_balance, 700);
+        assert_eq!(validator.deposit_queue.len(), 1);
+        assert!(validator.is_active(EPOCH_LENGTH));
+
+        runner.set_height(EPOCH_LENGTH + 2);
+        let mut staking = Staking::from_state(runner.clone());
+        staking.exit(&addr).unwrap();
+        assert!(staking.exit(&addr).is_err());
+        assert!(staking.deposit(&addr, 1).is_err());
+        let exit_height = staking.get_validator(&addr).unwrap().exit_height;
+        assert_eq!(exit_height, 3 * EPOCH_LENGTH);
+
+        runner.set_height(2 * EPOCH_LENGTH);
+        Staking::from_state(runner.clone()).on_epoch();
+        let validator = Staking::from_state(runner.clone()).get_validator(&addr).unwrap();
+        assert_eq!(validator.effective_balance, 900);
+        assert!(validator.is_active(2 * EPOCH_LENGTH));
+
+        runner.set_height(exit_height);
+        Staking::from_state(runner.clone()).on_epoch();
+        let validator = Staking::from_state(runner.clone()).get_validator(&addr).unwrap();
+        assert!(!validator.is_active(exit_height));
+        assert_eq!(validator.effective_balance, 0);
+        assert_eq!(validator.unlocked_queue[0].amount, 900);
+
+        // Unbonding period not passed
+        runner.set_height(exit_height + UNBONDING_PERIOD - 1);
+        assert!(Staking::from_state(runner.clone()).withdraw(&addr).is_err());
+
+        runner.set_height(exit_height + UNBONDING_PERIOD);
+        Staking::from_state(runner.clone()).withdraw(&addr).unwrap();
+        assert_eq!(Staking::from_state(runner.clone()).get_validator(&addr), None);
+        assert_eq!(Balance::from_state(runner.clone()).locked(addr), 0);
+        assert_eq!(Balance::from_state(runner.clone()).balance(addr), 1000);
+    }
+
+    #[test]
+    fn validator_equivocation() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db.clone());
+        let (key, pk) = Generator::default().new();
+        let (other, _) = Generator::default().new();
+        let offender = Address([1; 20]);
+        let reporter = Address([2; 20]);
+        Balance::from_state(runner.clone()).add_balance(offender, 1000);
+        Staking::from_state(runner.clone()).validate(&offender, pk.to_bytes(), 1000, 0).unwrap();
+        runner.set_height(EPOCH_LENGTH);
+        Staking::from_state(runner.clone()).on_epoch();
+
+        let header = Header {
+            height: EPOCH_LENGTH + 1,
+            ..Default::default()
+        };
+        let conflict = Header {
+            time: 1,
+            ..header
+        };
+        let mut evidence = MsgEquivocation {
+            offender: offender,
+            first: sign_header(&key, header),
+            second: sign_header(&key, header),
+        };
+        // Same header signed twice is not equivocation
+        assert!(Staking::from_state(runner.clone()).report_equivocation(&reporter, &evidence).is_err());
+
+        evidence.second = sign_header(&other, conflict);
+        assert!(Staking::from_state(runner.clone()).report_equivocation(&reporter, &evidence).is_err());
+
+        evidence.second = sign_header(&key, conflict);
+        runner.set_height(EPOCH_LENGTH + 2);
+        Staking::from_state(runner.clone()).report_equivocation(&reporter, &evidence).unwrap();
+        let validator = Staking::from_state(runner.clone()).get_validator(&offender).unwrap();
+        assert!(validator.jailed);
+        assert!(!validator.is_active(EPOCH_LENGTH + 2));
+        assert_eq!(validator.effective_balance, 900);
+        assert_eq!(validator.exit_height, 2 * EPOCH_LENGTH);
+        assert_eq!(Balance::from_state(runner.clone()).locked(offender), 900);
+        assert_eq!(Balance::from_state(runner.clone()).balance(reporter), 10);
+
+        // Evidence is not counted twice
+        assert!(Staking::from_state(runner.clone()).report_equivocation(&reporter, &evidence).is_err());
+    }
+
+    #[test]
+    fn delegation_rewards() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db.clone());
+        runner.set_issuance(IssuanceSchedule {
+            initial_reward: 100,
+            halving_interval: 0,
+            max_issuance: 1000000,
+        });
+        let (validator, delegator) = (Address([1; 20]), Address([2; 20]));
+        Balance::from_state(runner.clone()).add_balance(validator, 1000);
+        Balance::from_state(runner.clone()).add_balance(delegator, 1000);
+
+        runner.set_height(1);
+        assert!(Staking::from_state(runner.clone()).validate(&validator, Vec::new(), 1000, 101).is_err());
+        Staking::from_state(runner.clone()).validate(&validator, Vec::new(), 1000, 20).unwrap();
+        runner.set_height(EPOCH_LENGTH);
+        Staking::from_state(runner.clone()).on_epoch();
+
+        let mut staking = Staking::from_state(runner.clone());
+        assert!(staking.delegate(&delegator, &validator, 2000).is_err());
+        assert!(staking.delegate(&delegator, &validator, MIN_DELEGATION - 1).is_err());
+        staking.delegate(&delegator, &validator, 1000).unwrap();
+        let item = staking.get_validator(&validator).unwrap();
+        assert_eq!((item.effective_balance, item.delegated), (1000, 0));
+        assert_eq!(staking.pending_delegations(2).len(), 1);
+        assert_eq!(staking.get_delegation(&delegator, &validator).unwrap().pending, 1000);
+        assert_eq!(Balance::from_state(runner.clone()).locked(delegator), 1000);
+
+        // Delegation waits for next epoch, reward of current one goes to validator
+        runner.set_height(EPOCH_LENGTH + 1);
+        Rewards::from_state(runner.clone()).reward_block(EPOCH_LENGTH + 1, &validator, 0);
+        assert_eq!(Balance::from_state(runner.clone()).balance(validator), 100);
+        assert!(Staking::from_state(runner.clone()).undelegate(&delegator, &validator, 1000).is_err());
+
+        runner.set_height(2 * EPOCH_LENGTH);
+        Staking::from_state(runner.clone()).on_epoch();
+        let item = Staking::from_state(runner.clone()).get_validator(&validator).unwrap();
+        assert_eq!((item.effective_balance, item.delegated), (2000, 1000));
+        assert!(Staking::from_state(runner.clone()).pending_delegations(2).is_empty());
+        let delegation = Staking::from_state(runner.clone()).get_delegation(&delegator, &validator).unwrap();
+        assert_eq!((delegation.amount, delegation.pending, delegation.rewards), (1000, 0, 0));
+
+        // Half of reward belongs to delegators, validator takes 20 percent of it
+        runner.set_height(2 * EPOCH_LENGTH + 1);
+        Rewards::from_state(runner.clone()).reward_block(2 * EPOCH_LENGTH + 1, &validator, 0);
+        assert_eq!(Balance::from_state(runner.clone()).balance(validator), 160);
+
+        Staking::from_state(runner.clone()).undelegate(&delegator, &validator, 1000).unwrap();
+        let item = Staking::from_state(runner.clone()).get_validator(&validator).unwrap();
+        assert_eq!((item.effective_balance, item.delegated), (1000, 0));
+        // Rewards are claimable right away, stake after unbonding
+        Staking::from_state(runner.clone()).claim(&delegator, &validator).unwrap();
+        assert_eq!(Balance::from_state(runner.clone()).balance(delegator), 40);
+        assert!(Staking::from_state(runner.clone()).claim(&delegator, &validator).is_err());
+
+        runner.set_height(2 * EPOCH_LENGTH + 1 + UNBONDING_PERIOD);
+        Staking::from_state(runner.clone()).claim(&delegator, &validator).unwrap();
+        assert_eq!(Balance::from_state(runner.clone()).balance(delegator), 1040);
+        assert_eq!(Balance::from_state(runner.clone()).locked(delegator), 0);
+        assert!(Staking::from_state(runner.clone()).get_delegation(&delegator, &validator).is_none());
+    }
+
+    #[test]
+    fn delegation_pending() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db.clone());
+        let (validator, delegator) = (Address([1; 20]), Address([2; 20]));
+        Balance::from_state(runner.clone()).add_balance(validator, 1000);
+        Balance::from_state(runner.clone()).add_balance(delegator, 10000);
+
+        runner.set_height(1);
+        Staking::from_state(runner.clone()).validate(&validator, Vec::new(), 1000, 0).unwrap();
+        let before = Staking::from_state(runner.clone()).get_validator(&validator).unwrap();
+
+        // Repeated delegations add to one pending entry and leave validator record as is
+        let mut staking = Staking::from_state(runner.clone());
+        for _ in 0..5 {
+            staking.delegate(&delegator, &validator, MIN_DELEGATION).unwrap();
+        }
+        assert_eq!(staking.get_validator(&validator).unwrap(), before);
+        assert_eq!(staking.pending_delegations(1).len(), 1);
+        assert_eq!(staking.get_delegation(&delegator, &validator).unwrap().pending, 5 * MIN_DELEGATION);
+
+        runner.set_height(EPOCH_LENGTH);
+        Staking::from_state(runner.clone()).on_epoch();
+        let staking = Staking::from_state(runner.clone());
+        let item = staking.get_validator(&validator).unwrap();
+        assert_eq!((item.effective_balance, item.delegated), (1000 + 5 * MIN_DELEGATION, 5 * MIN_DELEGATION));
+        assert!(staking.pending_delegations(1).is_empty());
+        let delegation = staking.get_delegation(&delegator, &validator).unwrap();
+        assert_eq!((delegation.amount, delegation.pending), (5 * MIN_DELEGATION, 0));
+    }
 }
@@ -14,20 +14,185 @@
 // You should have received a copy of the GNU General Public License
 // along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 use serde::{Serialize, Deserialize};
 use bincode;
 use hash_db::{HashDB, HashDBRef, AsHashDB, Prefix};
-use trie_db::{DBValue, Trie, TrieMut};
-use map_store::KVDB;
+use trie_db::{DBValue, Trie, TrieMut, Recorder};
+use map_store::{KVDB, Column};
+use errors::{Error, InternalErrorKind};
 use crate::types::Hash;
 use crate::trie::{MemoryDB, EMPTY_TRIE, Blake2Hasher, TrieDBMut, TrieDB, NULL_ROOT};
+use crate::cache::LruCache;
+
+/// Column of trie nodes and journals
+const STATE_COL: Column = Column::State;
+const PRUNING_MODE_KEY: &[u8] = b"pruning-mode";
+const PRUNED_HEIGHT_KEY: &[u8] = b"pruned-height";
+const JOURNAL_PREFIX: &[u8] = b"journal";
+/// Default number of trie nodes and of values kept in state cache
+pub const DEFAULT_CACHE_SIZE: usize = 64 * 1024;
+
+/// How trie nodes of old states are kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PruningMode {
+    /// Keep nodes of all states
+    Archive,
+    /// Keep states of the recent number of blocks
+    Recent(u64),
+}
+
+impl Default for PruningMode {
+    fn default() -> Self {
+        PruningMode::Archive
+    }
+}
+
+/// Trie node reference changes made by executing a block
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StateJournal {
+    pub inserted: Vec<(Hash, u32)>,
+    pub removed: Vec<(Hash, u32)>,
+    /// Nodes written and dropped again in the same execution
+    pub transient: Vec<Hash>,
+}
+
+fn journal_key(height: u64, block: &Hash) -> Vec<u8> {
+    let mut key = journal_index_key(height);
+    key.extend_from_slice(block.as_bytes());
+    key
+}
+
+fn journal_index_key(height: u64) -> Vec<u8> {
+    let mut key = JOURNAL_PREFIX.to_vec();
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+fn load_payload(backend: &dyn KVDB, key: &Hash) -> Option<Payload> {
+    backend.get(STATE_COL, key.as_bytes()).expect("get diskdb payload failed")
+        .map(|data| bincode::deserialize(&data).unwrap())
+}
+
+// Change reference count of node, drop it when no state refers to it. True if dropped.
+fn add_ref(backend: &mut dyn KVDB, key: &Hash, delta: i64) -> bool {
+    let payload = match load_payload(backend, key) {
+        Some(p) => p,
+        None => {
+            warn!("missing trie node {} on reference update", key);
+            return false;
+        }
+    };
+    let count = payload.count as i64 + delta;
+    if count <= 0 {
+        if count < 0 {
+            warn!("negative count of trie node {}", key);
+        }
+        backend.remove(STATE_COL, key.as_bytes()).expect("remove backend");
+        true
+    } else {
+        let encoded = bincode::serialize(&Payload::new(count as u32, payload.value)).unwrap();
+        backend.put(STATE_COL, key.as_bytes(), &encoded).expect("write backend");
+        false
+    }
+}
+
+/// Hit and miss counters of state cache
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub node_hits: u64,
+    pub node_misses: u64,
+    pub value_hits: u64,
+    pub value_misses: u64,
+    pub nodes: usize,
+    pub values: usize,
+}
+
+/// Trie nodes and committed values read from backend, shared by all states of a db.
+/// Both are immutable for their key, a node only leaves the cache when it is pruned.
+pub struct StateCache {
+    nodes: LruCache<Hash, DBValue>,
+    // Value of key in state root
+    values: LruCache<(Hash, Hash), Option<Vec<u8>>>,
+    // Height and block of state roots under pruning, to evict values of pruned roots only
+    roots: HashMap<Hash, (u64, Hash)>,
+    stats: CacheStats,
+}
+
+impl StateCache {
+    pub fn new(size: usize) -> Self {
+        StateCache {
+            nodes: LruCache::new(size),
+            values: LruCache::new(size),
+            roots: HashMap::new(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn resize(&mut self, size: usize) {
+        self.nodes.resize(size);
+        self.values.resize(size);
+    }
+
+    fn node(&mut self, key: &Hash) -> Option<DBValue> {
+        match self.nodes.get(key) {
+            Some(v) => {
+                self.stats.node_hits += 1;
+                Some(v.clone())
+            }
+            None => {
+                self.stats.node_misses += 1;
+                None
+            }
+        }
+    }
+
+    fn value(&mut self, root: &Hash, key: &Hash) -> Option<Option<Vec<u8>>> {
+        match self.values.get(&(*root, *key)) {
+            Some(v) => {
+                self.stats.value_hits += 1;
+                Some(v.clone())
+            }
+            None => {
+                self.stats.value_misses += 1;
+                None
+            }
+        }
+    }
+
+    // Drop values of roots no longer readable once blocks of the height leave history,
+    // that is roots below it and roots of side blocks at it
+    fn evict_pruned(&mut self, height: u64, canonical: Option<Hash>) {
+        let pruned: HashSet<Hash> = self.roots.iter()
+            .filter(|(_, (h, block))| *h < height || (*h == height && Some(*block) != canonical))
+            .map(|(root, _)| *root)
+            .collect();
+        if pruned.is_empty() {
+            return;
+        }
+        for root in pruned.iter() {
+            self.roots.remove(root);
+        }
+        self.values.retain(|(root, _)| !pruned.contains(root));
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            nodes: self.nodes.len(),
+            values: self.values.len(),
+            ..self.stats
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct ArchiveDB {
     backend: Arc<RwLock<dyn KVDB>>,
     cached: MemoryDB,
+    pruning: PruningMode,
+    journal: HashMap<Hash, i32>,
+    cache: Arc<Mutex<StateCache>>,
 }
 
 impl AsHashDB<Blake2Hasher, DBValue> for ArchiveDB {
@@ -43,27 +208,196 @@ impl AsHashDB<Blake2Hasher, DBValue> for ArchiveDB {
 impl ArchiveDB {
     /// Create a storage backend of trie structure along with memory caching
     pub fn new(backend: Arc<RwLock<dyn KVDB>>) -> Self {
+        ArchiveDB::with_pruning(backend, PruningMode::Archive)
+    }
+
+    pub fn with_pruning(backend: Arc<RwLock<dyn KVDB>>, pruning: PruningMode) -> Self {
         ArchiveDB {
             backend: backend,
             cached: MemoryDB::new(EMPTY_TRIE),
+            pruning: pruning,
+            journal: HashMap::new(),
+            cache: Arc::new(Mutex::new(StateCache::new(DEFAULT_CACHE_SIZE))),
+        }
+    }
+
+    pub fn pruning(&self) -> PruningMode {
+        self.pruning
+    }
+
+    /// Change number of entries of cache shared by clones of this db
+    pub fn set_cache_size(&self, size: usize) {
+        self.cache.lock().unwrap().resize(size);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+
+    fn cached_value(&self, root: &Hash, key: &Hash) -> Option<Option<Vec<u8>>> {
+        self.cache.lock().unwrap().value(root, key)
+    }
+
+    fn cache_value(&self, root: Hash, key: Hash, value: Option<Vec<u8>>) {
+        self.cache.lock().unwrap().values.insert((root, key), value);
+    }
+
+    /// Record pruning mode in backend db, node encoding of existing db could not be switched.
+    /// Existing db without the record was written in archive mode.
+    pub fn check_pruning(&self, existing: bool) -> Result<(), Error> {
+        let mut backend = self.backend.write().unwrap();
+        let stored = match backend.get(STATE_COL, PRUNING_MODE_KEY).expect("get pruning mode") {
+            Some(data) => Some(bincode::deserialize(&data).unwrap()),
+            None if existing => Some(PruningMode::Archive),
+            None => None,
+        };
+        match (stored, self.pruning) {
+            (Some(PruningMode::Archive), PruningMode::Recent(_)) | (Some(PruningMode::Recent(_)), PruningMode::Archive) => {
+                return Err(InternalErrorKind::Other(format!("database is in pruning mode {:?}", stored.unwrap())).into());
+            }
+            _ => {}
         }
+        let encoded = bincode::serialize(&self.pruning).unwrap();
+        backend.put(STATE_COL, PRUNING_MODE_KEY, &encoded).expect("write pruning mode");
+        Ok(())
     }
 
     fn payload(&self, key: &Hash) -> Option<DBValue> {
+        if let Some(value) = self.cache.lock().unwrap().node(key) {
+            return Some(value);
+        }
         trace!("load payload {:}", key);
-        self.backend.read().unwrap().get(key.as_bytes()).expect("get diskdb payload failed")
+        let value = {
+            let backend = self.backend.read().unwrap();
+            match self.pruning {
+                PruningMode::Archive => backend.get(STATE_COL, key.as_bytes()).expect("get diskdb payload failed"),
+                PruningMode::Recent(_) => load_payload(&*backend, key).map(|p| p.value),
+            }
+        };
+        if let Some(ref v) = value {
+            self.cache.lock().unwrap().nodes.insert(*key, v.clone());
+        }
+        value
     }
 
     /// Write memory changes to backend db
     pub fn commit(&mut self) {
-        for i in self.cached.drain() {
-            let (key, (value, rc)) = i;
+        match self.pruning {
+            PruningMode::Archive => {
+                for i in self.cached.drain() {
+                    let (key, (value, rc)) = i;
+                    if rc > 0 {
+                        let mut backend = self.backend.write().unwrap();
+                        trace!("db set key={:}, value={:x?}", key, value);
+                        backend.put(STATE_COL, key.as_bytes(), &value).expect("wirte backend");
+                    }
+                }
+            }
+            PruningMode::Recent(_) => {
+                for i in self.cached.drain() {
+                    let (key, (value, rc)) = i;
+                    if rc == 0 {
+                        continue;
+                    }
+                    *self.journal.entry(key).or_insert(0) += rc;
+                    let mut backend = self.backend.write().unwrap();
+                    if rc > 0 && load_payload(&*backend, &key).is_none() {
+                        // Node is unreferenced until journal of block applied
+                        let encoded = bincode::serialize(&Payload::new(0, value)).unwrap();
+                        backend.put(STATE_COL, key.as_bytes(), &encoded).expect("write backend");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Take node reference changes of commits since last call
+    pub fn take_journal(&mut self) -> StateJournal {
+        let mut journal = StateJournal::default();
+        for (key, rc) in self.journal.drain() {
             if rc > 0 {
-                let mut backend = self.backend.write().unwrap();
-                trace!("db set key={:}, value={:x?}", key, value);
-                backend.put(key.as_bytes(), &value).expect("wirte backend");
+                journal.inserted.push((key, rc as u32));
+            } else if rc < 0 {
+                journal.removed.push((key, (-rc) as u32));
+            } else {
+                journal.transient.push(key);
+            }
+        }
+        journal
+    }
+
+    /// Reference the nodes inserted by block of the state root, removals are kept until the block is pruned
+    pub fn apply_journal(&mut self, height: u64, block: &Hash, root: &Hash, journal: StateJournal) {
+        if self.pruning == PruningMode::Archive {
+            return;
+        }
+        let mut backend = self.backend.write().unwrap();
+        let mut cache = self.cache.lock().unwrap();
+        cache.roots.insert(*root, (height, *block));
+        for (key, count) in journal.inserted.iter() {
+            if add_ref(&mut *backend, key, *count as i64) {
+                cache.nodes.remove(key);
+            }
+        }
+        for key in journal.transient.iter() {
+            if load_payload(&*backend, key).map_or(false, |p| p.count == 0) {
+                backend.remove(STATE_COL, key.as_bytes()).expect("remove backend");
+                cache.nodes.remove(key);
+            }
+        }
+
+        let record = StateJournal {
+            inserted: journal.inserted,
+            removed: journal.removed,
+            transient: Vec::new(),
+        };
+        backend.put(STATE_COL, &journal_key(height, block), &bincode::serialize(&record).unwrap()).expect("write journal");
+
+        let index_key = journal_index_key(height);
+        let mut blocks: Vec<Hash> = match backend.get(STATE_COL, &index_key).expect("get journal index") {
+            Some(data) => bincode::deserialize(&data).unwrap(),
+            None => Vec::new(),
+        };
+        if !blocks.contains(block) {
+            blocks.push(*block);
+        }
+        backend.put(STATE_COL, &index_key, &bincode::serialize(&blocks).unwrap()).expect("write journal index");
+    }
+
+    /// Height of the last pruned blocks
+    pub fn pruned_height(&self) -> Option<u64> {
+        self.backend.read().unwrap().get(STATE_COL, PRUNED_HEIGHT_KEY).expect("get pruned height")
+            .map(|data| bincode::deserialize(&data).unwrap())
+    }
+
+    /// Drop nodes no longer referenced once blocks of the height leave history.
+    /// Canonical block releases the parent nodes it replaced, side blocks release their own.
+    pub fn prune(&mut self, height: u64, canonical: Option<Hash>) {
+        let mut backend = self.backend.write().unwrap();
+        let index_key = journal_index_key(height);
+        let blocks: Vec<Hash> = match backend.get(STATE_COL, &index_key).expect("get journal index") {
+            Some(data) => bincode::deserialize(&data).unwrap(),
+            None => Vec::new(),
+        };
+
+        for block in blocks {
+            let key = journal_key(height, &block);
+            let record: StateJournal = match backend.get(STATE_COL, &key).expect("get journal") {
+                Some(data) => bincode::deserialize(&data).unwrap(),
+                None => continue,
+            };
+            let released = if Some(block) == canonical { record.removed } else { record.inserted };
+            for (node, count) in released.iter() {
+                if add_ref(&mut *backend, node, -(*count as i64)) {
+                    self.cache.lock().unwrap().nodes.remove(node);
+                }
             }
+            backend.remove(STATE_COL, &key).expect("remove journal");
         }
+        backend.remove(STATE_COL, &index_key).expect("remove journal index");
+        self.cache.lock().unwrap().evict_pruned(height, canonical);
+        backend.put(STATE_COL, PRUNED_HEIGHT_KEY, &bincode::serialize(&height).unwrap()).expect("write pruned height");
+        debug!("prune state height={}", height);
     }
 }
 
@@ -124,7 +458,7 @@ impl CachingDB {
     }
 
     fn payload(&self, key: &Hash) -> Option<Payload> {
-        if let Some(data) = self.backend.read().unwrap().get(key.as_bytes()).unwrap() {
+        if let Some(data) = self.backend.read().unwrap().get(STATE_COL, key.as_bytes()).unwrap() {
             let value: Payload = bincode::deserialize(&data.as_slice()).unwrap();
             Some(value)
         } else {
@@ -145,7 +479,7 @@ impl CachingDB {
                         }
                         let encoded = bincode::serialize(&Payload::new(total_rc as u32, x.value)).unwrap();
                         let mut backend = self.backend.write().unwrap();
-                        backend.put(key.as_bytes(), &encoded).expect("wirte backend");
+                        backend.put(STATE_COL, key.as_bytes(), &encoded).expect("wirte backend");
                     }
                     None => {
                         if rc < 0 {
@@ -153,7 +487,7 @@ impl CachingDB {
                         }
                         let encoded = bincode::serialize(&Payload::new(rc as u32, value)).unwrap();
                         let mut backend = self.backend.write().unwrap();
-                        backend.put(key.as_bytes(), &encoded).expect("write backend");
+                        backend.put(STATE_COL, key.as_bytes(), &encoded).expect("write backend");
                     }
                 };
             }
@@ -247,6 +581,7 @@ pub struct StateDB {
     db: ArchiveDB,
     state_root: Hash,
     local_changes: HashMap<Hash, Option<Vec<u8>>>,
+    checkpoints: Vec<HashMap<Hash, Option<Vec<u8>>>>,
 }
 
 impl StateDB {
@@ -255,6 +590,7 @@ impl StateDB {
             db: db.clone(),
             state_root: NULL_ROOT,
             local_changes: HashMap::new(),
+            checkpoints: Vec::new(),
         }
     }
 
@@ -263,6 +599,7 @@ impl StateDB {
             db: db.clone(),
             state_root: root,
             local_changes: HashMap::new(),
+            checkpoints: Vec::new(),
         }
     }
 
@@ -278,17 +615,71 @@ impl StateDB {
         if let Some(data) = self.local_changes.get(key) {
             return data.as_ref().map(|d| d.clone());
         }
+        if let Some(value) = self.db.cached_value(&self.state_root, key) {
+            return value;
+        }
         let t = match TrieDB::new(&self.db, &self.state_root) {
             Ok(trie) => trie,
             Err(_) => return None,
         };
-        t.get(key.as_bytes()).expect("state get key")
+        let value = t.get(key.as_bytes()).expect("state get key");
+        self.db.cache_value(self.state_root, *key, value.clone());
+        value
     }
 
     pub fn remove_storage(&mut self, key: Hash) {
         self.local_changes.insert(key, None);
     }
 
+    /// Mark current changes so the following ones could be reverted
+    pub fn checkpoint(&mut self) {
+        self.checkpoints.push(self.local_changes.clone());
+    }
+
+    /// Keep the changes made since last checkpoint
+    pub fn discard_checkpoint(&mut self) {
+        self.checkpoints.pop();
+    }
+
+    /// Drop the changes made since last checkpoint
+    pub fn revert_checkpoint(&mut self) {
+        if let Some(changes) = self.checkpoints.pop() {
+            self.local_changes = changes;
+        }
+    }
+
+    /// Trie nodes on the path of key in committed state, proving the value or its absence
+    pub fn prove(&self, key: &Hash) -> Vec<Vec<u8>> {
+        let t = match TrieDB::new(&self.db, &self.state_root) {
+            Ok(trie) => trie,
+            Err(_) => return Vec::new(),
+        };
+        let mut recorder = Recorder::new();
+        if let Err(e) = t.get_with(key.as_bytes(), &mut recorder) {
+            warn!("prove key={} err={:?}", key, e);
+        }
+        recorder.drain().into_iter().map(|r| r.data).collect()
+    }
+
+    /// Visit every key and value in committed state, in key order
+    pub fn for_each<F: FnMut(Hash, Vec<u8>)>(&self, mut f: F) -> Result<(), Error> {
+        let t = TrieDB::new(&self.db, &self.state_root)
+            .map_err(|e| InternalErrorKind::Other(format!("open trie {:?}", e)))?;
+        let iter = t.iter()
+            .map_err(|e| InternalErrorKind::Other(format!("trie iter {:?}", e)))?;
+        for item in iter {
+            let (key, value) = item
+                .map_err(|e| InternalErrorKind::Other(format!("trie iter {:?}", e)))?;
+            f(Hash::from_bytes(&key), value);
+        }
+        Ok(())
+    }
+
+    /// Node reference changes made by commits on this state
+    pub fn take_journal(&mut self) -> StateJournal {
+        self.db.take_journal()
+    }
+
     pub fn commit(&mut self) {
         {
             let mut t = TrieDBMut::from_existing(&mut self.db, &mut self.state_root).expect("open trie error");
@@ -306,6 +697,19 @@ impl StateDB {
     }
 }
 
+/// Check proof nodes against state root and return value of key, None if key is absent
+pub fn verify_proof(root: &Hash, key: &Hash, proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>, Error> {
+    let mut db = MemoryDB::new(EMPTY_TRIE);
+    for node in proof {
+        db.insert(hash_db::EMPTY_PREFIX, node);
+    }
+    let t = TrieDB::new(&db, root)
+        .map_err(|e| InternalErrorKind::Other(format!("invalid proof {:?}", e)))?;
+    let value = t.get(key.as_bytes())
+        .map_err(|e| InternalErrorKind::Other(format!("invalid proof {:?}", e)))?;
+    Ok(value)
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::{Arc, RwLock};
@@ -315,7 +719,7 @@ mod tests {
     use trie_db::TrieMut;
     use crate::types::Hash;
     use crate::trie::{TrieDBMut, TrieDB, Blake2Hasher, EMPTY_TRIE};
-    use super::{CachingDB, ArchiveDB, StateDB};
+    use super::{CachingDB, ArchiveDB, StateDB, PruningMode, verify_proof};
 
     #[test]
     fn test_caching_ref() {
@@ -432,4 +836,149 @@ mod tests {
             assert_eq!(state.get_storage(&key_null).unwrap(), b"foo");
         }
     }
+
+    #[test]
+    fn test_state_checkpoint() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let mut state = StateDB::new(&ArchiveDB::new(Arc::clone(&backend)));
+        let key_a = Hash([1u8; 32]);
+        let key_b = Hash([2u8; 32]);
+
+        state.set_storage(key_a, b"foo");
+        state.checkpoint();
+        state.set_storage(key_a, b"bar");
+        state.set_storage(key_b, b"bar");
+        state.revert_checkpoint();
+        assert_eq!(state.get_storage(&key_a).unwrap(), b"foo");
+        assert!(state.get_storage(&key_b).is_none());
+
+        state.checkpoint();
+        state.set_storage(key_b, b"bar");
+        state.discard_checkpoint();
+        state.commit();
+        assert_eq!(state.get_storage(&key_b).unwrap(), b"bar");
+    }
+
+    #[test]
+    fn test_state_pruning() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let mut db = ArchiveDB::with_pruning(Arc::clone(&backend), PruningMode::Recent(1));
+        assert!(db.check_pruning(false).is_ok());
+        assert!(ArchiveDB::new(Arc::clone(&backend)).check_pruning(true).is_err());
+
+        let key_a = Hash([1u8; 32]);
+        let key_b = Hash([2u8; 32]);
+        let mut state = StateDB::new(&db);
+        state.set_storage(key_a, b"foo");
+        state.commit();
+        let root1 = state.root();
+        db.apply_journal(1, &Hash([1u8; 32]), &root1, state.take_journal());
+
+        let mut canonical = StateDB::from_existing(&db, root1);
+        canonical.set_storage(key_a, b"bar");
+        canonical.commit();
+        let root2 = canonical.root();
+        db.apply_journal(2, &Hash([2u8; 32]), &root2, canonical.take_journal());
+
+        let mut side = StateDB::from_existing(&db, root1);
+        side.set_storage(key_b, b"baz");
+        side.commit();
+        let root_side = side.root();
+        db.apply_journal(2, &Hash([3u8; 32]), &root_side, side.take_journal());
+
+        // History of both blocks at height 2 is kept
+        assert_eq!(StateDB::from_existing(&db, root1).get_storage(&key_a).unwrap(), b"foo");
+        assert_eq!(StateDB::from_existing(&db, root_side).get_storage(&key_b).unwrap(), b"baz");
+
+        db.prune(1, Some(Hash([1u8; 32])));
+        db.prune(2, Some(Hash([2u8; 32])));
+        assert_eq!(db.pruned_height(), Some(2));
+        assert!(StateDB::from_existing(&db, root1).get_storage(&key_a).is_none());
+        assert!(StateDB::from_existing(&db, root_side).get_storage(&key_b).is_none());
+        assert_eq!(StateDB::from_existing(&db, root2).get_storage(&key_a).unwrap(), b"bar");
+    }
+
+    #[test]
+    fn test_prune_keeps_head_values() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let mut db = ArchiveDB::with_pruning(Arc::clone(&backend), PruningMode::Recent(1));
+        let key = Hash([1u8; 32]);
+        let mut roots = Vec::new();
+        let mut state = StateDB::new(&db);
+        for height in 1..4u8 {
+            state.set_storage(key, &[height; 40]);
+            state.commit();
+            db.apply_journal(height as u64, &Hash([height; 32]), &state.root(), state.take_journal());
+            roots.push(state.root());
+        }
+        for root in roots.iter() {
+            StateDB::from_existing(&db, *root).get_storage(&key);
+        }
+        assert_eq!(db.cache_stats().values, 3);
+
+        // Value of head stays cached, values of pruned roots are dropped
+        db.prune(2, Some(Hash([2u8; 32])));
+        assert_eq!(db.cache_stats().values, 2);
+        let hits = db.cache_stats().value_hits;
+        assert_eq!(StateDB::from_existing(&db, roots[2]).get_storage(&key).unwrap(), vec![3u8; 40]);
+        assert_eq!(db.cache_stats().value_hits, hits + 1);
+        assert!(StateDB::from_existing(&db, roots[0]).get_storage(&key).is_none());
+    }
+
+    #[test]
+    fn test_state_cache() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let key = Hash([1u8; 32]);
+        let mut state = StateDB::new(&db);
+        for i in 1..20u8 {
+            state.set_storage(Hash([i; 32]), &[i; 40]);
+        }
+        state.commit();
+        let root = state.root();
+
+        assert_eq!(StateDB::from_existing(&db, root).get_storage(&key).unwrap(), vec![1u8; 40]);
+        let stats = db.cache_stats();
+        assert_eq!(stats.value_misses, 1);
+        assert!(stats.node_misses > 0);
+        assert!(stats.nodes > 0);
+
+        // Other state of the same db reads the cached value
+        assert_eq!(StateDB::from_existing(&db, root).get_storage(&key).unwrap(), vec![1u8; 40]);
+        assert_eq!(db.cache_stats().value_hits, 1);
+        assert_eq!(db.cache_stats().node_misses, stats.node_misses);
+
+        // Nodes on the path of another key are partly cached
+        StateDB::from_existing(&db, root).get_storage(&Hash([2u8; 32]));
+        assert!(db.cache_stats().node_hits > 0);
+
+        db.set_cache_size(1);
+        assert!(db.cache_stats().nodes <= 1);
+        assert!(db.cache_stats().values <= 1);
+    }
+
+    #[test]
+    fn test_state_proof() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let mut state = StateDB::new(&ArchiveDB::new(Arc::clone(&backend)));
+        for i in 1..20u8 {
+            state.set_storage(Hash([i; 32]), &[i; 40]);
+        }
+        state.commit();
+        let root = state.root();
+
+        let key = Hash([7u8; 32]);
+        let proof = state.prove(&key);
+        assert!(!proof.is_empty());
+        assert_eq!(verify_proof(&root, &key, &proof).unwrap().unwrap(), vec![7u8; 40]);
+
+        let absent = Hash([30u8; 32]);
+        let proof = state.prove(&absent);
+        assert!(verify_proof(&root, &absent, &proof).unwrap().is_none());
+
+        // Proof of other key does not cover the path
+        let proof = state.prove(&Hash([1u8; 32]));
+        assert!(verify_proof(&root, &key, &proof).is_err());
+        assert!(verify_proof(&Hash([1u8; 32]), &key, &proof).is_err());
+    }
 }
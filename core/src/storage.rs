@@ -14,29 +14,462 @@
 // You should have received a copy of the GNU General Public License
 // along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
+//! Typed collections over state trie. Every collection is named by its module,
+//! e.g. `staking.validators`, and its entries are stored under hashed keys of the name.
+
+use std::cell::RefCell;
 use std::marker::PhantomData;
+use std::rc::Rc;
 
-use serde::{Serialize, Deserialize};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use bincode;
+use hash;
 use crate::types::Hash;
+use crate::state::StateDB;
+
+/// Root key of collection name
+pub fn storage_prefix(name: &[u8]) -> Hash {
+    Hash(hash::blake2b_256(name))
+}
+
+fn prefixed_key(prefix: &Hash, item: &[u8]) -> Hash {
+    let mut raw = prefix.to_vec();
+    raw.extend_from_slice(item);
+    Hash(hash::blake2b_256(&raw))
+}
+
+/// Storage key of map entry, same as `StorageMap::key` of map with name
+pub fn map_key<K: Serialize>(name: &[u8], key: &K) -> Hash {
+    prefixed_key(&storage_prefix(name), &bincode::serialize(key).unwrap())
+}
+
+fn load<T: DeserializeOwned>(state: &Rc<RefCell<StateDB>>, key: &Hash) -> Option<T> {
+    let encoded = state.borrow().get_storage(key)?;
+    match bincode::deserialize(&encoded) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            error!("decode storage key={}, err={:?}", key, e);
+            None
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize)]
-pub struct ListEntry<T> {
-    pub pre: Option<Hash>,
-    pub next: Option<Hash>,
-    pub payload: T,
+fn store<T: Serialize>(state: &Rc<RefCell<StateDB>>, key: Hash, value: &T) {
+    let encoded = bincode::serialize(value).unwrap();
+    state.borrow_mut().set_storage(key, &encoded);
 }
 
-pub struct List<T> {
-    pub head_key: Hash,
-    // pub state: &StateDB,
-    phantom: PhantomData<T>
+/// Single value
+pub struct StorageValue<T> {
+    state: Rc<RefCell<StateDB>>,
+    key: Hash,
+    phantom: PhantomData<T>,
 }
 
-impl<T> List<T> {
-    pub fn new(head: Hash) -> Self {
-        List {
-            head_key: head,
+impl<T: Serialize + DeserializeOwned> StorageValue<T> {
+    pub fn new(state: Rc<RefCell<StateDB>>, name: &[u8]) -> Self {
+        StorageValue {
+            state: state,
+            key: storage_prefix(name),
             phantom: PhantomData,
         }
     }
-}
\ No newline at end of file
+
+    pub fn key(&self) -> Hash {
+        self.key
+    }
+
+    pub fn get(&self) -> Option<T> {
+        load(&self.state, &self.key)
+    }
+
+    pub fn exists(&self) -> bool {
+        self.state.borrow().get_storage(&self.key).is_some()
+    }
+
+    pub fn set(&mut self, value: &T) {
+        store(&self.state, self.key, value)
+    }
+
+    pub fn remove(&mut self) {
+        self.state.borrow_mut().remove_storage(self.key)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Default> StorageValue<T> {
+    pub fn get_or_default(&self) -> T {
+        self.get().unwrap_or_default()
+    }
+}
+
+/// Key value map, entries are not enumerable
+pub struct StorageMap<K, V> {
+    state: Rc<RefCell<StateDB>>,
+    prefix: Hash,
+    phantom: PhantomData<(K, V)>,
+}
+
+impl<K: Serialize, V: Serialize + DeserializeOwned> StorageMap<K, V> {
+    pub fn new(state: Rc<RefCell<StateDB>>, name: &[u8]) -> Self {
+        StorageMap {
+            state: state,
+            prefix: storage_prefix(name),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Storage key of entry
+    pub fn key(&self, key: &K) -> Hash {
+        prefixed_key(&self.prefix, &bincode::serialize(key).unwrap())
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        load(&self.state, &self.key(key))
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.state.borrow().get_storage(&self.key(key)).is_some()
+    }
+
+    pub fn insert(&mut self, key: &K, value: &V) {
+        store(&self.state, self.key(key), value)
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        self.state.borrow_mut().remove_storage(self.key(key))
+    }
+}
+
+/// Indexed list with length, removal swaps the last item into the hole
+pub struct StorageList<T> {
+    state: Rc<RefCell<StateDB>>,
+    prefix: Hash,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> StorageList<T> {
+    pub fn new(state: Rc<RefCell<StateDB>>, name: &[u8]) -> Self {
+        StorageList {
+            state: state,
+            prefix: storage_prefix(name),
+            phantom: PhantomData,
+        }
+    }
+
+    fn index_key(&self, index: u64) -> Hash {
+        prefixed_key(&self.prefix, &index.to_be_bytes())
+    }
+
+    fn set_len(&mut self, len: u64) {
+        if len == 0 {
+            self.state.borrow_mut().remove_storage(self.prefix);
+        } else {
+            store(&self.state, self.prefix, &len);
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        load(&self.state, &self.prefix).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: u64) -> Option<T> {
+        if index >= self.len() {
+            return None;
+        }
+        load(&self.state, &self.index_key(index))
+    }
+
+    /// Replace item at index, false if index is out of range
+    pub fn set(&mut self, index: u64, item: &T) -> bool {
+        if index >= self.len() {
+            return false;
+        }
+        store(&self.state, self.index_key(index), item);
+        true
+    }
+
+    pub fn push(&mut self, item: &T) {
+        let len = self.len();
+        store(&self.state, self.index_key(len), item);
+        self.set_len(len + 1);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let len = self.len();
+        if len == 0 {
+            return None;
+        }
+        let item = load(&self.state, &self.index_key(len - 1));
+        self.state.borrow_mut().remove_storage(self.index_key(len - 1));
+        self.set_len(len - 1);
+        item
+    }
+
+    /// Remove item at index and move the last item into its place
+    pub fn swap_remove(&mut self, index: u64) -> Option<T> {
+        let len = self.len();
+        if index >= len {
+            return None;
+        }
+        let item = load(&self.state, &self.index_key(index));
+        if index != len - 1 {
+            if let Some(last) = load::<T>(&self.state, &self.index_key(len - 1)) {
+                store(&self.state, self.index_key(index), &last);
+            }
+        }
+        self.state.borrow_mut().remove_storage(self.index_key(len - 1));
+        self.set_len(len - 1);
+        item
+    }
+
+    pub fn iter(&self) -> StorageIter<T> {
+        StorageIter {
+            list: self,
+            index: 0,
+            end: self.len(),
+        }
+    }
+
+    /// Items in range [offset, offset + limit)
+    pub fn page(&self, offset: u64, limit: u64) -> Vec<T> {
+        let end = std::cmp::min(self.len(), offset.saturating_add(limit));
+        StorageIter {
+            list: self,
+            index: offset,
+            end: end,
+        }.collect()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + PartialEq> StorageList<T> {
+    pub fn position(&self, item: &T) -> Option<u64> {
+        (0..self.len()).find(|i| self.get(*i).as_ref() == Some(item))
+    }
+
+    /// Remove first item equal to item, false if not found
+    pub fn remove_item(&mut self, item: &T) -> bool {
+        match self.position(item) {
+            Some(index) => self.swap_remove(index).is_some(),
+            None => false,
+        }
+    }
+}
+
+/// Enumerable key value map, keys are listed in insertion order and
+/// removal moves the last key into the hole
+pub struct StorageIndexedMap<K, V> {
+    values: StorageMap<K, V>,
+    keys: StorageList<K>,
+    // Position of key in list, so removal does not scan the list
+    positions: StorageMap<K, u64>,
+}
+
+impl<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned> StorageIndexedMap<K, V> {
+    /// Values are stored under name, keys under `<name>.keys` and their positions under `<name>.positions`
+    pub fn new(state: Rc<RefCell<StateDB>>, name: &[u8]) -> Self {
+        StorageIndexedMap {
+            values: StorageMap::new(state.clone(), name),
+            keys: StorageList::new(state.clone(), &[name, b".keys"].concat()),
+            positions: StorageMap::new(state, &[name, b".positions"].concat()),
+        }
+    }
+
+    /// Storage key of value
+    pub fn key(&self, key: &K) -> Hash {
+        self.values.key(key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.values.get(key)
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.values.contains(key)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Insert or update value, new key is appended to the list
+    pub fn insert(&mut self, key: &K, value: &V) {
+        if !self.positions.contains(key) {
+            self.positions.insert(key, &self.keys.len());
+            self.keys.push(key);
+        }
+        self.values.insert(key, value);
+    }
+
+    /// Remove entry, false if key is not found
+    pub fn remove(&mut self, key: &K) -> bool {
+        let index = match self.positions.get(key) {
+            Some(i) => i,
+            None => return false,
+        };
+        let last = self.keys.len() - 1;
+        if index != last {
+            if let Some(moved) = self.keys.get(last) {
+                self.positions.insert(&moved, &index);
+            }
+        }
+        self.keys.swap_remove(index);
+        self.positions.remove(key);
+        self.values.remove(key);
+        true
+    }
+
+    pub fn keys(&self) -> StorageIter<K> {
+        self.keys.iter()
+    }
+
+    pub fn values(&self) -> Vec<V> {
+        self.keys.iter().filter_map(|key| self.values.get(&key)).collect()
+    }
+
+    /// Values of keys in range [offset, offset + limit) of the list
+    pub fn page(&self, offset: u64, limit: u64) -> Vec<V> {
+        self.keys.page(offset, limit).iter().filter_map(|key| self.values.get(key)).collect()
+    }
+}
+
+pub struct StorageIter<'a, T> {
+    list: &'a StorageList<T>,
+    index: u64,
+    end: u64,
+}
+
+impl<'a, T: Serialize + DeserializeOwned> Iterator for StorageIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        while self.index < self.end {
+            let key = self.list.index_key(self.index);
+            self.index += 1;
+            // Undecodable item is skipped and logged
+            if let Some(item) = load(&self.list.state, &key) {
+                return Some(item);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use map_store::{MemoryKV, KVDB};
+    use crate::state::{ArchiveDB, StateDB};
+    use crate::trie::NULL_ROOT;
+    use crate::types::Address;
+    use super::{StorageValue, StorageMap, StorageList, StorageIndexedMap, map_key};
+
+    fn state() -> Rc<RefCell<StateDB>> {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)))
+    }
+
+    #[test]
+    fn test_storage_value() {
+        let state = state();
+        let mut value: StorageValue<u128> = StorageValue::new(state.clone(), b"test.value");
+        assert_eq!(value.get(), None);
+        assert_eq!(value.get_or_default(), 0);
+        value.set(&7);
+        assert_eq!(StorageValue::<u128>::new(state.clone(), b"test.value").get(), Some(7));
+        assert!(!StorageValue::<u128>::new(state.clone(), b"test.other").exists());
+        value.remove();
+        assert!(!value.exists());
+    }
+
+    #[test]
+    fn test_storage_map() {
+        let state = state();
+        let mut map: StorageMap<Address, u64> = StorageMap::new(state.clone(), b"test.map");
+        let (a, b) = (Address([1; 20]), Address([2; 20]));
+        map.insert(&a, &1);
+        map.insert(&b, &2);
+        assert_eq!(map.get(&a), Some(1));
+        assert_eq!(map.key(&a), map_key(b"test.map", &a));
+        assert!(StorageMap::<Address, u64>::new(state.clone(), b"test.other").get(&a).is_none());
+        map.remove(&a);
+        assert!(!map.contains(&a));
+        assert_eq!(map.get(&b), Some(2));
+    }
+
+    #[test]
+    fn test_storage_list() {
+        let state = state();
+        let mut list: StorageList<u32> = StorageList::new(state.clone(), b"test.list");
+        assert!(list.is_empty());
+        for i in 0..10u32 {
+            list.push(&i);
+        }
+        assert_eq!(list.len(), 10);
+        assert_eq!(list.iter().collect::<Vec<u32>>(), (0..10).collect::<Vec<u32>>());
+        assert_eq!(list.page(8, 5), vec![8, 9]);
+        assert_eq!(list.page(3, 2), vec![3, 4]);
+        assert!(list.page(10, 2).is_empty());
+
+        assert!(list.set(0, &100));
+        assert!(!list.set(10, &100));
+        assert_eq!(list.swap_remove(0), Some(100));
+        assert_eq!(list.get(0), Some(9));
+        assert!(list.remove_item(&5));
+        assert!(!list.remove_item(&5));
+        assert_eq!(list.len(), 8);
+        assert_eq!(list.get(5), Some(8));
+        assert_eq!(list.pop(), Some(7));
+        assert_eq!(list.get(7), None);
+
+        while list.pop().is_some() {}
+        assert!(list.is_empty());
+        state.borrow_mut().commit();
+        assert_eq!(state.borrow().root(), NULL_ROOT);
+    }
+
+    #[test]
+    fn test_storage_indexed_map() {
+        let state = state();
+        let mut map: StorageIndexedMap<u32, String> = StorageIndexedMap::new(state.clone(), b"test.indexed");
+        assert!(map.is_empty());
+        for i in 0..4u32 {
+            map.insert(&i, &i.to_string());
+        }
+        // Update keeps position
+        map.insert(&1, &"one".to_string());
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(&1), Some("one".to_string()));
+        assert_eq!(map.key(&1), map_key(b"test.indexed", &1u32));
+        assert_eq!(map.keys().collect::<Vec<u32>>(), vec![0, 1, 2, 3]);
+        assert_eq!(map.page(1, 2), vec!["one".to_string(), "2".to_string()]);
+
+        // Last key moves into the hole and can be removed from there
+        assert!(map.remove(&1));
+        assert!(!map.remove(&1));
+        assert!(!map.contains(&1));
+        assert_eq!(map.keys().collect::<Vec<u32>>(), vec![0, 3, 2]);
+        assert!(map.remove(&3));
+        assert_eq!(map.values(), vec!["0".to_string(), "2".to_string()]);
+        map.insert(&5, &"5".to_string());
+        assert_eq!(map.keys().collect::<Vec<u32>>(), vec![0, 2, 5]);
+
+        for key in &[0u32, 2, 5] {
+            assert!(map.remove(key));
+        }
+        assert!(map.is_empty());
+        state.borrow_mut().commit();
+        assert_eq!(state.borrow().root(), NULL_ROOT);
+    }
+}
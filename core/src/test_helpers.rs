@@ -0,0 +1,48 @@
+// Copyright 2019 MarcoPolo Protocol Authors.
+// This file is part of MarcoPolo Protocol.
+
+// MarcoPolo Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MarcoPolo Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MarcoPolo Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fixtures shared by tests across the workspace, built with feature `test-helpers`
+
+use ed25519::privkey::PrivKey;
+use crate::genesis::ed_genesis_priv_key;
+use crate::transaction::Transaction;
+use crate::transaction::balance_msg::MsgTransfer;
+use crate::types::Address;
+
+/// Key of the genesis authority, also holding the genesis allocation
+pub fn genesis_key() -> PrivKey {
+    PrivKey::from_bytes(&ed_genesis_priv_key)
+}
+
+pub fn address_of(key: &PrivKey) -> Address {
+    key.to_pubkey().unwrap().into()
+}
+
+/// Transaction of the key owner calling method with input, signed by key
+pub fn signed_tx(key: &PrivKey, nonce: u64, gas_price: u64, gas: u64, method: &[u8], input: Vec<u8>) -> Transaction {
+    let mut tx = Transaction::new(address_of(key), nonce, gas_price, gas, method.to_vec(), input);
+    tx.sign(&key.to_bytes()).unwrap();
+    tx
+}
+
+pub fn transfer_input(to: Address, value: u128) -> Vec<u8> {
+    bincode::serialize(&MsgTransfer { receiver: to, value: value }).unwrap()
+}
+
+/// Transfer with gas price 1 and enough gas for a plain transfer
+pub fn transfer_tx(key: &PrivKey, nonce: u64, to: Address, value: u128) -> Transaction {
+    signed_tx(key, nonce, 1, 100_000, b"balance.transfer", transfer_input(to, value))
+}
@@ -46,12 +46,92 @@ pub mod balance_msg {
 pub mod staking_msg {
     use serde::{Deserialize, Serialize};
     use crate::types::{Address};
+    use crate::block::{Header, VerificationItem};
 
     #[derive(Serialize, Deserialize)]
     #[derive(Clone, Debug, PartialEq)]
     pub struct MsgValidatorCreate {
         pub pubkey: Vec<u8>,
         pub amount: u128,
+        pub commission: u8,
+    }
+
+    /// Stake amount of delegate and undelegate
+    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct MsgDelegate {
+        pub validator: Address,
+        pub amount: u128,
+    }
+
+    /// Header with validator signature of its seal hash
+    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct SignedHeader {
+        pub header: Header,
+        pub sign: VerificationItem,
+    }
+
+    /// Evidence of validator signing two different headers at the same height
+    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct MsgEquivocation {
+        pub offender: Address,
+        pub first: SignedHeader,
+        pub second: SignedHeader,
+    }
+}
+
+pub mod contract_msg {
+    use serde::{Deserialize, Serialize};
+    use crate::types::{Address};
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct MsgDeploy {
+        /// WASM module of contract
+        pub code: Vec<u8>,
+        /// Balance moved to contract account
+        pub value: u128,
+        /// Input of the optional deploy export
+        pub input: Vec<u8>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct MsgCall {
+        pub contract: Address,
+        pub value: u128,
+        pub input: Vec<u8>,
+    }
+}
+
+pub mod asset_msg {
+    use serde::{Deserialize, Serialize};
+    use crate::types::{Address, Hash};
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct MsgCreate {
+        pub symbol: String,
+        pub decimals: u8,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct MsgMint {
+        pub asset: Hash,
+        pub receiver: Address,
+        pub amount: u128,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct MsgBurn {
+        pub asset: Hash,
+        pub amount: u128,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct MsgTransfer {
+        pub asset: Hash,
+        pub receiver: Address,
+        pub amount: u128,
     }
 }
 
@@ -34,6 +34,13 @@ pub struct RegisterItem<M> (pub String, pub Sender<Receiver<M>>);
 pub type EventRegister<M> = Sender<RegisterItem<M>>;
 pub type SignalSender = Sender<()>;
 
+/// Blocks dropped from and added to the canonical chain on a head switch
+#[derive(Clone, Debug)]
+pub struct ChainReorg {
+    pub retracted: Vec<Block>,
+    pub enacted: Vec<Block>,
+}
+
 
 impl<M> RegisterItem<M> {
     pub fn call(sender: &Sender<RegisterItem<M>>, arguments: String) -> Option<Receiver<M>> {
@@ -48,6 +55,8 @@ pub struct EventHandler {
     stop: Option<Arc<Mutex<Option<SignalSender>>>>,
     new_block_register: EventRegister<Block>,
     new_block_notifier: Sender<Block>,
+    chain_reorg_register: EventRegister<ChainReorg>,
+    chain_reorg_notifier: Sender<ChainReorg>,
 }
 impl EventHandler {
     pub fn new_stop(stop: SignalSender) -> Option<Arc<Mutex<Option<SignalSender>>>> {
@@ -60,6 +69,13 @@ impl EventHandler {
     pub fn notify_new_block(&self, b: Block) {
         let _ = self.new_block_notifier.send(b);
     }
+    pub fn subscribe_chain_reorg<S: ToString>(&self, name: S) -> Receiver<ChainReorg> {
+        RegisterItem::call(&self.chain_reorg_register, name.to_string())
+            .expect("Subscribe chain reorg should be OK")
+    }
+    pub fn notify_chain_reorg(&self, r: ChainReorg) {
+        let _ = self.chain_reorg_notifier.send(r);
+    }
     pub fn stop(&mut self) {
         let inner = self.stop
         .take()
@@ -72,12 +88,14 @@ impl EventHandler {
 }
 pub struct EventService {
     new_block_subscribers: HashMap<String, Sender<Block>>,
+    chain_reorg_subscribers: HashMap<String, Sender<ChainReorg>>,
 }
 
 impl EventService {
     pub fn new() -> Self {
         Self {
             new_block_subscribers :HashMap::default(),
+            chain_reorg_subscribers :HashMap::default(),
         }
     }
     #[allow(clippy::zero_ptr, clippy::drop_copy)]
@@ -85,6 +103,8 @@ impl EventService {
         let (signal_sender, signal_receiver) = bounded::<()>(ONE_CHANNEL_SIZE);
         let (new_block_register, new_block_register_receiver) = bounded(REGISTER_CHANNEL_SIZE);
         let (new_block_sender, new_block_receiver) = bounded::<Block>(EVENT_CHANNEL_SIZE);
+        let (chain_reorg_register, chain_reorg_register_receiver) = bounded(REGISTER_CHANNEL_SIZE);
+        let (chain_reorg_sender, chain_reorg_receiver) = bounded::<ChainReorg>(EVENT_CHANNEL_SIZE);
 
         let mut thread_builder = thread::Builder::new();
         if let Some(name) = thread_name {
@@ -98,6 +118,8 @@ impl EventService {
                     }
                     recv(new_block_register_receiver) -> msg => self.handle_register_new_block(msg),
                     recv(new_block_receiver) -> msg => self.handle_notify_new_block(msg),
+                    recv(chain_reorg_register_receiver) -> msg => self.handle_register_chain_reorg(msg),
+                    recv(chain_reorg_receiver) -> msg => self.handle_notify_chain_reorg(msg),
                 }
             })
             .expect("Start notify service failed");
@@ -106,6 +128,8 @@ impl EventService {
             stop:   EventHandler::new_stop(signal_sender),
             new_block_register,
             new_block_notifier: new_block_sender,
+            chain_reorg_register,
+            chain_reorg_notifier: chain_reorg_sender,
         }
     }
     fn handle_register_new_block(
@@ -138,6 +162,36 @@ impl EventService {
             _ => debug!("new block channel is closed"),
         }
     }
+
+    fn handle_register_chain_reorg(
+        &mut self,
+        msg: Result<RegisterItem<ChainReorg>, RecvError>,
+    ) {
+        match msg {
+            Ok(RegisterItem (
+                name,
+                responder,
+            )) => {
+                debug!("Register chain_reorg {:?}", name);
+                let (sender, receiver) = bounded::<ChainReorg>(EVENT_CHANNEL_SIZE);
+                self.chain_reorg_subscribers.insert(name, sender);
+                let _ = responder.send(receiver);
+            }
+            _ => debug!("Register chain_reorg channel is closed"),
+        }
+    }
+
+    fn handle_notify_chain_reorg(&mut self, msg: Result<ChainReorg, RecvError>) {
+        match msg {
+            Ok(reorg) => {
+                trace!("event chain reorg retracted={} enacted={}", reorg.retracted.len(), reorg.enacted.len());
+                for subscriber in self.chain_reorg_subscribers.values() {
+                    let _ = subscriber.send(reorg.clone());
+                }
+            }
+            _ => debug!("chain reorg channel is closed"),
+        }
+    }
 }
 
 
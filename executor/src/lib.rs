@@ -19,55 +19,100 @@ extern crate log;
 extern crate errors;
 
 use core::transaction::Transaction;
+use core::receipt::{Receipt, ReceiptStatus};
 use core::balance::Balance;
+use core::staking::{self, Staking};
+use core::reward::Rewards;
+use core::runtime::Interpreter;
 use core::types::{Hash, Address};
 use core::block::{Block};
 use errors::{Error,InternalErrorKind};
 
-#[allow(non_upper_case_globals)]
-const transfer_fee: u128 = 10000;
-
 
 pub struct Executor;
 
 impl Executor {
-    pub fn exc_txs_in_block(b: &Block, state: &mut Balance, miner_addr: &Address) -> Result<Hash,Error> {
+    /// Enter context of block at height, staking epoch transition runs before its transactions
+    pub fn begin_block(height: u64, runner: &mut Interpreter) {
+        runner.set_height(height);
+        if staking::is_epoch_start(height) {
+            Staking::from_state(runner.clone()).on_epoch();
+        }
+    }
+
+    /// Mint block reward to proposer and record rewards of the block in its epoch
+    pub fn finalize_block(height: u64, runner: &mut Interpreter, proposer: &Address, fees: u128) -> u128 {
+        Rewards::from_state(runner.clone()).reward_block(height, proposer, fees)
+    }
+
+    /// Apply all transactions of block, returns the state root and receipt of each transaction.
+    /// Fees and reward go to proposer in block proof, default proposer is used for block without proof.
+    pub fn exc_txs_in_block(b: &Block, runner: &mut Interpreter, default_proposer: &Address) -> Result<(Hash, Vec<Receipt>), Error> {
+        Executor::begin_block(b.height(), runner);
+        let proposer = match b.proof_one() {
+            Some(proof) => proof.to_address(),
+            None => *default_proposer,
+        };
         let txs = b.get_txs();
-        // let mut h = Hash([0u8;32]);
+        let mut receipts = Vec::with_capacity(txs.len());
         for tx in txs {
-            Executor::exc_transfer_tx(tx,state)?;
-            state.add_balance(*miner_addr, transfer_fee);
+            receipts.push(Executor::exc_tx(tx, runner, &proposer)?);
         }
+        let fees: u128 = receipts.iter().map(|r| r.fee).sum();
+        Executor::finalize_block(b.height(), runner, &proposer, fees);
 
-        Ok(state.commit())
+        let root = Balance::from_state(runner.clone()).commit();
+        Ok((root, receipts))
     }
 
-    // handle the state for the tx,caller handle the gas of tx
-    pub fn exc_transfer_tx(tx: &Transaction, state: &mut Balance) -> Result<Hash, Error> {
+    /// Apply single transaction and pay the fee to miner.
+    /// Invalid transaction returns error, while execution failure is recorded in receipt.
+    pub fn exc_tx(tx: &Transaction, runner: &mut Interpreter, miner_addr: &Address) -> Result<Receipt, Error> {
         let from_addr = tx.get_from_address();
-        let to_addr = tx.get_to_address();
+        let mut state = Balance::from_state(runner.clone());
 
         Executor::verify_tx_sign(&tx)?;
-        // Ensure balance and nance field available
+        // Ensure nonce field and the upfront gas cost available
         let from_account = state.get_account(from_addr);
         if tx.get_nonce() != from_account.get_nonce() + 1 {
             return Err(InternalErrorKind::InvalidTxNonce.into());
         }
-        if tx.get_value() + transfer_fee > from_account.get_balance() {
+        let max_fee = tx.gas as u128 * tx.gas_price as u128;
+        if max_fee > from_account.get_balance() {
             return Err(InternalErrorKind::BalanceNotEnough.into());
         }
+        let intrinsic_gas = runner.schedule().intrinsic_gas(tx.data.len());
+        if intrinsic_gas > tx.gas {
+            return Err(InternalErrorKind::OutOfGas.into());
+        }
 
-        state.sub_balance(from_addr, transfer_fee);
         state.inc_nonce(from_addr);
+        state.sub_balance(from_addr, max_fee);
+        runner.set_gas_limit(tx.gas);
+        runner.charge_gas(intrinsic_gas)?;
+
+        let statedb = runner.statedb();
+        statedb.borrow_mut().checkpoint();
+        let status = match runner.call(&from_addr, tx.call.clone(), tx.data.clone()) {
+            Ok(_) => {
+                statedb.borrow_mut().discard_checkpoint();
+                ReceiptStatus::Success
+            }
+            Err(e) => {
+                // Revert message changes, the fee is still paid
+                debug!("transaction failed hash={}, err={:?}", tx.hash(), e);
+                statedb.borrow_mut().revert_checkpoint();
+                ReceiptStatus::Failed
+            }
+        };
 
-        state.transfer(from_addr, to_addr, tx.get_value());
-        Ok(Hash::default())
+        let gas_used = runner.gas_used();
+        let fee = gas_used as u128 * tx.gas_price as u128;
+        state.add_balance(from_addr, max_fee - fee);
+        state.add_balance(*miner_addr, fee);
+        Ok(Receipt::new(status, fee, gas_used, state.commit()))
     }
 
-    // handle the state for the contract
-    pub fn exc_contract_tx() -> Result<(),Error> {
-        Ok(())
-    }
     fn verify_tx_sign(tx: &Transaction) -> Result<(),Error> {
         tx.verify_sign()
     }
@@ -76,36 +121,118 @@ impl Executor {
 #[cfg(test)]
 pub mod tests {
     extern crate ed25519;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use bincode;
     use ed25519::{privkey::PrivKey,pubkey::Pubkey,generator::Generator};
     use core::balance::Balance;
-    use core::types::{Hash, Address};
-    use core::transaction::Transaction;
-    use std::path::PathBuf;
+    use core::runtime::Interpreter;
+    use core::state::{ArchiveDB, StateDB};
+    use core::contract::Contract;
+    use core::test_helpers::{signed_tx, transfer_input};
+    use core::transaction::contract_msg;
+    use core::receipt::ReceiptStatus;
+    use core::trie::NULL_ROOT;
+    use core::types::Address;
+    use map_store::{MemoryKV, KVDB};
     use super::Executor;
-    use bytes::Bytes;
 
     pub fn get_pair() -> (PrivKey,Pubkey) {
         Generator::default().new()
     }
+
     #[test]
     pub fn test_tx_execute() {
-        let path = PathBuf::from("testdb_04".to_string());
-        let mut state = Balance::new(path);
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db);
+        let miner = Address([9; 20]);
+        let user1 = get_pair();
+        let addr1: Address = user1.1.clone().into();
+        let addr2 = Address::from_hex("0000000000000000000000000000000000000001").unwrap();
+        Balance::from_state(runner.clone()).add_balance(addr1, 100000u128);
+
+        let tx = signed_tx(&user1.0, 1, 10, 1000, b"balance.transfer", transfer_input(addr2, 100));
+        let receipt = Executor::exc_tx(&tx, &mut runner, &miner).unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Success);
+        assert_eq!(receipt.fee, receipt.gas_used as u128 * 10);
+
+        let state = Balance::from_state(runner.clone());
+        assert_eq!(state.balance(addr2), 100);
+        assert_eq!(state.balance(miner), receipt.fee);
+        assert_eq!(state.balance(addr1), 100000 - 100 - receipt.fee);
+        assert_eq!(state.nonce(addr1), 1);
+
+        // Stale nonce makes transaction invalid
+        assert!(Executor::exc_tx(&tx, &mut runner, &miner).is_err());
+    }
+
+    #[test]
+    pub fn test_tx_out_of_gas() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db);
+        let miner = Address([9; 20]);
+        let user1 = get_pair();
+        let addr1: Address = user1.1.clone().into();
+        let addr2 = Address([1; 20]);
+        Balance::from_state(runner.clone()).add_balance(addr1, 100000u128);
+
+        // Enough for intrinsic gas only
+        let gas = runner.schedule().intrinsic_gas(36) + 1;
+        let tx = signed_tx(&user1.0, 1, 10, gas, b"balance.transfer", transfer_input(addr2, 100));
+        let receipt = Executor::exc_tx(&tx, &mut runner, &miner).unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Failed);
+        assert_eq!(receipt.gas_used, gas);
+
+        let state = Balance::from_state(runner.clone());
+        assert_eq!(state.balance(addr2), 0);
+        assert_eq!(state.balance(addr1), 100000 - gas as u128 * 10);
+        assert_eq!(state.nonce(addr1), 1);
+    }
+
+    #[test]
+    pub fn test_staking_tx() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db);
+        let user1 = get_pair();
+        let addr1: Address = user1.1.clone().into();
+        Balance::from_state(runner.clone()).add_balance(addr1, 100000u128);
+
+        let input = bincode::serialize(&1000u128).unwrap();
+        let tx = signed_tx(&user1.0, 1, 10, 1000, b"staking.deposit", input);
+        // Not a validator yet, fails without panic
+        let receipt = Executor::exc_tx(&tx, &mut runner, &Address::default()).unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Failed);
+    }
+
+    #[test]
+    pub fn test_contract_deploy_tx() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db);
         let user1 = get_pair();
-        let addr1 = user1.1.into();
-        let const_value = 100000u128;
-        let tval = 100u128;
-        state.add_balance(addr1, const_value);
-        state.inc_nonce(addr1);
-        let hex_addr = "0000000000000000000000000000000000000001";
-        let addr2 = Address::from_hex(hex_addr).unwrap();
-        let tx = Transaction::new(addr1, addr2, 2,
-             10, 10, tval, Bytes::new());
-        match Executor::exc_transfer_tx(&tx, &mut state) {
-            Ok(h) => println!("root:{:?}",h),
-            Err(e) => {println!("err:{:?}",e);return;},
+        let addr1: Address = user1.1.clone().into();
+        Balance::from_state(runner.clone()).add_balance(addr1, 100000u128);
+
+        // Empty module without exports
+        let msg = contract_msg::MsgDeploy {
+            code: b"\0asm\x01\0\0\0".to_vec(),
+            value: 10,
+            input: Vec::new(),
         };
-        let val2 = state.balance(addr2);
-        assert_eq!(val2,tval);
+        let tx = signed_tx(&user1.0, 1, 10, 5000, b"contract.deploy", bincode::serialize(&msg).unwrap());
+        let receipt = Executor::exc_tx(&tx, &mut runner, &Address::default()).unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Success);
+
+        let contract = Contract::contract_address(&addr1, 1);
+        assert!(Contract::from_state(runner.clone()).code(&contract).is_some());
+        assert_eq!(Balance::from_state(runner.clone()).balance(contract), 10);
     }
 }
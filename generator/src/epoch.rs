@@ -22,15 +22,19 @@ use std::time::{Duration, Instant, SystemTime};
 use crate::types::{seed_info, HolderItem};
 use crate::{apos::APOS, types};
 use chain::blockchain::BlockChain;
+use chain::tx_pool::TxPoolManager;
 use crossbeam_channel::{bounded, unbounded, select, tick, Receiver, RecvError, Sender};
 use futures::future::Future;
 // use tokio::sync::mpsc::{Receiver, Sender};
 use ed25519::{privkey::PrivKey, pubkey::Pubkey, signature::SignatureInfo};
 use errors::{Error, ErrorKind};
 use map_consensus::ConsensusErrorKind;
+use map_consensus::poa::POA;
 use map_network::manager::NetworkExecutor;
-use map_core::block::{self, Block, BlockProof, VerificationItem};
+use map_core::balance::Balance;
+use map_core::block::{self, Block, BlockProof, Header, VerificationItem, BLOCK_GAS_LIMIT, MAX_BLOCK_TXS};
 use map_core::types::Hash;
+use map_executor::Executor;
 // use super::fts;
 
 /// Slots per epoch constant
@@ -46,26 +50,67 @@ pub type TypeStopEpoch = Sender<()>;
 #[derive(Clone)]
 pub struct Builder {
     chain: Arc<RwLock<BlockChain>>,
+    tx_pool: Arc<RwLock<TxPoolManager>>,
+    key: PrivKey,
 }
 
 impl Builder {
-    pub fn new(chain: Arc<RwLock<BlockChain>>) -> Self {
-        Builder { chain: chain }
+    pub fn new(chain: Arc<RwLock<BlockChain>>, tx_pool: Arc<RwLock<TxPoolManager>>, key: PrivKey) -> Self {
+        Builder {
+            chain: chain,
+            tx_pool: tx_pool,
+            key: key,
+        }
     }
     // Proposal new block from certain slot
-    pub fn make_new_block(&self, height: u64, parent: Hash) -> Block {
-        let pre = self.chain.read().unwrap().get_block(parent).unwrap();
-        let mut block = Block::default();
-        block.header.parent_hash = parent;
-        block.header.height = height + 1;
-        block.header.tx_root = Hash::default();
-        block.header.state_root = pre.state_root();
-        block.header.sign_root = Hash::default();
-        block.header.time = SystemTime::now()
+    pub fn make_new_block(&self, height: u64, parent: Hash) -> Result<Block, Error> {
+        // Ordered by gas price, nonce order kept per sender
+        let pending = self.tx_pool.read().expect("acquiring tx_pool read lock").get_txs();
+
+        let chain = self.chain.read().unwrap();
+        let pre = chain.get_block(parent).unwrap();
+        let mut runner = chain.interpreter_at(pre.state_root());
+        Executor::begin_block(height + 1, &mut runner);
+        // Fees and reward are paid to proposer in block proof
+        let pk = self.key.to_pubkey()?;
+        let proof = BlockProof::new(0u8, &pk.to_bytes());
+        let proposer = proof.to_address();
+
+        let mut txs = Vec::new();
+        let mut gas: u64 = 0;
+        let mut fees: u128 = 0;
+        for tx in pending {
+            if txs.len() >= MAX_BLOCK_TXS {
+                break;
+            }
+            if gas.saturating_add(tx.gas) > BLOCK_GAS_LIMIT {
+                continue;
+            }
+            match Executor::exc_tx(&tx, &mut runner, &proposer) {
+                Ok(receipt) => {
+                    gas += tx.gas;
+                    fees += receipt.fee;
+                    txs.push(tx);
+                }
+                Err(e) => warn!("skip transaction hash={}, err={:?}", tx.hash(), e),
+            }
+        }
+
+        Executor::finalize_block(height + 1, &mut runner, &proposer, fees);
+
+        let mut header = Header::default();
+        header.parent_hash = parent;
+        header.height = height + 1;
+        header.state_root = Balance::from_state(runner).commit();
+        let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        block
+        // Chain requires block time to increase
+        header.time = std::cmp::max(now, pre.header.time + 1);
+        let mut block = Block::new(header, txs, Vec::new(), Vec::new());
+        block.add_proof(proof);
+        POA::sign_block(0u8, Some(self.key), block)
     }
 
     pub fn get_current_height(&self) -> u64 {
@@ -159,14 +204,15 @@ pub struct EpochProcess {
 }
 
 impl EpochProcess {
-    pub fn new(mid: Pubkey, eid: u64, seed: u64, chain: Arc<RwLock<BlockChain>>, p2p: NetworkExecutor, exit: Receiver<i32>) -> Self {
+    pub fn new(key: PrivKey, eid: u64, seed: u64, chain: Arc<RwLock<BlockChain>>, tx_pool: Arc<RwLock<TxPoolManager>>,
+        p2p: NetworkExecutor, exit: Receiver<i32>) -> Self {
         EpochProcess {
-            myid: mid,
+            myid: key.to_pubkey().expect("invalid node key"),
             cur_eid: eid,
             cur_seed: seed,
             slots: Vec::new(),
             received_seed_info: Vec::new(),
-            block_chain: Builder::new(chain.clone()),
+            block_chain: Builder::new(chain.clone(), tx_pool, key),
             exit_event: exit,
             network: p2p,
         }
@@ -248,15 +294,21 @@ impl EpochProcess {
         info!("new slot id={}", sid);
         if self.is_proposer(sid, state) {
             let current = self.block_chain.get_head_block();
-            let b = self
+            let b = match self
                 .block_chain
-                .make_new_block(current.height(), current.hash());
-            info!("make new block hash={} num={}", b.hash(), b.height());
+                .make_new_block(current.height(), current.hash()) {
+                Ok(b) => b,
+                Err(e) => {
+                    error!("make_new_block Error: {:?}", e);
+                    return;
+                }
+            };
+            info!("make new block hash={} num={} txs={}", b.hash(), b.height(), b.txs.len());
 
             let block_chain = self.block_chain.get_blockchain();
-            let mut chain = block_chain.write().unwrap();
-            if let Err(e) = chain.insert_block(b.clone()) {
+            if let Err(e) = block_chain.write().unwrap().insert_block(b.clone()) {
                 error!("insert_block Error: {:?}", e);
+                return;
             }
             // boradcast and import the block
             self.network.gossip(b);
@@ -266,10 +318,11 @@ impl EpochProcess {
     pub fn slot_handle(&mut self, sid: u64, state: Arc<RwLock<APOS>>) {
         if self.is_proposer(sid, state) {
             let current = self.block_chain.get_head_block();
-            let b = self
+            if let Ok(b) = self
                 .block_chain
-                .make_new_block(current.height(), current.hash());
-            info!("make new block hash={}", b.hash());
+                .make_new_block(current.height(), current.hash()) {
+                info!("make new block hash={}", b.hash());
+            }
             // boradcast and import the block
         }
     }
@@ -502,9 +555,69 @@ impl EpochProcess {
 
 #[cfg(test)]
 pub mod tests {
+    use super::*;
     use std::thread;
     use std::time::{Duration, Instant};
     use crossbeam_channel::tick;
+    use chain::tx_pool::PoolConfig;
+    use map_core::test_helpers::{genesis_key, signed_tx, transfer_input};
+    use map_core::transaction::Transaction;
+    use map_core::types::Address;
+    use map_store::MemoryKV;
+
+    fn setup() -> (Arc<RwLock<BlockChain>>, Arc<RwLock<TxPoolManager>>, Builder) {
+        let chain = Arc::new(RwLock::new(BlockChain::new(Arc::new(RwLock::new(MemoryKV::new())), "".to_string())));
+        chain.write().unwrap().load().unwrap();
+        let config = PoolConfig { max_per_account: 1024, ..PoolConfig::default() };
+        let pool = Arc::new(RwLock::new(TxPoolManager::with_config(chain.clone(), config)));
+        let builder = Builder::new(chain.clone(), pool.clone(), genesis_key());
+        (chain, pool, builder)
+    }
+
+    fn transfer_tx(nonce: u64, gas_price: u64, gas: u64) -> Transaction {
+        signed_tx(&genesis_key(), nonce, gas_price, gas, b"balance.transfer", transfer_input(Address([1; 20]), 1))
+    }
+
+    // Pack pool transactions and import the block through chain, then update pool as the
+    // new block listener of service does
+    fn produce(chain: &Arc<RwLock<BlockChain>>, pool: &Arc<RwLock<TxPoolManager>>, builder: &Builder) -> Block {
+        let head = builder.get_head_block();
+        let block = builder.make_new_block(head.height(), head.hash()).unwrap();
+        chain.write().unwrap().insert_block_ref(&block).unwrap();
+        pool.write().unwrap().notify_block(&block);
+        assert_eq!(chain.read().unwrap().current_block().hash(), block.hash());
+        block
+    }
+
+    #[test]
+    fn test_pack_max_txs() {
+        let (chain, pool, builder) = setup();
+        for nonce in 1..601 {
+            pool.write().unwrap().submit_txs(transfer_tx(nonce, 1, 1000)).unwrap();
+        }
+
+        let b1 = produce(&chain, &pool, &builder);
+        assert_eq!(b1.txs.len(), MAX_BLOCK_TXS);
+        assert_eq!(pool.read().unwrap().get_txs().len(), 600 - MAX_BLOCK_TXS);
+
+        let b2 = produce(&chain, &pool, &builder);
+        assert_eq!(b2.txs.len(), 600 - MAX_BLOCK_TXS);
+        assert!(pool.read().unwrap().get_txs().is_empty());
+    }
+
+    #[test]
+    fn test_pack_gas_limit() {
+        let (chain, pool, builder) = setup();
+        pool.write().unwrap().submit_txs(transfer_tx(1, 1, 400_000)).unwrap();
+        pool.write().unwrap().submit_txs(transfer_tx(2, 1, 400_000)).unwrap();
+        // Exceeds the limit left, the gas sum must not overflow
+        pool.write().unwrap().submit_txs(transfer_tx(3, 0, u64::max_value())).unwrap();
+
+        let b1 = produce(&chain, &pool, &builder);
+        assert_eq!(b1.txs.len(), 2);
+        assert!(b1.txs.iter().map(|tx| tx.gas).sum::<u64>() <= BLOCK_GAS_LIMIT);
+        assert_eq!(pool.read().unwrap().get_txs().len(), 1);
+    }
 
     #[test]
     fn slot_tick() {
@@ -82,13 +82,7 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
         // behaviour.add_address(&"QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ".parse().unwrap(), "/ip4/104.131.131.82/tcp/4001".parse().unwrap());
 
         // The function used to generate a gossipsub message id
-        // We use base64(SHA256(data)) for content addressing
-        let gossip_message_id = |message: &GossipsubMessage| {
-            MessageId(base64::encode_config(
-                &Sha256::digest(&message.data),
-                base64::URL_SAFE,
-            ))
-        };
+        let gossip_message_id = |message: &GossipsubMessage| message_id(&message.data);
 
         Ok(Behaviour {
             gossipsub: Gossipsub::new(local_peer_id, GossipsubConfigBuilder::new()
@@ -110,6 +104,20 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
     }
 }
 
+/// Gossipsub message id, we use base64(SHA256(data)) for content addressing
+pub(crate) fn message_id(data: &[u8]) -> MessageId {
+    MessageId(base64::encode_config(
+        &Sha256::digest(data),
+        base64::URL_SAFE,
+    ))
+}
+
+/// Record a gossip message as seen before it is validated, returns false for a duplicate.
+/// Invalid messages are recorded as well, so a peer is not penalised twice for the same message.
+pub(crate) fn record_seen(seen: &mut LruCache<MessageId, ()>, id: &MessageId) -> bool {
+    seen.put(id.clone(), ()).is_none()
+}
+
 // Implement the NetworkBehaviourEventProcess trait so that we can derive NetworkBehaviour for Behaviour
 impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<GossipsubEvent>
 for Behaviour<TSubstream>
@@ -122,7 +130,7 @@ for Behaviour<TSubstream>
 
                 // Note: We are keeping track here of the peer that sent us the message, not the
                 // peer that originally published the message.
-                if self.seen_gossip_messages.put(id.clone(), ()).is_none() {
+                if record_seen(&mut self.seen_gossip_messages, &id) {
                     // if this message isn't a duplicate, notify the network
                     self.events.push(BehaviourEvent::GossipMessage {
                         id,
@@ -375,6 +383,8 @@ pub enum BehaviourEvent {
 pub enum PubsubMessage {
     /// Gossipsub message providing notification of a new block.
     Block(Vec<u8>),
+    /// Gossipsub message providing a new transaction.
+    Transaction(Vec<u8>),
     /// Gossipsub message from an unknown topic.
     Unknown(Vec<u8>),
 }
@@ -390,6 +400,7 @@ impl PubsubMessage {
         for topic in topics {
             match GossipTopic::from(topic.as_str()) {
                 GossipTopic::MapBlock => return PubsubMessage::Block(data),
+                GossipTopic::MapTransaction => return PubsubMessage::Transaction(data),
                 GossipTopic::Shard => return PubsubMessage::Unknown(data),
                 GossipTopic::Unknown(_) => continue,
             }
@@ -400,6 +411,7 @@ impl PubsubMessage {
     fn into_data(self) -> Vec<u8> {
         match self {
             PubsubMessage::Block(data)
+            | PubsubMessage::Transaction(data)
             | PubsubMessage::Unknown(data) => data,
         }
     }
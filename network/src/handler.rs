@@ -9,6 +9,7 @@ use slog::{debug, o, trace, warn};
 use tokio::sync::mpsc;
 
 use chain::blockchain::BlockChain;
+use chain::tx_pool::TxPoolManager;
 
 use crate::{behaviour::PubsubMessage, manager::NetworkMessage};
 use crate::error;
@@ -47,6 +48,7 @@ impl MessageHandler {
     /// Initializes and runs the MessageHandler.
     pub fn spawn(
         block_chain: Arc<RwLock<BlockChain>>,
+        tx_pool: Arc<RwLock<TxPoolManager>>,
         network_send: mpsc::UnboundedSender<NetworkMessage>,
         executor: &tokio::runtime::TaskExecutor,
         log: slog::Logger,
@@ -57,7 +59,7 @@ impl MessageHandler {
 
         // Initialise a message instance, which itself spawns the syncing thread.
         let message_processor =
-            MessageProcessor::new(executor, block_chain, network_send.clone(), &log);
+            MessageProcessor::new(executor, block_chain, tx_pool, network_send.clone(), &log);
 
         // generate the Message handler
         let mut handler = MessageHandler {
@@ -218,6 +220,20 @@ impl MessageHandler {
                     debug!(self.log, "Invalid gossiped block"; "peer_id" => format!("{}", peer_id), "Error" => format!("{:?}", e));
                 }
             },
+            PubsubMessage::Transaction(message) => match bincode::deserialize(&message[..]) {
+                Ok(tx) => {
+                    let should_forward_on = self
+                        .message_processor
+                        .on_transaction_gossip(peer_id.clone(), tx);
+                    if should_forward_on {
+                        self.propagate_message(id, peer_id);
+                    }
+                }
+                Err(e) => {
+                    debug!(self.log, "Invalid gossiped transaction"; "peer_id" => format!("{}", peer_id), "Error" => format!("{:?}", e));
+                    self.message_processor.on_invalid_gossip(peer_id);
+                }
+            },
             PubsubMessage::Unknown(message) => {
                 // Received a message from an unknown topic. Ignore for now
                 debug!(self.log, "Unknown Gossip Message"; "peer_id" => format!("{}", peer_id), "Message" => format!("{:?}", message));
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
 use libp2p::PeerId;
@@ -5,7 +6,9 @@ use slog::{debug, error, o, trace, warn};
 use tokio::sync::{mpsc, oneshot};
 
 use chain::blockchain::BlockChain;
+use chain::tx_pool::TxPoolManager;
 use map_core::block::Block;
+use map_core::transaction::Transaction;
 use map_core::types::Hash;
 
 use crate::manager::NetworkMessage;
@@ -20,6 +23,8 @@ pub(crate) const FUTURE_SLOT_TOLERANCE: u64 = 1;
 const SHOULD_FORWARD_GOSSIP_BLOCK: bool = true;
 const SHOULD_NOT_FORWARD_GOSSIP_BLOCK: bool = false;
 const QUEUE_GOSSIP_BLOCK: usize = 512;
+/// Peer is disconnected after sending this many invalid gossip messages.
+const MAX_INVALID_GOSSIP: u32 = 8;
 
 /// Keeps track of syncing information for known connected peers.
 #[derive(Clone, Copy, Debug)]
@@ -62,6 +67,10 @@ impl PeerSyncInfo {
 pub struct MessageProcessor {
     /// A reference to the underlying beacon chain.
     chain: Arc<RwLock<BlockChain>>,
+    /// Pool validating transactions received from gossip.
+    tx_pool: Arc<RwLock<TxPoolManager>>,
+    /// Number of invalid gossip messages sent by each peer.
+    invalid_gossip: HashMap<PeerId, u32>,
     /// A channel to the syncing thread.
     sync_send: mpsc::UnboundedSender<SyncMessage>,
     /// A oneshot channel for destroying the sync thread.
@@ -78,6 +87,7 @@ impl MessageProcessor {
     pub fn new(
         executor: &tokio::runtime::TaskExecutor,
         block_chain: Arc<RwLock<BlockChain>>,
+        tx_pool: Arc<RwLock<TxPoolManager>>,
         network_send: mpsc::UnboundedSender<NetworkMessage>,
         log: &slog::Logger,
     ) -> Self {
@@ -92,6 +102,8 @@ impl MessageProcessor {
 
         MessageProcessor {
             chain: block_chain,
+            tx_pool,
+            invalid_gossip: HashMap::new(),
             sync_send,
             _sync_exit,
             network: HandlerNetworkContext::new(network_send, log.clone()),
@@ -113,6 +125,7 @@ impl MessageProcessor {
     ///
     /// Removes the peer from the manager.
     pub fn on_disconnect(&mut self, peer_id: PeerId) {
+        self.invalid_gossip.remove(&peer_id);
         self.send_to_sync(SyncMessage::Disconnect(peer_id));
     }
 
@@ -341,7 +354,8 @@ impl MessageProcessor {
             }
         }
 
-        if block.height() == current_block.height() + 1 {
+        // Blocks up to head height may extend a side chain
+        if block.height() <= current_block.height() + 1 {
             let broadcast = match self.chain.write().expect("").insert_block_ref(&block) {
                 Ok(_) => {
                     true
@@ -360,6 +374,47 @@ impl MessageProcessor {
 
         false
     }
+
+    /// Process a gossip message declaring a new transaction.
+    ///
+    /// Returns a `bool` which, if `true`, indicates we should forward the transaction to our peers.
+    pub fn on_transaction_gossip(&mut self, peer_id: PeerId, tx: Transaction) -> bool {
+        let hash = tx.hash();
+        let mut pool = self.tx_pool.write().expect("acquiring tx_pool write lock");
+        // Known transaction is not a fault of peer
+        if pool.contains(&hash) {
+            return false;
+        }
+
+        match pool.import_tx(tx) {
+            Ok(_) => true,
+            // Peer could not have relayed it in good faith
+            Err(ref e) if e.is_invalid() => {
+                drop(pool);
+                debug!(self.log, "Invalid gossip transaction"; "hash" => format!("{}", hash), "error" => format!("{}", e));
+                self.on_invalid_gossip(peer_id);
+                false
+            }
+            // Pool state of peer differs, the transaction is dropped without penalty
+            Err(e) => {
+                trace!(self.log, "Drop gossip transaction"; "hash" => format!("{}", hash), "error" => format!("{}", e));
+                false
+            }
+        }
+    }
+
+    /// Penalise peer sending invalid gossip, disconnect it when over the limit.
+    pub fn on_invalid_gossip(&mut self, peer_id: PeerId) {
+        let count = {
+            let count = self.invalid_gossip.entry(peer_id.clone()).or_insert(0);
+            *count += 1;
+            *count
+        };
+        if count >= MAX_INVALID_GOSSIP {
+            self.invalid_gossip.remove(&peer_id);
+            self.network.disconnect(peer_id, GoodbyeReason::Fault);
+        }
+    }
 }
 
 /// Build a `StatusMessage` representing the state of the given `block_chain`.
@@ -450,3 +505,99 @@ impl HandlerNetworkContext {
             });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Future, Stream};
+    use lru::LruCache;
+    use slog::Discard;
+    use map_core::test_helpers::{genesis_key, transfer_tx};
+    use map_core::types::Address;
+    use map_store::MemoryKV;
+    use crate::behaviour::{message_id, record_seen};
+
+    fn setup(runtime: &tokio::runtime::Runtime) -> (MessageProcessor, Arc<RwLock<TxPoolManager>>, mpsc::UnboundedReceiver<NetworkMessage>) {
+        let chain = Arc::new(RwLock::new(BlockChain::new(Arc::new(RwLock::new(MemoryKV::new())), "".to_string())));
+        chain.write().unwrap().load().unwrap();
+        let tx_pool = Arc::new(RwLock::new(TxPoolManager::start(chain.clone())));
+        let (network_send, network_recv) = mpsc::unbounded_channel();
+        let log = slog::Logger::root(Discard, o!());
+        let processor = MessageProcessor::new(&runtime.executor(), chain, tx_pool.clone(), network_send, &log);
+        (processor, tx_pool, network_recv)
+    }
+
+    fn gossip_tx(nonce: u64) -> Transaction {
+        transfer_tx(&genesis_key(), nonce, Address([1; 20]), 1)
+    }
+
+    // Signature no longer matches the transaction
+    fn invalid_tx(nonce: u64) -> Transaction {
+        let mut tx = gossip_tx(nonce);
+        tx.gas_price += 1;
+        tx
+    }
+
+    #[test]
+    fn test_gossip_valid_tx() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (mut processor, tx_pool, _network_recv) = setup(&runtime);
+        let peer = PeerId::random();
+        let tx = gossip_tx(1);
+
+        assert!(processor.on_transaction_gossip(peer.clone(), tx.clone()));
+        assert!(tx_pool.read().unwrap().contains(&tx.hash()));
+        assert_eq!(tx_pool.read().unwrap().get_txs(), vec![tx.clone()]);
+
+        // Known transaction is not forwarded again, nor a fault of peer
+        assert!(!processor.on_transaction_gossip(peer.clone(), tx));
+        assert!(processor.invalid_gossip.get(&peer).is_none());
+    }
+
+    #[test]
+    fn test_gossip_rejected_tx() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (mut processor, tx_pool, _network_recv) = setup(&runtime);
+        let peer = PeerId::random();
+
+        // Nonce already used on chain is rejected by pool policy only
+        for _ in 0..MAX_INVALID_GOSSIP + 1 {
+            assert!(!processor.on_transaction_gossip(peer.clone(), gossip_tx(0)));
+        }
+        assert!(!tx_pool.read().unwrap().contains(&gossip_tx(0).hash()));
+        assert!(processor.invalid_gossip.get(&peer).is_none());
+    }
+
+    #[test]
+    fn test_gossip_invalid_tx() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (mut processor, tx_pool, network_recv) = setup(&runtime);
+        let mut seen = LruCache::new(16);
+        let peer = PeerId::random();
+        let tx = invalid_tx(1);
+
+        let id = message_id(&bincode::serialize(&tx).unwrap());
+        assert!(record_seen(&mut seen, &id));
+        assert!(!processor.on_transaction_gossip(peer.clone(), tx.clone()));
+        assert!(!tx_pool.read().unwrap().contains(&tx.hash()));
+        assert_eq!(processor.invalid_gossip.get(&peer), Some(&1));
+
+        // Same message gossiped again is dropped as seen, before it is processed
+        assert!(!record_seen(&mut seen, &id));
+
+        // Peer is disconnected when over the limit
+        for nonce in 2..MAX_INVALID_GOSSIP as u64 + 1 {
+            assert!(!processor.on_transaction_gossip(peer.clone(), invalid_tx(nonce)));
+        }
+        assert!(processor.invalid_gossip.get(&peer).is_none());
+        let messages = network_recv.take(2).collect().wait().unwrap();
+        match messages[0] {
+            NetworkMessage::P2P(ref p, P2PEvent::Request(_, P2PRequest::Goodbye(GoodbyeReason::Fault))) => assert_eq!(p, &peer),
+            ref m => panic!("unexpected message {:?}", m),
+        }
+        match messages[1] {
+            NetworkMessage::Disconnect { ref peer_id } => assert_eq!(peer_id, &peer),
+            ref m => panic!("unexpected message {:?}", m),
+        }
+    }
+}
@@ -17,7 +17,9 @@ use tokio::sync::{mpsc, oneshot};
 use tokio::timer::Delay;
 
 use chain::blockchain::BlockChain;
+use chain::tx_pool::TxPoolManager;
 use map_core::block::Block;
+use map_core::transaction::Transaction;
 
 use crate::{
     {behaviour::{PubsubMessage}
@@ -43,7 +45,8 @@ pub struct NetworkExecutor {
 }
 
 impl NetworkExecutor {
-    pub fn new(cfg: NetworkConfig, block_chain: Arc<RwLock<BlockChain>>, log_level: String) -> error::Result<Self> {
+    pub fn new(cfg: NetworkConfig, block_chain: Arc<RwLock<BlockChain>>, tx_pool: Arc<RwLock<TxPoolManager>>,
+               log_level: String) -> error::Result<Self> {
         // build the network channel
         let (network_send, network_recv) = mpsc::unbounded_channel::<NetworkMessage>();
         // launch libp2p Network
@@ -72,6 +75,7 @@ impl NetworkExecutor {
 
         let message_handler_send = MessageHandler::spawn(
             block_chain.clone(),
+            tx_pool.clone(),
             network_send.clone(),
             &executor,
             log.clone(),
@@ -79,6 +83,16 @@ impl NetworkExecutor {
 
         let service = Arc::new(Mutex::new(Service::new(cfg, log.clone())?));
 
+        // Broadcast transactions admitted to local pool
+        let admitted = tx_pool.write().expect("acquiring tx_pool write lock").subscribe_admitted();
+        let mut tx_send = network_send.clone();
+        let tx_log = log.clone();
+        thread::spawn(move || {
+            while let Ok(tx) = admitted.recv() {
+                publish_transaction(&mut tx_send, &tx, &tx_log);
+            }
+        });
+
         // A delay used to initialise code after the network has started
         // This is currently used to obtain the listening addresses from the libp2p service.
         let initial_delay = Delay::new(Instant::now() + Duration::from_secs(1));
@@ -114,6 +128,21 @@ impl NetworkExecutor {
             })
             .unwrap_or_else(|_| warn!(self.log, "Could not send gossip message."));
     }
+
+    pub fn gossip_transaction(&mut self, tx: &Transaction) {
+        publish_transaction(&mut self.network_send, tx, &self.log);
+    }
+}
+
+fn publish_transaction(network_send: &mut mpsc::UnboundedSender<NetworkMessage>, tx: &Transaction, log: &slog::Logger) {
+    let topic = GossipTopic::MapTransaction;
+    let message = PubsubMessage::Transaction(bincode::serialize(tx).unwrap());
+    network_send
+        .try_send(NetworkMessage::Publish {
+            topics: vec![topic.into()],
+            message,
+        })
+        .unwrap_or_else(|_| warn!(log, "Could not send transaction gossip message."));
 }
 
 fn start_service(
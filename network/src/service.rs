@@ -105,6 +105,7 @@ impl Service {
         // subscribe to default gossipsub topics
         let topics = vec![
             GossipTopic::MapBlock,
+            GossipTopic::MapTransaction,
         ];
 
         let mut subscribed_topics: Vec<String> = vec![];
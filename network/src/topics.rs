@@ -7,12 +7,14 @@ use serde::{Serialize, Deserialize};
 pub const TOPIC_PREFIX: &str = "map";
 pub const TOPIC_ENCODING_POSTFIX: &str = "bin";
 pub const MAP_BLOCK_TOPIC: &str = "block";
+pub const MAP_TRANSACTION_TOPIC: &str = "transaction";
 pub const SHARD_TOPIC_PREFIX: &str = "shard";
 
 /// Enum that brings these topics into the rust type system.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum GossipTopic {
     MapBlock,
+    MapTransaction,
     Shard,
     Unknown(String),
 }
@@ -26,6 +28,7 @@ impl From<&str > for GossipTopic {
         {
             match topic_parts[2] {
                 MAP_BLOCK_TOPIC => GossipTopic::MapBlock,
+                MAP_TRANSACTION_TOPIC => GossipTopic::MapTransaction,
                 unknown_topic => GossipTopic::Unknown(unknown_topic.into()),
             }
         } else {
@@ -44,6 +47,7 @@ impl Into<String> for GossipTopic {
     fn into(self) -> String {
         match self {
             GossipTopic::MapBlock => topic_builder(MAP_BLOCK_TOPIC),
+            GossipTopic::MapTransaction => topic_builder(MAP_TRANSACTION_TOPIC),
             GossipTopic::Shard => topic_builder(SHARD_TOPIC_PREFIX),
             GossipTopic::Unknown(topic) => topic,
         }
@@ -76,7 +76,9 @@ impl AccountManager for AccountManagerImpl {
         let mut tx = Transaction::new(from, nonce + 1, 1000, 1000, b"balance.transfer".to_vec(), input);
 
         tx.sign(&priv_key.to_bytes()).expect("sign ok");
-        self.tx_pool.write().expect("acquiring tx pool write lock").submit_txs(tx.clone());
+        if let Err(e) = self.tx_pool.write().expect("acquiring tx pool write lock").submit_txs(tx.clone()) {
+            return Ok(format!("submit transaction err {}", e));
+        }
         Ok(format!("{}", tx.hash()))
     }
 }
@@ -1,11 +1,23 @@
 use std::sync::{Arc, RwLock, RwLockReadGuard};
 
-use jsonrpc_core::Result;
+use jsonrpc_core::{Error, Result};
 use jsonrpc_derive::rpc;
 
 use chain::blockchain::BlockChain;
+use chain::tx_pool::TxPoolManager;
+use map_core::asset::Asset;
+use map_core::balance::Balance;
 use map_core::block::{Block, Header};
-use map_core::types::Hash;
+use map_core::runtime::Interpreter;
+use map_core::staking::Staking;
+use map_core::types::{Address, Hash};
+
+use crate::types::account_json::AccountJson;
+use crate::types::block_number::BlockNumber;
+use crate::types::holding_json::HoldingJson;
+use crate::types::proof_json::{encode_nodes, ProofJson, StorageProofJson};
+use crate::types::transaction_json::{ReceiptJson, TransactionJson, TransactionProofJson};
+use crate::types::validator_json::ValidatorJson;
 
 #[rpc(server)]
 pub trait ChainRpc {
@@ -19,11 +31,35 @@ pub trait ChainRpc {
     fn get_block_by_number(&self, num: u64) -> Result<Option<Block>>;
 
     #[rpc(name = "map_getTransaction")]
-    fn get_transaction(&self, hash: Hash) -> Result<Option<String>>;
+    fn get_transaction(&self, hash: Hash) -> Result<Option<TransactionJson>>;
+
+    #[rpc(name = "map_getTransactionReceipt")]
+    fn get_transaction_receipt(&self, hash: Hash) -> Result<Option<ReceiptJson>>;
+
+    #[rpc(name = "map_getTransactionProof")]
+    fn get_transaction_proof(&self, hash: Hash) -> Result<Option<TransactionProofJson>>;
+
+    #[rpc(name = "map_getProof")]
+    fn get_proof(&self, address: String, keys: Vec<Hash>, block: Option<BlockNumber>) -> Result<ProofJson>;
+
+    /// curl -d '{"id": 1, "jsonrpc": "2.0", "method":"map_getBalance","params": ["0xd2480451ef35ff2fdd7c69cad058719b9dc4d631","latest"]}' -H 'content-type:application/json' 'http://localhost:9545'
+    #[rpc(name = "map_getBalance")]
+    fn get_balance(&self, address: String, block: Option<BlockNumber>) -> Result<u128>;
+
+    #[rpc(name = "map_getNonce")]
+    fn get_nonce(&self, address: String, block: Option<BlockNumber>) -> Result<u64>;
+
+    /// Account at block, pending tag reads the state of head with nonce of pooled transactions
+    #[rpc(name = "map_getAccount")]
+    fn get_account(&self, address: String, block: Option<BlockNumber>) -> Result<AccountJson>;
+
+    #[rpc(name = "map_getHoldings")]
+    fn get_holdings(&self, address: String, block: Option<BlockNumber>) -> Result<Vec<HoldingJson>>;
 }
 
 pub(crate) struct ChainRpcImpl {
     pub block_chain: Arc<RwLock<BlockChain>>,
+    pub tx_pool: Arc<RwLock<TxPoolManager>>,
 }
 
 impl ChainRpc for ChainRpcImpl {
@@ -39,13 +75,208 @@ impl ChainRpc for ChainRpcImpl {
         Ok(self.get_blockchain().get_header_by_number(num))
     }
 
-    fn get_transaction(&self, _hash: Hash) -> Result<Option<String>> {
-        Ok(Some(format!("{}", "Success")))
+    fn get_transaction(&self, hash: Hash) -> Result<Option<TransactionJson>> {
+        let tx = self.get_blockchain().get_transaction(&hash);
+        Ok(tx.map(|(tx, location)| TransactionJson::new(&tx, &location)))
+    }
+
+    fn get_transaction_receipt(&self, hash: Hash) -> Result<Option<ReceiptJson>> {
+        let receipt = self.get_blockchain().get_receipt(&hash);
+        Ok(receipt.map(|(r, location)| ReceiptJson::new(hash, &r, &location)))
     }
+
+    fn get_transaction_proof(&self, hash: Hash) -> Result<Option<TransactionProofJson>> {
+        let proof = self.get_blockchain().get_transaction_proof(&hash);
+        Ok(proof.map(|(tx, location, header, p)| TransactionProofJson::new(&tx, &location, &header, &p)))
+    }
+
+    fn get_proof(&self, address: String, keys: Vec<Hash>, block: Option<BlockNumber>) -> Result<ProofJson> {
+        let addr = parse_address(&address)?;
+        let chain = self.get_blockchain();
+        let block = state_block(&chain, block.unwrap_or_default())?;
+
+        let state = chain.state_at(block.state_root());
+        let account = Balance::new(Interpreter::new(state.clone())).get_account(addr);
+        let state = state.borrow();
+        let storage_proof = keys.into_iter()
+            .map(|key| StorageProofJson::new(key, state.get_storage(&key), &state.prove(&key)))
+            .collect();
+
+        Ok(ProofJson {
+            address: format!("0x{}", addr),
+            block_hash: block.hash(),
+            block_number: block.height(),
+            state_root: block.state_root(),
+            balance: account.get_balance(),
+            nonce: account.get_nonce(),
+            account_proof: encode_nodes(&state.prove(&Balance::address_key(addr))),
+            storage_proof: storage_proof,
+        })
+    }
+
+    fn get_balance(&self, address: String, block: Option<BlockNumber>) -> Result<u128> {
+        self.get_account(address, block).map(|account| account.balance)
+    }
+
+    fn get_nonce(&self, address: String, block: Option<BlockNumber>) -> Result<u64> {
+        self.get_account(address, block).map(|account| account.nonce)
+    }
+
+    fn get_account(&self, address: String, block: Option<BlockNumber>) -> Result<AccountJson> {
+        let addr = parse_address(&address)?;
+        let tag = block.unwrap_or_default();
+        // Pool locks chain itself, so it is asked before chain is locked here
+        let pending_nonce = match tag {
+            BlockNumber::Pending => Some(self.tx_pool.read().expect("acquiring tx pool read lock").get_nonce(&addr)),
+            _ => None,
+        };
+        let chain = self.get_blockchain();
+        let block = state_block(&chain, tag)?;
+        let state = chain.state_at(block.state_root());
+        let balance = Balance::new(Interpreter::new(state.clone()));
+        let validator = Staking::from_state(Interpreter::new(state)).get_validator(&addr);
+
+        Ok(AccountJson {
+            address: format!("0x{}", addr),
+            block_hash: block.hash(),
+            block_number: block.height(),
+            balance: balance.balance(addr),
+            locked_balance: balance.locked(addr),
+            nonce: pending_nonce.unwrap_or_else(|| balance.nonce(addr)),
+            validator: validator.map(|v| ValidatorJson::new(&v, block.height())),
+        })
+    }
+
+    fn get_holdings(&self, address: String, block: Option<BlockNumber>) -> Result<Vec<HoldingJson>> {
+        let addr = parse_address(&address)?;
+        let chain = self.get_blockchain();
+        let block = state_block(&chain, block.unwrap_or_default())?;
+        let assets = Asset::from_state(Interpreter::new(chain.state_at(block.state_root())));
+
+        Ok(assets.holdings(&addr).into_iter().map(|(info, balance)| HoldingJson {
+            asset: info.id,
+            issuer: format!("0x{}", info.issuer),
+            symbol: info.symbol,
+            decimals: info.decimals,
+            balance: balance,
+        }).collect())
+    }
+}
+
+// Block of tag whose state is kept, pending reads the state of head
+fn state_block(chain: &BlockChain, tag: BlockNumber) -> Result<Block> {
+    let block = match tag {
+        BlockNumber::Latest | BlockNumber::Pending => chain.current_block(),
+        BlockNumber::Number(num) => match chain.get_block_by_number(num) {
+            Some(b) => b,
+            None => return Err(Error::invalid_params(format!("unknown block {}", num))),
+        },
+    };
+    if !chain.has_state(&block.header) {
+        return Err(Error::invalid_params(format!("state of block {} is pruned", block.height())));
+    }
+    Ok(block)
+}
+
+fn parse_address(address: &str) -> Result<Address> {
+    let raw = address.trim_start_matches("0x");
+    if raw.len() != 40 {
+        return Err(Error::invalid_params(format!("invalid address {}", address)));
+    }
+    Address::from_hex(raw).map_err(|e| Error::invalid_params(format!("invalid address {}: {:?}", address, e)))
 }
 
 impl ChainRpcImpl {
     fn get_blockchain(&self) -> RwLockReadGuard<BlockChain> {
         self.block_chain.read().expect("acquiring block_chain read lock")
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain::test_helpers::make_block;
+    use map_core::state::PruningMode;
+    use map_core::test_helpers::{address_of, genesis_key, transfer_tx};
+    use map_core::transaction::Transaction;
+    use map_store::MemoryKV;
+
+    fn rpc_with(pruning: PruningMode) -> ChainRpcImpl {
+        let mut chain = BlockChain::with_pruning(Arc::new(RwLock::new(MemoryKV::new())), "".to_string(), pruning);
+        chain.load().unwrap();
+        let chain = Arc::new(RwLock::new(chain));
+        ChainRpcImpl {
+            tx_pool: Arc::new(RwLock::new(TxPoolManager::start(chain.clone()))),
+            block_chain: chain,
+        }
+    }
+
+    // Block of txs sealed by the genesis authority on head
+    fn push_block(rpc: &ChainRpcImpl, txs: Vec<Transaction>) {
+        let mut chain = rpc.block_chain.write().unwrap();
+        let block = make_block(&chain, &chain.current_block(), txs, &genesis_key(), 1);
+        chain.insert_block(block).unwrap();
+    }
+
+    fn hex_address(addr: &Address) -> String {
+        format!("0x{}", addr)
+    }
+
+    #[test]
+    fn test_account_at_block() {
+        let rpc = rpc_with(PruningMode::Archive);
+        let key = genesis_key();
+        let sender = hex_address(&address_of(&key));
+        let receiver = Address([7; 20]);
+        push_block(&rpc, vec![transfer_tx(&key, 1, receiver, 100)]);
+
+        let receiver = hex_address(&receiver);
+        assert_eq!(rpc.get_balance(receiver.clone(), Some(BlockNumber::Number(0))).unwrap(), 0);
+        assert_eq!(rpc.get_balance(receiver.clone(), Some(BlockNumber::Number(1))).unwrap(), 100);
+        assert_eq!(rpc.get_balance(receiver.clone(), None).unwrap(), 100);
+        assert_eq!(rpc.get_nonce(sender.clone(), Some(BlockNumber::Number(0))).unwrap(), 0);
+
+        let account = rpc.get_account(sender.clone(), Some(BlockNumber::Latest)).unwrap();
+        assert_eq!(account.block_number, 1);
+        assert_eq!(account.nonce, 1);
+        assert!(rpc.get_account(sender.clone(), Some(BlockNumber::Number(2))).is_err());
+        assert!(rpc.get_account("0x01".to_string(), None).is_err());
+
+        // Pending only counts nonce of pooled transactions
+        rpc.tx_pool.write().unwrap().submit_txs(transfer_tx(&key, 2, Address([7; 20]), 100)).unwrap();
+        let pending = rpc.get_account(sender.clone(), Some(BlockNumber::Pending)).unwrap();
+        assert_eq!(pending.nonce, 2);
+        assert_eq!(pending.block_number, 1);
+        assert_eq!(pending.balance, account.balance);
+        assert_eq!(rpc.get_nonce(sender, Some(BlockNumber::Latest)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_account_validator() {
+        let rpc = rpc_with(PruningMode::Archive);
+        let validator = {
+            let chain = rpc.get_blockchain();
+            let staking = Staking::from_state(chain.interpreter_at(chain.current_block().state_root()));
+            staking.validator_set()[0].clone()
+        };
+        let account = rpc.get_account(hex_address(&validator.address), None).unwrap();
+        let record = account.validator.unwrap();
+        assert_eq!(record.pubkey, format!("0x{}", hex::encode(&validator.pubkey)));
+        assert_eq!(record.effective_balance, validator.effective_balance);
+        assert!(record.active);
+        assert!(rpc.get_account(hex_address(&Address([7; 20])), None).unwrap().validator.is_none());
+    }
+
+    #[test]
+    fn test_pruned_block() {
+        let rpc = rpc_with(PruningMode::Recent(1));
+        for _ in 0..3 {
+            push_block(&rpc, Vec::new());
+        }
+        let sender = hex_address(&address_of(&genesis_key()));
+        assert!(rpc.get_account(sender.clone(), Some(BlockNumber::Number(0))).is_err());
+        assert!(rpc.get_proof(sender.clone(), Vec::new(), Some(BlockNumber::Number(0))).is_err());
+        assert!(rpc.get_account(sender.clone(), Some(BlockNumber::Number(2))).is_ok());
+        let proof = rpc.get_proof(sender, Vec::new(), Some(BlockNumber::Number(3))).unwrap();
+        assert_eq!(proof.block_number, 3);
+    }
+}
@@ -18,8 +18,8 @@ impl RpcBuilder {
             io_handler: IoHandler::new(),
         }
     }
-    pub fn config_chain(mut self, block_chain: Arc<RwLock<BlockChain>>) -> Self {
-        let chain = ChainRpcImpl { block_chain }.to_delegate();
+    pub fn config_chain(mut self, block_chain: Arc<RwLock<BlockChain>>, tx_pool: Arc<RwLock<TxPoolManager>>) -> Self {
+        let chain = ChainRpcImpl { block_chain, tx_pool }.to_delegate();
         self.io_handler.extend_with(chain);
         self
     }
@@ -0,0 +1,20 @@
+use map_core::types::Hash;
+use serde::Serialize;
+
+use super::validator_json::ValidatorJson;
+
+/// Account state at a block.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountJson {
+    pub address: String,
+    pub block_hash: Hash,
+    pub block_number: u64,
+    pub balance: u128,
+    /// Balance reserved by staking and other modules
+    pub locked_balance: u128,
+    /// Nonce of head plus transactions in pool for pending tag
+    pub nonce: u64,
+    /// Staking record if account is a validator
+    pub validator: Option<ValidatorJson>,
+}
@@ -0,0 +1,65 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+/// Block parameter given by number or tag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockNumber {
+    /// Head of canonical chain
+    Latest,
+    /// State of head, only nonce counts transactions waiting in pool
+    Pending,
+    /// Canonical block of height
+    Number(u64),
+}
+
+impl Default for BlockNumber {
+    fn default() -> Self {
+        BlockNumber::Latest
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawBlockNumber {
+    Num(u64),
+    Tag(String),
+}
+
+impl<'de> Deserialize<'de> for BlockNumber {
+    fn deserialize<D>(deserializer: D) -> Result<BlockNumber, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match RawBlockNumber::deserialize(deserializer)? {
+            RawBlockNumber::Num(n) => Ok(BlockNumber::Number(n)),
+            RawBlockNumber::Tag(tag) => match tag.as_str() {
+                "latest" => Ok(BlockNumber::Latest),
+                "pending" => Ok(BlockNumber::Pending),
+                s if s.starts_with("0x") => u64::from_str_radix(&s[2..], 16)
+                    .map(BlockNumber::Number)
+                    .map_err(|e| D::Error::custom(format!("invalid block number {}: {}", s, e))),
+                s => s.parse::<u64>()
+                    .map(BlockNumber::Number)
+                    .map_err(|e| D::Error::custom(format!("invalid block number {}: {}", s, e))),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockNumber;
+
+    #[test]
+    fn test_block_number() {
+        let n: BlockNumber = serde_json::from_str(r#""latest""#).unwrap();
+        assert_eq!(n, BlockNumber::Latest);
+        let n: BlockNumber = serde_json::from_str(r#""pending""#).unwrap();
+        assert_eq!(n, BlockNumber::Pending);
+        let n: BlockNumber = serde_json::from_str(r#""0x10""#).unwrap();
+        assert_eq!(n, BlockNumber::Number(16));
+        let n: BlockNumber = serde_json::from_str("7").unwrap();
+        assert_eq!(n, BlockNumber::Number(7));
+        assert!(serde_json::from_str::<BlockNumber>(r#""earliest""#).is_err());
+    }
+}
@@ -0,0 +1,13 @@
+use map_core::types::Hash;
+use serde::Serialize;
+
+/// Balance of an asset held by account.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HoldingJson {
+    pub asset: Hash,
+    pub issuer: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub balance: u128,
+}
@@ -1 +1,7 @@
-pub mod block_json;
\ No newline at end of file
+pub mod account_json;
+pub mod block_json;
+pub mod block_number;
+pub mod holding_json;
+pub mod proof_json;
+pub mod transaction_json;
+pub mod validator_json;
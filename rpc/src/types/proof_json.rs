@@ -0,0 +1,40 @@
+use map_core::types::Hash;
+use serde::Serialize;
+
+/// Hex encoded trie nodes
+pub fn encode_nodes(nodes: &[Vec<u8>]) -> Vec<String> {
+    nodes.iter().map(|n| format!("0x{}", hex::encode(n))).collect()
+}
+
+/// Proof of a state trie key.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageProofJson {
+    pub key: Hash,
+    pub value: Option<String>,
+    pub proof: Vec<String>,
+}
+
+impl StorageProofJson {
+    pub fn new(key: Hash, value: Option<Vec<u8>>, proof: &[Vec<u8>]) -> Self {
+        StorageProofJson {
+            key: key,
+            value: value.map(|v| format!("0x{}", hex::encode(v))),
+            proof: encode_nodes(proof),
+        }
+    }
+}
+
+/// Account and storage proofs against state root of a block.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofJson {
+    pub address: String,
+    pub block_hash: Hash,
+    pub block_number: u64,
+    pub state_root: Hash,
+    pub balance: u128,
+    pub nonce: u64,
+    pub account_proof: Vec<String>,
+    pub storage_proof: Vec<StorageProofJson>,
+}
@@ -0,0 +1,97 @@
+use chain::store::TxLocation;
+use map_core::block::{Header, MerkleProof};
+use map_core::receipt::Receipt;
+use map_core::transaction::Transaction;
+use map_core::types::Hash;
+use serde::Serialize;
+
+/// Transaction representation with its position in chain.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionJson {
+    pub hash: Hash,
+    pub block_hash: Hash,
+    pub block_number: u64,
+    pub transaction_index: u32,
+    pub from: String,
+    pub nonce: u64,
+    pub gas_price: u64,
+    pub gas: u64,
+    pub call: String,
+    pub data: String,
+}
+
+impl TransactionJson {
+    pub fn new(tx: &Transaction, location: &TxLocation) -> Self {
+        TransactionJson {
+            hash: tx.hash(),
+            block_hash: location.block_hash,
+            block_number: location.height,
+            transaction_index: location.index,
+            from: format!("0x{}", tx.get_from_address()),
+            nonce: tx.nonce,
+            gas_price: tx.gas_price,
+            gas: tx.gas,
+            call: String::from_utf8_lossy(&tx.call).into_owned(),
+            data: format!("0x{}", hex::encode(&tx.data)),
+        }
+    }
+}
+
+/// Transaction receipt representation.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiptJson {
+    pub transaction_hash: Hash,
+    pub block_hash: Hash,
+    pub block_number: u64,
+    pub transaction_index: u32,
+    pub status: u8,
+    pub fee: u128,
+    pub gas_used: u64,
+    pub state_root: Hash,
+}
+
+impl ReceiptJson {
+    pub fn new(hash: Hash, receipt: &Receipt, location: &TxLocation) -> Self {
+        ReceiptJson {
+            transaction_hash: hash,
+            block_hash: location.block_hash,
+            block_number: location.height,
+            transaction_index: location.index,
+            status: receipt.status as u8,
+            fee: receipt.fee,
+            gas_used: receipt.gas_used,
+            state_root: receipt.state_root,
+        }
+    }
+}
+
+/// Merkle inclusion proof of transaction in block.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionProofJson {
+    pub transaction_hash: Hash,
+    pub block_hash: Hash,
+    pub block_number: u64,
+    pub tx_root: Hash,
+    pub transaction_index: u32,
+    pub leaves: u32,
+    pub proof: Vec<Hash>,
+    pub raw: String,
+}
+
+impl TransactionProofJson {
+    pub fn new(tx: &Transaction, location: &TxLocation, header: &Header, proof: &MerkleProof) -> Self {
+        TransactionProofJson {
+            transaction_hash: tx.hash(),
+            block_hash: location.block_hash,
+            block_number: location.height,
+            tx_root: header.tx_root,
+            transaction_index: proof.index,
+            leaves: proof.leaves,
+            proof: proof.path.clone(),
+            raw: format!("0x{}", hex::encode(bincode::serialize(tx).unwrap())),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use map_core::staking::Validator;
+use serde::Serialize;
+
+/// Staking record of a validator at a block.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidatorJson {
+    pub pubkey: String,
+    pub balance: u128,
+    pub effective_balance: u128,
+    /// Stake bonded by delegators
+    pub delegated: u128,
+    pub commission: u8,
+    pub activate_height: u64,
+    pub exit_height: u64,
+    pub jailed: bool,
+    /// Takes part in consensus at the block
+    pub active: bool,
+}
+
+impl ValidatorJson {
+    pub fn new(v: &Validator, height: u64) -> Self {
+        ValidatorJson {
+            pubkey: format!("0x{}", hex::encode(&v.pubkey)),
+            balance: v.balance,
+            effective_balance: v.effective_balance,
+            delegated: v.delegated,
+            commission: v.commission,
+            activate_height: v.activate_height,
+            exit_height: v.exit_height,
+            jailed: v.jailed,
+            active: v.is_active(height),
+        }
+    }
+}
@@ -28,20 +28,21 @@ use std::path::PathBuf;
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::time::{Duration, SystemTime};
 
-use crossbeam_channel::unbounded;
+use crossbeam_channel::{unbounded, select, Receiver};
 use chain::blockchain::BlockChain;
 use chain::tx_pool::TxPoolManager;
 use ed25519::pubkey::Pubkey;
 use ed25519::privkey::PrivKey;
 use ed25519::generator::create_key;
 use consensus::{ConsensusErrorKind, poa::POA};
-use core::balance::Balance;
 use core::block::{self, Block, Header};
 use core::genesis::{ed_genesis_priv_key, ed_genesis_pub_key};
 use core::types::Hash;
-use core::runtime::Interpreter;
+use core::state::{PruningMode, DEFAULT_CACHE_SIZE};
+use map_store::{DBKind, KVDB};
 use errors::Error;
 use executor::Executor;
+use events::{ChainReorg, EventService};
 use generator::epoch::EpochProcess;
 use generator::apos::APOS;
 use network::{manager as network_executor, Multiaddr, NetworkConfig};
@@ -60,6 +61,13 @@ pub struct NodeConfig {
     pub dial_addrs: Vec<Multiaddr>,
     pub p2p_port: u16,
     pub seal_block: bool,
+    /// Keep state of all blocks, or only recent ones of pruning history
+    pub pruning: String,
+    pub pruning_history: u64,
+    /// Database backend, memory or rocksdb
+    pub db: String,
+    /// Number of trie nodes and of values kept in state cache
+    pub state_cache: usize,
 }
 
 impl Default for NodeConfig {
@@ -74,10 +82,44 @@ impl Default for NodeConfig {
             dial_addrs: vec![],
             p2p_port: 40313,
             seal_block:false,
+            pruning: "archive".into(),
+            pruning_history: 256,
+            db: "rocksdb".into(),
+            state_cache: DEFAULT_CACHE_SIZE,
         }
     }
 }
 
+impl NodeConfig {
+    pub fn pruning_mode(&self) -> Result<PruningMode, String> {
+        match self.pruning.as_str() {
+            "archive" => Ok(PruningMode::Archive),
+            "fast" if self.pruning_history == 0 => Err("pruning history of fast mode must be positive".to_string()),
+            "fast" => Ok(PruningMode::Recent(self.pruning_history)),
+            other => Err(format!("unknown pruning mode {}, expected archive or fast", other)),
+        }
+    }
+
+    pub fn db_kind(&self) -> Result<DBKind, String> {
+        DBKind::from_name(&self.db)
+            .ok_or_else(|| format!("unknown database {}, expected memory or rocksdb", self.db))
+    }
+
+    /// Check options which could not be rejected on parsing
+    pub fn validate(&self) -> Result<(), String> {
+        self.pruning_mode()?;
+        self.db_kind()?;
+        Ok(())
+    }
+
+    /// Open database of chain and state in data dir
+    pub fn open_db(&self) -> Arc<RwLock<dyn KVDB>> {
+        info!("using {} db, datadir {}", self.db, self.data_dir.display());
+        map_store::open_db(self.db_kind().expect("invalid database option"), map_store::Config::new(self.data_dir.clone()))
+            .expect("can not open database")
+    }
+}
+
 //#[derive(Debug, Copy, Clone, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Service {
     pub block_chain: Arc<RwLock<BlockChain>>,
@@ -87,7 +129,10 @@ pub struct Service {
 
 impl Service {
     pub fn new_service(cfg: NodeConfig) -> Self {
-        let chain = Arc::new(RwLock::new(BlockChain::new(cfg.data_dir.clone(),cfg.poa_privkey.clone())));
+        let mut block_chain = BlockChain::with_pruning(
+            cfg.open_db(), cfg.poa_privkey.clone(), cfg.pruning_mode().expect("invalid pruning option"));
+        block_chain.set_state_cache_size(cfg.state_cache);
+        let chain = Arc::new(RwLock::new(block_chain));
         Service {
             block_chain: chain.clone(),
             tx_pool: Arc::new(RwLock::new(TxPoolManager::start(chain.clone()))),
@@ -99,14 +144,20 @@ impl Service {
         POA::new_from_string(key)
     }
     pub fn start(self, cfg: NodeConfig) -> (mpsc::Sender<i32>, JoinHandle<()>) {
-        self.get_write_blockchain().load();
+        if let Err(e) = self.get_write_blockchain().load() {
+            error!("can not load chain: {}", e);
+            std::process::exit(1);
+        }
+        let mut events = EventService::new().start(Some("events"));
+        self.get_write_blockchain().set_event_handler(events.clone());
+        self.start_pool_listener(events.subscribe_new_block("tx_pool"), events.subscribe_chain_reorg("tx_pool"));
         let network_block_chain = self.block_chain.clone();
         let thread_cfg = cfg.clone();
 
         let mut config = NetworkConfig::new();
         config.update_network_cfg(cfg.data_dir, cfg.dial_addrs, cfg.p2p_port).unwrap();
         let network = network_executor::NetworkExecutor::new(
-            config.clone(), network_block_chain, cfg.log).expect("Network start error");
+            config.clone(), network_block_chain, self.tx_pool.clone(), cfg.log).expect("Network start error");
         // let p2p_network = Arc::new(RwLock::new(
         //     network_executor::NetworkExecutor::new(config.clone(), network_block_chain).expect("Network start error")));
 
@@ -122,10 +173,10 @@ impl Service {
         let shared_block_chain = self.block_chain.clone();
 
         let node_key = match PrivKey::from_hex(&cfg.key.clone()) {
-            Ok(k) => k.to_pubkey().unwrap(),
+            Ok(k) => k,
             _ => {
-                let (_, pk) = create_key();
-                pk
+                let (sk, _) = create_key();
+                sk
             },
         };
 
@@ -134,6 +185,7 @@ impl Service {
             0,
             0,
             shared_block_chain.clone(),
+            self.tx_pool.clone(),
             network,
             rs,
         );
@@ -148,6 +200,7 @@ impl Service {
                 if rx.try_recv().is_ok() {
                     // Cancel slot tick service
                     ts.send(0).unwrap();
+                    events.stop();
                     // if !network.exit_signal.is_closed() {
                     //     network.exit_signal.send(1).expect("network exit error");
                     // }
@@ -196,6 +249,27 @@ impl Service {
         // });
         (tx, builder)
     }
+    // Keep pool in line with canonical chain, whichever way its blocks are imported
+    fn start_pool_listener(&self, blocks: Receiver<Block>, reorgs: Receiver<ChainReorg>) {
+        let tx_pool = self.tx_pool.clone();
+        thread::spawn(move || loop {
+            select! {
+                recv(blocks) -> msg => match msg {
+                    Ok(block) => tx_pool.write()
+                        .expect("acquiring tx_pool write lock")
+                        .notify_block(&block),
+                    Err(_) => break,
+                },
+                recv(reorgs) -> msg => match msg {
+                    Ok(reorg) => tx_pool.write()
+                        .expect("acquiring tx_pool write lock")
+                        .notify_reorg(&reorg.retracted, &reorg.enacted),
+                    Err(_) => break,
+                },
+            }
+        });
+    }
+
     pub fn new_empty_block() -> Block {
         Block::default()
     }
@@ -217,10 +291,12 @@ impl Service {
         info!("seal block, height={}, parent={}, tx={}", header.height, header.parent_hash, txs.len());
         let b = Block::new(header,txs,Vec::new(),Vec::new());
         let finalize = self.get_POA();
-        let chain = self.block_chain.read().unwrap();
-        let statedb = chain.state_at(cur_block.state_root());
-
-        let h = Executor::exc_txs_in_block(&b, &mut Balance::new(Interpreter::new(statedb)), &POA::get_default_miner())?;
+        let h = {
+            let chain = self.block_chain.read().unwrap();
+            let mut runner = chain.interpreter_at(cur_block.state_root());
+            Executor::exc_txs_in_block(&b, &mut runner, &POA::get_default_miner())?.0
+        };
+        // Pool revalidates against chain head, the chain guard must be released first
         tx_pool.write().expect("acquiring tx_pool write lock").notify_block(&b);
         finalize.finalize_block(b,h)
     }
@@ -249,6 +325,9 @@ mod tests {
     use std::fmt;
 
     use super::*;
+    use chain::test_helpers::make_block;
+    use core::test_helpers::{genesis_key, transfer_tx};
+    use core::types::Address;
 
     #[test]
     fn test_service() {
@@ -263,4 +342,52 @@ mod tests {
         th_handle.join();
         println!("end service");
     }
+
+    #[test]
+    fn test_pool_follows_imported_blocks() {
+        let mut config = NodeConfig::default();
+        config.db = "memory".into();
+        let service = Service::new_service(config);
+        service.get_write_blockchain().load().unwrap();
+        let events = EventService::new().start(None::<String>);
+        service.get_write_blockchain().set_event_handler(events.clone());
+        service.start_pool_listener(events.subscribe_new_block("tx_pool"), events.subscribe_chain_reorg("tx_pool"));
+
+        let tx = transfer_tx(&genesis_key(), 1, Address([1; 20]), 10);
+        service.tx_pool.write().unwrap().submit_txs(tx.clone()).unwrap();
+
+        // Block of the transaction is built elsewhere and imported as from network
+        let block = {
+            let chain = service.get_readblockchain();
+            make_block(&chain, &chain.current_block(), vec![tx.clone()], &genesis_key(), 1)
+        };
+        service.get_write_blockchain().insert_block_ref(&block).unwrap();
+
+        for _ in 0..50 {
+            if !service.tx_pool.read().unwrap().contains(&tx.hash()) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(!service.tx_pool.read().unwrap().contains(&tx.hash()));
+        assert_eq!(service.tx_pool.read().unwrap().get_nonce(&tx.sender), 1);
+    }
+
+    #[test]
+    fn test_config_options() {
+        let mut config = NodeConfig::default();
+        assert!(config.validate().is_ok());
+        config.pruning = "fast".into();
+        assert_eq!(config.pruning_mode(), Ok(PruningMode::Recent(256)));
+        config.pruning_history = 0;
+        assert!(config.validate().is_err());
+        config.pruning = "archve".into();
+        assert!(config.pruning_mode().is_err());
+
+        let mut config = NodeConfig::default();
+        config.db = "rocks".into();
+        assert!(config.validate().is_err());
+        config.db = "memory".into();
+        assert_eq!(config.db_kind(), Ok(DBKind::Memory));
+    }
 }